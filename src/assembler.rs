@@ -0,0 +1,936 @@
+use crate::bus::Bus;
+use crate::cpu_6502::{AddrMode, Cpu6502};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+// Two pass assembler for the instruction set in the Cpu6502 lookup table,
+// so programs can be written as source instead of hex dumps. It understands
+//
+//     ; comments
+//     label:          labels, the colon is optional in the first column
+//     @loop:          local labels, scoped to the last global label
+//     name = expr     constants
+//     *= $8000        origin, .org works too
+//     .byte 1, "hi"   .db as well, strings give one byte per character
+//     .word label     .dw as well
+//     .res 16, $FF    reserve bytes, filled with zero unless told otherwise
+//     .include "file" relative to the file doing the including
+//
+// Expressions use $hex, %binary, decimal and 'c' numbers, * for the current
+// address, <expr and >expr for the low and high byte, and + - * / & | ^ ~
+// << >> with parentheses. An operand can be forced to absolute with a:
+// the way ca65 does it. BRK on its own is BRK #0.
+//
+// The size of every instruction is fixed in the first pass: an operand
+// that is known and fits in a byte gets the zero page form, anything
+// defined further down gets the absolute form, as does a hex number
+// written with four digits. The second pass then only fills in values, so
+// labels cannot move between passes.
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Contiguous run of assembled bytes
+#[derive(Clone, PartialEq, Debug)]
+pub struct Segment {
+    pub origin: u16,
+    pub data: Vec<u8>,
+}
+
+// One source line and the bytes it produced
+#[derive(Clone, PartialEq, Debug)]
+pub struct ListingLine {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+}
+
+pub struct Assembly {
+    pub segments: Vec<Segment>,
+    pub symbols: BTreeMap<String, u16>,
+    pub lines: Vec<ListingLine>,
+}
+
+impl Assembly {
+    // Puts every assembled byte at its address on the bus
+    pub fn load(&self, bus: &mut Bus) {
+        for segment in &self.segments {
+            for (i, b) in segment.data.iter().enumerate() {
+                bus.poke(segment.origin.wrapping_add(i as u16), *b);
+            }
+        }
+    }
+
+    // Source line that produced the byte at addr
+    pub fn line_at(&self, addr: u16) -> Option<&ListingLine> {
+        self.lines.iter().find(|l| {
+            let offset = addr.wrapping_sub(l.addr) as usize;
+            offset < l.bytes.len()
+        })
+    }
+
+    // Address, bytes, file:line and source text for every line, followed by
+    // the symbol table
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            let mut chunks = line.bytes.chunks(4);
+            let first = chunks.next().unwrap_or(&[]);
+            let place = format!("{}:{}", line.file, line.line);
+            let _ = writeln!(
+                out,
+                "${}  {:<12} {:>16}  {}",
+                Cpu6502::hex(line.addr as u32, 4),
+                hex_bytes(first),
+                place,
+                line.text
+            );
+            // Long data lines carry on underneath
+            let mut addr = line.addr.wrapping_add(first.len() as u16);
+            for chunk in chunks {
+                let _ = writeln!(
+                    out,
+                    "${}  {}",
+                    Cpu6502::hex(addr as u32, 4),
+                    hex_bytes(chunk)
+                );
+                addr = addr.wrapping_add(chunk.len() as u16);
+            }
+        }
+
+        let _ = writeln!(out);
+        for (name, value) in &self.symbols {
+            let _ = writeln!(out, "{:<24} ${}", name, Cpu6502::hex(*value as u32, 4));
+        }
+        out
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let list: Vec<String> = bytes.iter().map(|b| Cpu6502::hex(*b as u32, 2)).collect();
+    list.join(" ")
+}
+
+struct SourceLine {
+    file: String,
+    line: usize,
+    text: String,
+}
+
+pub struct Assembler {
+    // Only used for its instruction lookup table
+    cpu: Cpu6502,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new()
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self {
+            cpu: Cpu6502::new(),
+        }
+    }
+
+    pub fn assemble(&self, source: &str) -> Result<Assembly, String> {
+        let mut lines: Vec<SourceLine> = Vec::new();
+        self.read_lines("<source>", source, Path::new("."), 0, &mut lines)?;
+        self.assemble_lines(&lines)
+    }
+
+    pub fn assemble_file(&self, path: &Path) -> Result<Assembly, String> {
+        let mut lines: Vec<SourceLine> = Vec::new();
+        self.read_file(path, 0, &mut lines)?;
+        self.assemble_lines(&lines)
+    }
+
+    // Assembles one line as if it were at addr, for the monitor's a command.
+    // Symbols from elsewhere, such as the debugger's, can be used in it.
+    pub fn assemble_at(
+        &self,
+        addr: u16,
+        text: &str,
+        symbols: &HashMap<String, u16>,
+    ) -> Result<Vec<u8>, String> {
+        let mut pass = Pass {
+            second: false,
+            pc: addr as u32,
+            scope: String::new(),
+            symbols: symbols
+                .iter()
+                .map(|(k, v)| (k.clone(), *v as i64))
+                .collect(),
+            modes: vec![None],
+            segments: Vec::new(),
+            listing: Vec::new(),
+        };
+        self.statement(&mut pass, 0, text)?;
+        pass.second = true;
+        pass.pc = addr as u32;
+        pass.scope.clear();
+        let bytes = self.statement(&mut pass, 0, text)?;
+        if addr as usize + bytes.len() > 0x10000 {
+            return Err("code runs past $FFFF".to_string());
+        }
+        Ok(bytes)
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // SOURCE FILES
+
+    fn read_file(
+        &self,
+        path: &Path,
+        depth: usize,
+        lines: &mut Vec<SourceLine>,
+    ) -> Result<(), String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        self.read_lines(&path.display().to_string(), &text, dir, depth, lines)
+    }
+
+    // Splits a file into lines, pulling in included files as it goes
+    fn read_lines(
+        &self,
+        name: &str,
+        text: &str,
+        dir: &Path,
+        depth: usize,
+        lines: &mut Vec<SourceLine>,
+    ) -> Result<(), String> {
+        for (i, text) in text.lines().enumerate() {
+            let code = strip_comment(text).trim();
+            let (word, rest) = split_word(code);
+            if word.eq_ignore_ascii_case(".include") {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(format!("{}:{}: includes nested too deep", name, i + 1));
+                }
+                let file = rest.trim().trim_matches('"');
+                let path: PathBuf = dir.join(file);
+                self.read_file(&path, depth + 1, lines)
+                    .map_err(|e| format!("{}:{}: {}", name, i + 1, e))?;
+            } else {
+                lines.push(SourceLine {
+                    file: name.to_string(),
+                    line: i + 1,
+                    text: text.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // PASSES
+
+    fn assemble_lines(&self, lines: &[SourceLine]) -> Result<Assembly, String> {
+        let mut pass = Pass {
+            second: false,
+            pc: 0,
+            scope: String::new(),
+            symbols: HashMap::new(),
+            modes: vec![None; lines.len()],
+            segments: Vec::new(),
+            listing: Vec::new(),
+        };
+
+        for second in [false, true] {
+            pass.second = second;
+            pass.pc = 0;
+            pass.scope.clear();
+            for (index, line) in lines.iter().enumerate() {
+                self.assemble_line(&mut pass, index, line)
+                    .map_err(|e| format!("{}:{}: {}", line.file, line.line, e))?;
+            }
+        }
+
+        Ok(Assembly {
+            segments: pass.segments,
+            symbols: pass
+                .symbols
+                .into_iter()
+                .map(|(k, v)| (k, v as u16))
+                .collect(),
+            lines: pass.listing,
+        })
+    }
+
+    fn assemble_line(
+        &self,
+        pass: &mut Pass,
+        index: usize,
+        line: &SourceLine,
+    ) -> Result<(), String> {
+        let start = pass.pc;
+        let bytes = self.statement(pass, index, &line.text)?;
+
+        if pass.second {
+            // Lines without bytes are listed at the address they leave behind,
+            // so an origin shows where it moved to
+            let addr = if bytes.is_empty() { pass.pc } else { start } as u16;
+            let contiguous = pass
+                .segments
+                .last()
+                .is_some_and(|s| s.origin as u32 + s.data.len() as u32 == start);
+            if !bytes.is_empty() {
+                if !contiguous {
+                    pass.segments.push(Segment {
+                        origin: addr,
+                        data: Vec::new(),
+                    });
+                }
+                if let Some(segment) = pass.segments.last_mut() {
+                    segment.data.extend_from_slice(&bytes);
+                }
+            }
+            pass.listing.push(ListingLine {
+                addr,
+                bytes: bytes.clone(),
+                file: line.file.clone(),
+                line: line.line,
+                text: line.text.clone(),
+            });
+        }
+
+        pass.pc += bytes.len() as u32;
+        if pass.pc > 0x10000 {
+            return Err("code runs past $FFFF".to_string());
+        }
+        Ok(())
+    }
+
+    // Handles the label, constant, directive or instruction on one line and
+    // returns the bytes it assembles to (empty bytes in the first pass are
+    // still the right length)
+    fn statement(&self, pass: &mut Pass, index: usize, text: &str) -> Result<Vec<u8>, String> {
+        let code = strip_comment(text);
+        let mut rest = code.trim();
+        if rest.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // *= origin
+        if let Some(value) = rest.strip_prefix('*') {
+            if let Some(value) = value.trim_start().strip_prefix('=') {
+                pass.pc = pass.known(value, "origin")? as u32;
+                return Ok(Vec::new());
+            }
+        }
+
+        // name = value
+        let (word, after) = split_ident(rest);
+        if !word.is_empty() {
+            if let Some(value) = after.trim_start().strip_prefix('=') {
+                let name = pass.qualify(word);
+                if let Some(value) = pass.evaluate(value)? {
+                    pass.define(&name, value, false)?;
+                }
+                return Ok(Vec::new());
+            }
+        }
+
+        // Labels, with a colon anywhere or without one in the first column
+        let first_column = !code.starts_with(char::is_whitespace);
+        if !word.is_empty() && after.starts_with(':') {
+            pass.label(word)?;
+            rest = after[1..].trim();
+        } else if !word.is_empty() && first_column && !self.is_mnemonic(word) {
+            pass.label(word)?;
+            rest = after.trim();
+        }
+        if rest.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (word, operand) = split_word(rest);
+        if word.starts_with('.') {
+            return self.directive(pass, &word.to_ascii_lowercase(), operand.trim());
+        }
+        self.instruction(pass, index, &word.to_ascii_uppercase(), operand)
+    }
+
+    fn directive(&self, pass: &mut Pass, name: &str, args: &str) -> Result<Vec<u8>, String> {
+        let mut bytes: Vec<u8> = Vec::new();
+        match name {
+            ".org" => pass.pc = pass.known(args, "origin")? as u32,
+            ".byte" | ".db" => {
+                for arg in split_args(args) {
+                    if let Some(text) = arg.strip_prefix('"') {
+                        let text = text.strip_suffix('"').ok_or("unterminated string")?;
+                        bytes.extend(text.bytes());
+                    } else {
+                        let value = pass.evaluate(&arg)?.unwrap_or(0);
+                        bytes.push(to_byte(value)?);
+                    }
+                }
+            }
+            ".word" | ".dw" => {
+                for arg in split_args(args) {
+                    let value = pass.evaluate(&arg)?.unwrap_or(0);
+                    let value = to_word(value)?;
+                    bytes.push(value as u8);
+                    bytes.push((value >> 8) as u8);
+                }
+            }
+            ".res" | ".ds" => {
+                let args = split_args(args);
+                let count = pass.known(args.first().ok_or(".res needs a size")?, "size")?;
+                let fill = match args.get(1) {
+                    Some(fill) => to_byte(pass.evaluate(fill)?.unwrap_or(0))?,
+                    None => 0,
+                };
+                bytes = vec![fill; count as usize];
+            }
+            _ => return Err(format!("unknown directive {}", name)),
+        }
+        Ok(bytes)
+    }
+
+    fn instruction(
+        &self,
+        pass: &mut Pass,
+        index: usize,
+        name: &str,
+        operand: &str,
+    ) -> Result<Vec<u8>, String> {
+        if !self.is_mnemonic(name) {
+            return Err(format!("unknown instruction {}", name));
+        }
+        let find = |mode: AddrMode| self.cpu.find_opcode(name, mode);
+
+        let operand = compact(operand);
+        let upper = operand.to_ascii_uppercase();
+        let len = operand.len();
+
+        // Possible modes, the zero page one first, and the expression
+        let (modes, expr): ([AddrMode; 2], &str) = if operand.is_empty()
+            && find(AddrMode::Imp).is_none()
+            && find(AddrMode::Imm).is_some()
+        {
+            // BRK, which the cpu reads as two bytes: a bare one gets a zero
+            // for its second
+            ([AddrMode::Imm, AddrMode::Imm], "")
+        } else if operand.is_empty() || upper == "A" {
+            ([AddrMode::Imp, AddrMode::Imp], "")
+        } else if let Some(value) = operand.strip_prefix('#') {
+            ([AddrMode::Imm, AddrMode::Imm], value)
+        } else if upper.starts_with('(') && upper.ends_with(",X)") {
+            ([AddrMode::Izx, AddrMode::Izx], &operand[1..len - 3])
+        } else if upper.starts_with('(') && upper.ends_with("),Y") {
+            ([AddrMode::Izy, AddrMode::Izy], &operand[1..len - 3])
+        } else if upper.starts_with('(') && upper.ends_with(')') && find(AddrMode::Ind).is_some() {
+            ([AddrMode::Ind, AddrMode::Ind], &operand[1..len - 1])
+        } else if upper.ends_with(",X") {
+            ([AddrMode::Zpx, AddrMode::Abx], &operand[..len - 2])
+        } else if upper.ends_with(",Y") {
+            ([AddrMode::Zpy, AddrMode::Aby], &operand[..len - 2])
+        } else if find(AddrMode::Rel).is_some() {
+            ([AddrMode::Rel, AddrMode::Rel], operand.as_str())
+        } else {
+            ([AddrMode::Zp0, AddrMode::Abs], operand.as_str())
+        };
+
+        // a: forces the absolute form
+        let (modes, expr) = match expr.strip_prefix("a:").or_else(|| expr.strip_prefix("A:")) {
+            Some(expr) => ([modes[1], modes[1]], expr),
+            None => (modes, expr),
+        };
+
+        let value = if expr.is_empty() {
+            Some(0)
+        } else {
+            pass.evaluate(expr)?
+        };
+
+        // The choice of mode is made once, in the first pass
+        let mode = match pass.modes[index] {
+            Some(mode) => mode,
+            None => {
+                let fits_zero_page =
+                    matches!(value, Some(v) if (0..=0xFF).contains(&v)) && !is_wide_literal(expr);
+                let mode = match (find(modes[0]), find(modes[1])) {
+                    (Some(_), _) if fits_zero_page || modes[0] == modes[1] => modes[0],
+                    (_, Some(_)) => modes[1],
+                    (Some(_), None) => modes[0],
+                    (None, None) => return Err(format!("{} has no {} form", name, modes[1].tag())),
+                };
+                pass.modes[index] = Some(mode);
+                mode
+            }
+        };
+        let opcode = find(mode).ok_or(format!("{} has no {} form", name, mode.tag()))?;
+
+        let value = value.unwrap_or(0);
+        let bytes = match mode {
+            AddrMode::Imp => vec![opcode],
+            AddrMode::Rel => {
+                let offset = value - (pass.pc as i64 + 2);
+                if pass.second && !(-128..=127).contains(&offset) {
+                    return Err(format!(
+                        "branch to ${} is out of range",
+                        Cpu6502::hex(value as u32, 4)
+                    ));
+                }
+                vec![opcode, offset as u8]
+            }
+            _ if mode.operand_size() == 1 => vec![opcode, to_byte(value)?],
+            _ => {
+                let value = to_word(value)?;
+                vec![opcode, value as u8, (value >> 8) as u8]
+            }
+        };
+        Ok(bytes)
+    }
+
+    fn is_mnemonic(&self, name: &str) -> bool {
+        let name = name.to_ascii_uppercase();
+        name != "???"
+            && AddrMode::ALL
+                .iter()
+                .any(|m| self.cpu.find_opcode(&name, *m).is_some())
+    }
+}
+
+// State carried through a pass
+struct Pass {
+    second: bool,
+    pc: u32,
+    // Last global label, local labels are stored as scope@name
+    scope: String,
+    symbols: HashMap<String, i64>,
+    // Addressing mode picked for each line in the first pass
+    modes: Vec<Option<AddrMode>>,
+    segments: Vec<Segment>,
+    listing: Vec<ListingLine>,
+}
+
+impl Pass {
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('@') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn label(&mut self, name: &str) -> Result<(), String> {
+        let full = self.qualify(name);
+        if !name.starts_with('@') {
+            self.scope = name.to_string();
+        }
+        self.define(&full, self.pc as i64, true)
+    }
+
+    fn define(&mut self, name: &str, value: i64, label: bool) -> Result<(), String> {
+        match self.symbols.get(name) {
+            // Labels come round again in the second pass at the same address
+            Some(old) if self.second && *old == value => Ok(()),
+            Some(_) if label || !self.second => Err(format!("{} is already defined", name)),
+            _ => {
+                self.symbols.insert(name.to_string(), value);
+                Ok(())
+            }
+        }
+    }
+
+    // Value of an expression, None in the first pass when it uses a symbol
+    // that has not been defined yet
+    fn evaluate(&self, text: &str) -> Result<Option<i64>, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = ExprParser {
+            tokens,
+            pos: 0,
+            pass: self,
+            undefined: None,
+        };
+        let value = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(format!(
+                "unexpected {:?} in '{}'",
+                parser.tokens[parser.pos],
+                text.trim()
+            ));
+        }
+        match parser.undefined {
+            Some(name) if self.second => Err(format!("undefined symbol {}", name)),
+            Some(_) => Ok(None),
+            None => Ok(Some(value)),
+        }
+    }
+
+    // Things like the origin have to be known straight away in the first pass
+    fn known(&self, text: &str, what: &str) -> Result<u16, String> {
+        match self.evaluate(text)? {
+            Some(value) => to_word(value),
+            None => Err(format!("{} must not use symbols defined later", what)),
+        }
+    }
+}
+
+// A hex number written with more than two digits, like $0010, asks for the
+// absolute form even though its value would fit in the zero page
+fn is_wide_literal(expr: &str) -> bool {
+    match expr.strip_prefix('$') {
+        Some(digits) => digits.len() > 2 && digits.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+fn to_byte(value: i64) -> Result<u8, String> {
+    if (-128..=0xFF).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("{} does not fit in a byte", value))
+    }
+}
+
+fn to_word(value: i64) -> Result<u16, String> {
+    if (-32768..=0xFFFF).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("{} does not fit in a word", value))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TEXT HELPERS
+
+// Cuts a ; comment off, ignoring semicolons inside quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..i],
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => (),
+        }
+    }
+    text
+}
+
+// Removes whitespace outside quotes
+fn compact(text: &str) -> String {
+    let mut out = String::new();
+    let mut quote: Option<char> = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, _) if c.is_whitespace() => continue,
+            _ => (),
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Splits on commas outside quotes
+fn split_args(text: &str) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (None, ',') => {
+                args.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => (),
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !args.is_empty() {
+        args.push(current.trim().to_string());
+    }
+    args
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], &text[i..]),
+        None => (text, ""),
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@'
+}
+
+// Leading identifier and whatever follows it
+fn split_ident(text: &str) -> (&str, &str) {
+    let starts = text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '@');
+    if !starts {
+        return ("", text);
+    }
+    let end = text.find(|c: char| !is_ident_char(c)).unwrap_or(text.len());
+    (&text[..end], &text[end..])
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// EXPRESSIONS
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+// Longest operators first so "<<" is not read as two "<"
+const OPERATORS: [&str; 14] = [
+    "<<", ">>", "+", "-", "*", "/", "&", "|", "^", "~", "<", ">", "(", ")",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c == '\'' {
+            if i + 2 < chars.len() && chars[i + 2] == '\'' {
+                tokens.push(Token::Number(chars[i + 1] as i64));
+                i += 3;
+                continue;
+            }
+            return Err(format!("bad character literal in '{}'", text.trim()));
+        }
+
+        let radix = match c {
+            '$' => 16,
+            '%' => 2,
+            _ if c.is_ascii_digit() => 10,
+            _ => 0,
+        };
+        if radix != 0 {
+            let start = if radix == 10 { i } else { i + 1 };
+            let mut end = start;
+            while end < chars.len() && chars[end].is_digit(radix) {
+                end += 1;
+            }
+            let digits: String = chars[start..end].iter().collect();
+            let n = i64::from_str_radix(&digits, radix)
+                .map_err(|_| format!("bad number in '{}'", text.trim()))?;
+            tokens.push(Token::Number(n));
+            i = end;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '@' {
+            let start = i;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => return Err(format!("unexpected '{}' in '{}'", c, text.trim())),
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct ExprParser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    pass: &'a Pass,
+    // First symbol that had no value yet
+    undefined: Option<String>,
+}
+
+impl ExprParser<'_> {
+    fn accept(&mut self, op: &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&str],
+        next: fn(&mut Self) -> Result<i64, String>,
+    ) -> Result<i64, String> {
+        let mut lhs = next(self)?;
+        'outer: loop {
+            for op in ops {
+                if self.accept(op) {
+                    let rhs = next(self)?;
+                    lhs = match *op {
+                        "|" => lhs | rhs,
+                        "^" => lhs ^ rhs,
+                        "&" => lhs & rhs,
+                        "<<" => lhs << (rhs & 0x3F),
+                        ">>" => lhs >> (rhs & 0x3F),
+                        "+" => lhs.wrapping_add(rhs),
+                        "-" => lhs.wrapping_sub(rhs),
+                        "*" => lhs.wrapping_mul(rhs),
+                        _ if rhs != 0 => lhs.wrapping_div(rhs),
+                        // Stand in values for undefined symbols may well be zero
+                        _ if self.undefined.is_some() => 0,
+                        _ => return Err("division by zero".to_string()),
+                    };
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<i64, String> {
+        self.binary(&["|"], Self::parse_xor)
+    }
+
+    fn parse_xor(&mut self) -> Result<i64, String> {
+        self.binary(&["^"], Self::parse_and)
+    }
+
+    fn parse_and(&mut self) -> Result<i64, String> {
+        self.binary(&["&"], Self::parse_shift)
+    }
+
+    fn parse_shift(&mut self) -> Result<i64, String> {
+        self.binary(&["<<", ">>"], Self::parse_sum)
+    }
+
+    fn parse_sum(&mut self) -> Result<i64, String> {
+        self.binary(&["+", "-"], Self::parse_product)
+    }
+
+    fn parse_product(&mut self) -> Result<i64, String> {
+        self.binary(&["*", "/"], Self::parse_unary)
+    }
+
+    fn parse_unary(&mut self) -> Result<i64, String> {
+        if self.accept("-") {
+            Ok(-self.parse_unary()?)
+        } else if self.accept("~") {
+            Ok(!self.parse_unary()?)
+        } else if self.accept("<") {
+            Ok(self.parse_unary()? & 0xFF)
+        } else if self.accept(">") {
+            Ok((self.parse_unary()? >> 8) & 0xFF)
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<i64, String> {
+        if self.accept("(") {
+            let value = self.parse_or()?;
+            return if self.accept(")") {
+                Ok(value)
+            } else {
+                Err("missing ')'".to_string())
+            };
+        }
+        // * on its own is the address of the current line
+        if self.accept("*") {
+            return Ok(self.pass.pc as i64);
+        }
+
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Ident(name)) => {
+                let name = self.pass.qualify(&name);
+                match self.pass.symbols.get(&name) {
+                    Some(value) => Ok(*value),
+                    None => {
+                        self.undefined.get_or_insert(name);
+                        Ok(0)
+                    }
+                }
+            }
+            Some(t) => Err(format!("unexpected {:?}", t)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_a_program() {
+        let source = "
+            *=$8000
+        start:
+            LDA #$01
+            STA $10         ; zero page
+            STA $0010       ; four digits, absolute
+            LDX table,Y     ; not known yet, absolute
+        @loop:
+            DEX
+            BNE @loop
+            JMP (vector)
+            BRK
+            BRK #$12
+        table:
+            .byte 1, \"hi\"
+        vector:
+            .word start
+        ";
+        let assembly = Assembler::new().assemble(source).unwrap();
+        assert_eq!(assembly.segments.len(), 1);
+        assert_eq!(assembly.segments[0].origin, 0x8000);
+        let expected = [
+            0xA9, 0x01, // LDA #$01
+            0x85, 0x10, // STA $10
+            0x8D, 0x10, 0x00, // STA $0010
+            0xBE, 0x14, 0x80, // LDX table,Y
+            0xCA, // DEX
+            0xD0, 0xFD, // BNE @loop
+            0x6C, 0x17, 0x80, // JMP (vector)
+            0x00, 0x00, // BRK
+            0x00, 0x12, // BRK #$12
+            0x01, 0x68, 0x69, // table
+            0x00, 0x80, // vector
+        ];
+        assert_eq!(assembly.segments[0].data, expected);
+        assert_eq!(assembly.symbols["table"], 0x8014);
+        assert_eq!(assembly.symbols["vector"], 0x8017);
+    }
+
+    #[test]
+    fn assembles_single_instructions() {
+        let assembler = Assembler::new();
+        let symbols = HashMap::from([("target".to_string(), 0x8010)]);
+        let at = |text: &str| assembler.assemble_at(0x8000, text, &symbols);
+        assert_eq!(at("BRK").unwrap(), [0x00, 0x00]);
+        assert_eq!(at("brk").unwrap(), [0x00, 0x00]);
+        assert_eq!(at("ASL A").unwrap(), [0x0A]);
+        assert_eq!(at("LDA a:$10").unwrap(), [0xAD, 0x10, 0x00]);
+        assert_eq!(at("BEQ target").unwrap(), [0xF0, 0x0E]);
+        assert_eq!(at("LDA ($20),Y").unwrap(), [0xB1, 0x20]);
+        assert!(at("BEQ $9000").is_err());
+        assert!(at("LDA #$100").is_err());
+        assert!(at("FOO #1").is_err());
+        assert!(at("STA #1").is_err());
+    }
+}
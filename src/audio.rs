@@ -0,0 +1,503 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Sound leaves the machine one level per CPU cycle, far faster than any
+// sound card plays. AudioOutput turns each change in level into a band
+// limited step at the output rate, runs the result through the filters the
+// NES itself has between the APU and the RCA jack, and hands the samples to
+// an AudioSink: a WAV file, a ring buffer a frontend pulls from, or nowhere.
+
+// NTSC CPU clock, which the APU runs at
+pub const CPU_RATE: f64 = 1_789_773.0;
+pub const DEFAULT_RATE: u32 = 44_100;
+
+// Where samples go. They are mono, between -1 and 1, at whatever rate the
+// sink was set up for.
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]);
+
+    // Called once the last samples are written. Sinks that can fail say so
+    // here, with the first error they hit.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn write(&mut self, _samples: &[f32]) {}
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// RESAMPLING
+
+// One pole filters, as on nesdev's APU mixer page: two high passes at 90
+// and 440 Hz, and a low pass at 14 kHz
+struct OnePole {
+    high_pass: bool,
+    alpha: f32,
+    last_in: f32,
+    last_out: f32,
+}
+
+impl OnePole {
+    fn new(high_pass: bool, cutoff: f64, rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * std::f64::consts::PI * cutoff);
+        let dt = 1.0 / rate;
+        let alpha = if high_pass {
+            rc / (rc + dt)
+        } else {
+            dt / (rc + dt)
+        };
+        Self {
+            high_pass,
+            alpha: alpha as f32,
+            last_in: 0.0,
+            last_out: 0.0,
+        }
+    }
+
+    fn apply(&mut self, x: f32) -> f32 {
+        let y = if self.high_pass {
+            self.alpha * (self.last_out + x - self.last_in)
+        } else {
+            self.last_out + self.alpha * (x - self.last_out)
+        };
+        self.last_in = x;
+        self.last_out = y;
+        y
+    }
+}
+
+// Output samples a step is spread over, and how finely where it falls
+// between two samples is told apart
+const STEP_WIDTH: usize = 32;
+const STEP_PHASES: usize = 64;
+// Where the step's band ends, as a share of the output rate. Past half the
+// rate the Blackman window has it down more than 70 dB.
+const STEP_CUTOFF: f64 = 0.42;
+
+// Band limited step synthesis, like blip_buf: the APU's output only ever
+// jumps from one level to another, so each jump adds a step that has had
+// everything the output rate cannot hold taken out. The steps are kept as
+// their differences, a windowed sinc for every fraction of a sample the
+// jump can land on, and the output is the running sum of those. The high
+// passes and the 14 kHz low pass then run at the output rate.
+pub struct Resampler {
+    // Output samples one input sample lasts
+    step: f64,
+    // How far into the current output sample we are, 0 to 1
+    time: f64,
+    level: f32,
+    // Differences still to come, starting with the current output sample
+    deltas: VecDeque<f32>,
+    sum: f32,
+    // STEP_PHASES + 1 rows of STEP_WIDTH taps, each summing to 1
+    kernel: Vec<f32>,
+    filters: [OnePole; 3],
+}
+
+impl Resampler {
+    pub fn new(input_rate: f64, output_rate: u32) -> Self {
+        let rate = output_rate as f64;
+        Self {
+            step: rate / input_rate,
+            time: 0.0,
+            level: 0.0,
+            deltas: std::iter::repeat_n(0.0, STEP_WIDTH).collect(),
+            sum: 0.0,
+            kernel: step_kernel(),
+            filters: [
+                OnePole::new(false, 14_000.0, rate),
+                OnePole::new(true, 90.0, rate),
+                OnePole::new(true, 440.0, rate),
+            ],
+        }
+    }
+
+    // Takes one input sample, returns an output sample whenever one is
+    // complete
+    pub fn clock(&mut self, input: f32) -> Option<f32> {
+        if input != self.level {
+            self.add_step(input - self.level);
+            self.level = input;
+        }
+        self.time += self.step;
+        if self.time < 1.0 {
+            return None;
+        }
+
+        self.time -= 1.0;
+        self.sum += self.deltas.pop_front().unwrap_or(0.0);
+        self.deltas.push_back(0.0);
+        Some(
+            self.filters
+                .iter_mut()
+                .fold(self.sum, |sample, filter| filter.apply(sample)),
+        )
+    }
+
+    // Spreads a jump at the current time over the samples to come, blending
+    // the two nearest rows of the kernel
+    fn add_step(&mut self, delta: f32) {
+        let phase = self.time * STEP_PHASES as f64;
+        let row = (phase as usize).min(STEP_PHASES - 1);
+        let blend = (phase - row as f64) as f32;
+        let (before, after) = self.kernel[row * STEP_WIDTH..].split_at(STEP_WIDTH);
+        for (i, slot) in self.deltas.iter_mut().enumerate() {
+            let tap = before[i] + (after[i] - before[i]) * blend;
+            *slot += delta * tap;
+        }
+    }
+}
+
+// Row p holds the step's differences when it lands p / STEP_PHASES of the
+// way into a sample, centred STEP_WIDTH / 2 samples later
+fn step_kernel() -> Vec<f32> {
+    use std::f64::consts::PI;
+    let half = (STEP_WIDTH / 2) as f64;
+    let mut kernel = Vec::with_capacity((STEP_PHASES + 1) * STEP_WIDTH);
+    for phase in 0..=STEP_PHASES {
+        let offset = phase as f64 / STEP_PHASES as f64;
+        let taps: Vec<f64> = (0..STEP_WIDTH)
+            .map(|i| {
+                // The difference between two samples is the impulse half
+                // way between them
+                let x = i as f64 - 0.5 - offset - half + 1.0;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (2.0 * PI * STEP_CUTOFF * x).sin() / (2.0 * PI * STEP_CUTOFF * x)
+                };
+                let window = if x.abs() >= half {
+                    0.0
+                } else {
+                    0.42 + 0.5 * (PI * x / half).cos() + 0.08 * (2.0 * PI * x / half).cos()
+                };
+                sinc * window
+            })
+            .collect();
+        let total: f64 = taps.iter().sum();
+        kernel.extend(taps.iter().map(|tap| (tap / total) as f32));
+    }
+    kernel
+}
+
+// What the bus holds: the resampler, and the sink its samples go to
+pub struct AudioOutput {
+    resampler: Resampler,
+    sink: Box<dyn AudioSink>,
+    buffer: Vec<f32>,
+}
+
+impl AudioOutput {
+    pub fn new(output_rate: u32, sink: Box<dyn AudioSink>) -> Self {
+        Self {
+            resampler: Resampler::new(CPU_RATE, output_rate),
+            sink,
+            buffer: Vec::with_capacity(BUFFER_SAMPLES),
+        }
+    }
+
+    // Called every CPU cycle with the level the APU puts out, 0 to 1
+    pub fn clock(&mut self, level: f32) {
+        if let Some(sample) = self.resampler.clock(level) {
+            self.buffer.push(sample);
+            if self.buffer.len() == BUFFER_SAMPLES {
+                self.flush();
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.sink.write(&self.buffer);
+        self.buffer.clear();
+    }
+
+    // Writes out what is left and lets the sink finish, reporting anything
+    // that went wrong on the way. Dropping the output does the same but
+    // has nowhere to put the error.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.flush();
+        self.sink.finish()
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// Samples are handed over in blocks this big
+const BUFFER_SAMPLES: usize = 512;
+
+///////////////////////////////////////////////////////////////////////////////
+// SINKS
+
+// 16 bit mono PCM. The sizes in the header are filled in by finish(), or
+// when the writer is dropped. Write errors are kept for finish() to return.
+pub struct WavWriter {
+    out: BufWriter<File>,
+    samples: u32,
+    error: Option<io::Error>,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, rate: u32) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM, one channel, rate, bytes a second, block align, bits
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&rate.to_le_bytes())?;
+        out.write_all(&(rate * 2).to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(Self {
+            out,
+            samples: 0,
+            error: None,
+        })
+    }
+
+    // The first error writing hit, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl AudioSink for WavWriter {
+    fn write(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
+            if let Err(e) = self.out.write_all(&value.to_le_bytes()) {
+                self.error = Some(e);
+                return;
+            }
+        }
+        self.samples += samples.len() as u32;
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let data = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+// Holds the newest samples for a frontend to pull at its own pace. When
+// the frontend falls behind the oldest samples are dropped, when it gets
+// ahead it is given silence. Shared across threads as a SharedRing.
+pub struct RingBuffer {
+    samples: VecDeque<f32>,
+    capacity: usize,
+}
+
+pub type SharedRing = Arc<Mutex<RingBuffer>>;
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn shared(capacity: usize) -> SharedRing {
+        Arc::new(Mutex::new(RingBuffer::new(capacity)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // Fills out, returning how many of its samples were real rather than
+    // silence
+    pub fn pull(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.samples.len());
+        for (slot, sample) in out.iter_mut().zip(self.samples.drain(..count)) {
+            *slot = sample;
+        }
+        out[count..].fill(0.0);
+        count
+    }
+}
+
+impl AudioSink for RingBuffer {
+    fn write(&mut self, samples: &[f32]) {
+        for sample in samples {
+            if self.samples.len() == self.capacity {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(*sample);
+        }
+    }
+}
+
+impl AudioSink for SharedRing {
+    fn write(&mut self, samples: &[f32]) {
+        if let Ok(mut ring) = self.lock() {
+            ring.write(samples);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Gain of the whole chain for a sine at the CPU rate, measured once the
+    // filters have settled
+    fn gain(frequency: f64) -> f64 {
+        let mut resampler = Resampler::new(CPU_RATE, DEFAULT_RATE);
+        let cycles = (CPU_RATE * 0.2) as usize;
+        let output: Vec<f32> = (0..cycles)
+            .filter_map(|i| {
+                let t = i as f64 / CPU_RATE;
+                let level = 0.25 * (2.0 * std::f64::consts::PI * frequency * t).sin();
+                resampler.clock(level as f32)
+            })
+            .collect();
+        let settled = &output[output.len() / 2..];
+        let power: f64 = settled.iter().map(|s| (*s as f64).powi(2)).sum();
+        let rms = (power / settled.len() as f64).sqrt();
+        rms / (0.25 / 2f64.sqrt())
+    }
+
+    #[test]
+    fn resamples_at_the_output_rate() {
+        let mut resampler = Resampler::new(CPU_RATE, DEFAULT_RATE);
+        let samples = (0..CPU_RATE as usize)
+            .filter_map(|i| resampler.clock(((i / 500) % 2) as f32))
+            .count();
+        assert!(samples.abs_diff(DEFAULT_RATE as usize) <= 1);
+    }
+
+    #[test]
+    fn passes_what_can_be_heard_and_not_what_would_alias() {
+        let audible = gain(2_000.0);
+        // What is lost is the 440 Hz high pass, as on the console
+        assert!((0.9..1.0).contains(&audible), "{}", audible);
+        for frequency in [30_000.0, 40_000.0, 100_000.0] {
+            let gain = gain(frequency);
+            assert!(gain < 0.001, "{} Hz {}", frequency, gain);
+        }
+    }
+
+    #[test]
+    fn a_steady_level_settles_to_silence() {
+        let mut resampler = Resampler::new(CPU_RATE, DEFAULT_RATE);
+        let output: Vec<f32> = (0..CPU_RATE as usize / 2)
+            .filter_map(|_| resampler.clock(0.5))
+            .collect();
+        let peak = output[..40].iter().fold(0.0f32, |a, b| a.max(*b));
+        assert!(peak > 0.3, "{}", peak);
+        assert!(output[output.len() - 100..].iter().all(|s| s.abs() < 0.001));
+    }
+
+    #[test]
+    fn wav_header_counts_the_samples() {
+        let dir = std::env::temp_dir().join(format!("nes-wav-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.wav");
+        let mut wav = WavWriter::create(&path, 22_050).unwrap();
+        wav.write(&[0.0, 0.5]);
+        wav.write(&[-1.0, 2.0]);
+        wav.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(
+            (&data[0..4], u32_at(4), &data[8..16]),
+            (&b"RIFF"[..], 36 + 8, &b"WAVEfmt "[..])
+        );
+        assert_eq!((u32_at(16), u16_at(20), u16_at(22)), (16, 1, 1));
+        assert_eq!(
+            (u32_at(24), u32_at(28), u16_at(32), u16_at(34)),
+            (22_050, 44_100, 2, 16)
+        );
+        assert_eq!((&data[36..40], u32_at(40)), (&b"data"[..], 8));
+        let samples: Vec<i16> = data[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, [0, 16383, -32767, 32767]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    struct FailingSink;
+
+    impl AudioSink for FailingSink {
+        fn write(&mut self, _samples: &[f32]) {}
+
+        fn finish(&mut self) -> io::Result<()> {
+            Err(io::Error::other("disk full"))
+        }
+    }
+
+    #[test]
+    fn finish_reports_what_the_sink_hit() {
+        let ring = RingBuffer::shared(1000);
+        let mut output = AudioOutput::new(DEFAULT_RATE, Box::new(ring.clone()));
+        // A little over 100 samples' worth
+        for _ in 0..4060 {
+            output.clock(0.0);
+        }
+        // Less than a block, so nothing reaches the sink until finish
+        assert!(ring.lock().unwrap().is_empty());
+        output.finish().unwrap();
+        assert_eq!(ring.lock().unwrap().len(), 100);
+
+        let mut output = AudioOutput::new(DEFAULT_RATE, Box::new(FailingSink));
+        assert_eq!(output.finish().unwrap_err().to_string(), "disk full");
+    }
+
+    #[test]
+    fn ring_buffer_drops_the_oldest_and_pads_with_silence() {
+        let mut ring = RingBuffer::new(4);
+        ring.write(&[1.0, 2.0, 3.0]);
+        ring.write(&[4.0, 5.0, 6.0]);
+        assert_eq!(ring.len(), 4);
+
+        let mut out = [9.0; 6];
+        assert_eq!(ring.pull(&mut out), 4);
+        assert_eq!(out, [3.0, 4.0, 5.0, 6.0, 0.0, 0.0]);
+        assert!(ring.is_empty());
+        assert_eq!(ring.pull(&mut out[..2]), 0);
+        assert_eq!(out[..2], [0.0, 0.0]);
+    }
+}
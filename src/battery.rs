@@ -0,0 +1,208 @@
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::cpu_6502::Cpu6502;
+use crate::debugger::CPU_CYCLES_PER_FRAME;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Saves of games whose boards keep memory with the power off. The memory
+// is read from a .sav file next to the ROM when the game is loaded, and
+// written back when the frontend exits and every few seconds while it
+// runs, whenever it has changed.
+//
+// A .sav is written to a temporary file that then replaces the old one,
+// so a crash part way leaves the last good save rather than half of one.
+//
+// What gets saved is up to the board, behind SaveMemory: the 8K of PRG RAM
+// at $6000-$7FFF for most, the serial EEPROM for Bandai's 24C01/24C02
+// boards once those mappers exist.
+
+// Memory a board keeps with the power off
+pub trait SaveMemory {
+    fn save(&self, bus: &Bus) -> Vec<u8>;
+
+    // A file shorter than the memory fills only the start of it
+    fn restore(&self, bus: &mut Bus, data: &[u8]);
+}
+
+// The work RAM at $6000-$7FFF
+pub struct PrgRam;
+
+impl SaveMemory for PrgRam {
+    fn save(&self, bus: &Bus) -> Vec<u8> {
+        bus.ram[0x6000..0x8000].to_vec()
+    }
+
+    fn restore(&self, bus: &mut Bus, data: &[u8]) {
+        let len = data.len().min(0x2000);
+        bus.ram[0x6000..0x6000 + len].copy_from_slice(&data[..len]);
+    }
+}
+
+// About five seconds
+pub const DEFAULT_INTERVAL: u64 = 300;
+
+pub struct BatterySave {
+    path: PathBuf,
+    memory: Box<dyn SaveMemory>,
+    // What the file holds, so unchanged memory is not written again
+    saved: Vec<u8>,
+    // Frames between writes while running
+    interval: u64,
+    last_frame: Option<u64>,
+    error: Option<String>,
+}
+
+impl BatterySave {
+    pub fn new(path: &Path, memory: Box<dyn SaveMemory>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            memory,
+            saved: Vec::new(),
+            interval: DEFAULT_INTERVAL,
+            last_frame: None,
+            error: None,
+        }
+    }
+
+    // game.sav for game.nes, when the header says the board has a battery
+    pub fn for_cartridge(rom: &Path, cartridge: &Cartridge) -> Option<Self> {
+        cartridge
+            .battery
+            .then(|| BatterySave::new(&rom.with_extension("sav"), Box::new(PrgRam)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set_interval(&mut self, frames: u64) {
+        self.interval = frames.max(1);
+    }
+
+    // The first error a write while running hit, if any
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    // Puts the save into memory, false when there is no save yet
+    pub fn load(&mut self, bus: &mut Bus) -> Result<bool, String> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                self.memory.restore(bus, &data);
+                self.saved = self.memory.save(bus);
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.saved = self.memory.save(bus);
+                Ok(false)
+            }
+            Err(e) => Err(format!("{}: {}", self.path.display(), e)),
+        }
+    }
+
+    // Writes the memory out if it changed since it was loaded or last
+    // written, returning whether it did
+    pub fn flush(&mut self, bus: &Bus) -> Result<bool, String> {
+        let data = self.memory.save(bus);
+        if data == self.saved {
+            return Ok(false);
+        }
+        write_atomic(&self.path, &data).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        self.saved = data;
+        Ok(true)
+    }
+
+    // Called before every instruction, writes the save every interval
+    // frames
+    pub fn log(&mut self, cpu: &Cpu6502) {
+        let frame = cpu.clock_count / CPU_CYCLES_PER_FRAME;
+        let last = *self.last_frame.get_or_insert(frame);
+        if frame < last {
+            // Rewound, count from here
+            self.last_frame = Some(frame);
+        }
+        if frame < last + self.interval {
+            return;
+        }
+        self.last_frame = Some(frame);
+        if let Err(e) = self.flush(&cpu.bus) {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+// Writes path.tmp, syncs it to disk and renames it over path
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+    let mut file = File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of the test's own, emptied first
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nes-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_atomic_replaces_the_file() {
+        let dir = temp_dir("write-atomic");
+        let path = dir.join("game.sav");
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!dir.join("game.sav.tmp").exists());
+
+        // Nowhere to put the temporary file, and the old save is untouched
+        assert!(write_atomic(&dir.join("missing").join("game.sav"), b"x").is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_changes_are_written() {
+        let dir = temp_dir("battery");
+        let path = dir.join("game.sav");
+        let mut bus = Bus::new();
+        let mut battery = BatterySave::new(&path, Box::new(PrgRam));
+
+        // No save yet, and nothing to write until the game changes memory
+        assert!(!battery.load(&mut bus).unwrap());
+        assert!(!battery.flush(&bus).unwrap());
+        assert!(!path.exists());
+
+        bus.ram[0x6000] = 0x12;
+        bus.ram[0x7FFF] = 0x34;
+        assert!(battery.flush(&bus).unwrap());
+        assert!(!battery.flush(&bus).unwrap());
+        let saved = fs::read(&path).unwrap();
+        assert_eq!(saved.len(), 0x2000);
+        assert_eq!((saved[0], saved[0x1FFF]), (0x12, 0x34));
+
+        // Loaded into a fresh machine it is there, and counts as written
+        let mut bus = Bus::new();
+        let mut battery = BatterySave::new(&path, Box::new(PrgRam));
+        assert!(battery.load(&mut bus).unwrap());
+        assert_eq!((bus.ram[0x6000], bus.ram[0x7FFF]), (0x12, 0x34));
+        assert!(!battery.flush(&bus).unwrap());
+
+        // A short file only fills the start
+        fs::write(&path, [0xAB; 16]).unwrap();
+        assert!(battery.load(&mut bus).unwrap());
+        assert_eq!((bus.ram[0x600F], bus.ram[0x6010]), (0xAB, 0x00));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,419 @@
+use nes::audio::{self, AudioOutput, WavWriter};
+use nes::battery::BatterySave;
+use nes::cartridge::Cartridge;
+use nes::cheats::{Cheat, CheatList};
+use nes::cpu_6502::Cpu6502;
+use nes::debugger::{Debugger, StopReason, CPU_CYCLES_PER_FRAME};
+use nes::expression::Expression;
+use nes::frame_buffer::Overscan;
+use nes::movie::{Movie, MovieSession};
+use nes::ntsc::{self, NtscFilter, NtscParams};
+use nes::palette::Palette;
+use nes::video_filter::FilterChain;
+use nes::video_recorder::VideoRecorder;
+use std::path::Path;
+use std::process::exit;
+
+// Runs the emulator with no window for a fixed number of frames or cycles,
+// then reports on what the program did. Meant for test pipelines:
+//
+//     cargo run --bin headless -- --frames 600 --expect '$6000=0' game.nes
+//     cargo run --bin headless -- --movie run.fm2 --dump-ram ram.bin game.nes
+//     cargo run --bin headless -- --hash 300=1A2B3C4D --screenshot 300 title.png game.nes
+//
+// The exit status is 0 when every --expect and --hash holds, 1 when one does not and
+// 2 when the run could not be set up. With --status it is instead the byte
+// at the given address, like the $6000 result code of blargg's test ROMs.
+
+const USAGE: &str = "\
+usage: headless [options] file [load address]
+
+file is an iNES .nes image, anything else is loaded as a raw binary at the
+load address (default $8000) and started there
+
+--frames n            run n frames (default 60)
+--cycles n            run n cpu cycles instead
+--seconds n           run n seconds, 60 frames each
+--until addr          stop early when execution reaches addr
+--movie file          play an .fm2 or .bk2 movie from its start
+--cheats file         apply the cheats in an FCEUX .cht file
+--code code           apply a Game Genie or Pro Action Replay code, repeatable
+--battery             load the game's .sav if it has a battery, and save it at the end
+--dump-ram file       write the 2K of internal RAM to a file
+--dump file start end write a memory range to a file
+--expect addr=value   fail unless memory holds value at the end, repeatable
+--screenshot n file   save the picture at the end of frame n as .png or .ppm, repeatable
+--hash n[=crc]        print the hash of the picture at the end of frame n, and fail
+                      unless it is crc when one is given, repeatable
+                      (there is no PPU yet, so pictures are blank for now)
+--wav file            record the sound of the whole run
+--video file          record a .y4m video of the run from the first frame boundary,
+                      with its sound next to it as .wav (or in the --wav file)
+--rate n              sample rate of the recording (default 44100)
+--palette file|ntsc   use a .pal file, or the palette worked out from the NTSC signal
+--ntsc                put screenshots through the NTSC composite filter (twice as wide)
+--filter names        upscale screenshots with a comma separated chain of filters:
+                      nearest2-4, scale2x, scale3x, hq2x, hq3x, blend2x, blend3x, xbr2x
+--crop                cut the 8 NTSC overscan lines off the top and bottom of screenshots
+--status addr         exit with the byte at addr as the status
+--quiet               do not print the summary";
+
+struct Options {
+    file: String,
+    load_addr: u16,
+    frames: u64,
+    cycles: Option<u64>,
+    until: Option<u16>,
+    movie: Option<String>,
+    cheats: Option<String>,
+    codes: Vec<String>,
+    battery: bool,
+    dump_ram: Option<String>,
+    dumps: Vec<(String, u16, u16)>,
+    expects: Vec<(u16, u8)>,
+    captures: Vec<(u64, Capture)>,
+    palette: Option<String>,
+    ntsc: bool,
+    filters: String,
+    overscan: Overscan,
+    wav: Option<String>,
+    video: Option<String>,
+    rate: u32,
+    status: Option<u16>,
+    quiet: bool,
+}
+
+// Things done with the picture at the end of a frame
+enum Capture {
+    Screenshot(String),
+    Hash(Option<u32>),
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            file: String::new(),
+            load_addr: 0x8000,
+            frames: 60,
+            cycles: None,
+            until: None,
+            movie: None,
+            cheats: None,
+            codes: Vec::new(),
+            battery: false,
+            dump_ram: None,
+            dumps: Vec::new(),
+            expects: Vec::new(),
+            captures: Vec::new(),
+            palette: None,
+            ntsc: false,
+            filters: String::new(),
+            overscan: Overscan::NONE,
+            wav: None,
+            video: None,
+            rate: audio::DEFAULT_RATE,
+            status: None,
+            quiet: false,
+        };
+
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut next = || args.next().cloned().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--frames" => options.frames = number(&next()?)?,
+                "--cycles" => options.cycles = Some(number(&next()?)?),
+                "--seconds" => options.frames = number(&next()?)?.saturating_mul(60),
+                "--wav" => options.wav = Some(next()?),
+                "--video" => options.video = Some(next()?),
+                "--rate" => {
+                    options.rate = u32::try_from(number(&next()?)?)
+                        .ok()
+                        .filter(|r| (8000..=192_000).contains(r))
+                        .ok_or("the rate must be between 8000 and 192000")?;
+                }
+                "--until" => options.until = Some(address(&next()?)?),
+                "--movie" => options.movie = Some(next()?),
+                "--cheats" => options.cheats = Some(next()?),
+                "--code" => options.codes.push(next()?),
+                "--battery" => options.battery = true,
+                "--dump-ram" => options.dump_ram = Some(next()?),
+                "--dump" => {
+                    let path = next()?;
+                    let start = address(&next()?)?;
+                    let end = address(&next()?)?;
+                    options.dumps.push((path, start, end));
+                }
+                "--expect" => {
+                    let text = next()?;
+                    let (addr, value) = text
+                        .split_once('=')
+                        .ok_or(format!("expected addr=value, got '{}'", text))?;
+                    let value = address(value)?;
+                    if value > 0xFF {
+                        return Err(format!("${:04X} does not fit in a byte", value));
+                    }
+                    options.expects.push((address(addr)?, value as u8));
+                }
+                "--screenshot" => {
+                    let frame = number(&next()?)?;
+                    options.captures.push((frame, Capture::Screenshot(next()?)));
+                }
+                "--hash" => {
+                    let text = next()?;
+                    let (frame, crc) = match text.split_once('=') {
+                        Some((frame, crc)) => {
+                            let crc = u32::from_str_radix(crc.trim_start_matches('$'), 16)
+                                .map_err(|_| format!("bad hash '{}'", crc))?;
+                            (frame, Some(crc))
+                        }
+                        None => (text.as_str(), None),
+                    };
+                    options.captures.push((number(frame)?, Capture::Hash(crc)));
+                }
+                "--palette" => options.palette = Some(next()?),
+                "--ntsc" => options.ntsc = true,
+                "--filter" => options.filters = next()?,
+                "--crop" => options.overscan = Overscan::NTSC,
+                "--status" => options.status = Some(address(&next()?)?),
+                "--quiet" => options.quiet = true,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    exit(0);
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        options.file = positional.first().ok_or("no file to run")?.clone();
+        if let Some(addr) = positional.get(1) {
+            options.load_addr = address(addr)?;
+        }
+        Ok(options)
+    }
+}
+
+// Numbers and addresses are debugger expressions, so "$8000" and "60*10"
+// both work
+fn number(text: &str) -> Result<u64, String> {
+    let value = Expression::parse(text)?.evaluate(&Cpu6502::new());
+    u64::try_from(value).map_err(|_| format!("'{}' is negative", text))
+}
+
+fn address(text: &str) -> Result<u16, String> {
+    let value = number(text)?;
+    u16::try_from(value).map_err(|_| format!("'{}' is not an address", text))
+}
+
+fn setup(options: &Options) -> Result<(Cpu6502, Debugger), String> {
+    let mut cpu = Cpu6502::new();
+    let path = Path::new(&options.file);
+    let is_rom = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("nes"));
+    let mut battery = None;
+    if is_rom {
+        let cartridge = Cartridge::load_file(path)?;
+        if options.battery {
+            battery = BatterySave::for_cartridge(path, &cartridge);
+        }
+        cartridge.insert(&mut cpu.bus);
+        cpu.power_on();
+        if let Some(battery) = battery.as_mut() {
+            battery.load(&mut cpu.bus)?;
+        }
+    } else {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (i, b) in data.iter().enumerate() {
+            cpu.bus.write(&options.load_addr.wrapping_add(i as u16), b);
+        }
+        cpu.power_on();
+        cpu.pc = options.load_addr;
+    }
+
+    let mut debugger = Debugger::new();
+    debugger.set_battery(battery);
+    if let Some(path) = &options.movie {
+        // Movies start from power on or their own save state, which for a
+        // raw binary means from its reset vector
+        let movie = Movie::load(Path::new(path))?;
+        debugger.set_movie(Some(MovieSession::play(movie, &mut cpu)?));
+    }
+    if let Some(path) = &options.cheats {
+        *debugger.cheats_mut() = CheatList::load(Path::new(path))?;
+    }
+    for code in &options.codes {
+        debugger.cheats_mut().add(Cheat::from_code(code)?);
+    }
+    if let Some(addr) = options.until {
+        debugger.add_breakpoint(addr, None);
+    }
+    Ok((cpu, debugger))
+}
+
+fn run(options: &Options) -> Result<i32, String> {
+    let (mut cpu, mut debugger) = setup(options)?;
+    let palette = match &options.palette {
+        Some(name) if name.eq_ignore_ascii_case("ntsc") => {
+            ntsc::generate_palette(&NtscParams::new())
+        }
+        Some(path) => Palette::load(Path::new(path))?,
+        None => Palette::new(),
+    };
+    let filter = options.ntsc.then(|| NtscFilter::new(NtscParams::new()));
+    let filters = FilterChain::parse(&options.filters)?;
+
+    if let Some(path) = &options.video {
+        let wav = match &options.wav {
+            Some(wav) => Path::new(wav).to_path_buf(),
+            None => Path::new(path).with_extension("wav"),
+        };
+        let video = VideoRecorder::create(
+            Path::new(path),
+            palette.clone(),
+            options.overscan,
+            Some((&wav, options.rate)),
+        )
+        .map_err(|e| format!("{}: {}", path, e))?;
+        debugger.set_video(Some(video));
+    } else if let Some(path) = &options.wav {
+        let wav = WavWriter::create(Path::new(path), options.rate)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        cpu.bus.audio = Some(AudioOutput::new(options.rate, Box::new(wav)));
+    }
+
+    let cycles = options
+        .cycles
+        .unwrap_or(options.frames.saturating_mul(CPU_CYCLES_PER_FRAME));
+    // Time is counted from power on through the debugger, whose count keeps
+    // going when a movie powers the console on again
+    let start = cpu.clock_count;
+    let clock = |debugger: &Debugger| start + debugger.elapsed();
+    let end = start.saturating_add(cycles);
+
+    // Run up to the end of each frame something wants the picture of, then
+    // on to the end
+    let mut captures: Vec<&(u64, Capture)> = options.captures.iter().collect();
+    captures.sort_by_key(|(frame, _)| *frame);
+    let mut status = 0;
+    let mut reason = None;
+    debugger.resume();
+    for (frame, capture) in captures {
+        let target = frame.saturating_mul(CPU_CYCLES_PER_FRAME);
+        if target > end {
+            println!("frame {} is past the end of the run", frame);
+            status = 1;
+            continue;
+        }
+        if clock(&debugger) < target {
+            let budget = target - clock(&debugger);
+            reason = debugger.update(&mut cpu, budget);
+            if reason.is_some() {
+                break;
+            }
+        }
+        match capture {
+            Capture::Screenshot(path) => {
+                let screen = &cpu.bus.screen;
+                let image = match &filter {
+                    Some(filter) => filter.apply(screen, options.overscan, *frame),
+                    None => screen.screenshot(&palette, options.overscan),
+                };
+                filters.apply(&image).save(Path::new(path))?
+            }
+            Capture::Hash(expected) => {
+                let hash = cpu.bus.screen.hash();
+                println!("frame {} hash {:08X}", frame, hash);
+                if let Some(expected) = expected.filter(|e| *e != hash) {
+                    println!("expected hash {:08X}", expected);
+                    status = 1;
+                }
+            }
+        }
+    }
+    if reason.is_none() && clock(&debugger) < end {
+        let budget = end - clock(&debugger);
+        reason = debugger.update(&mut cpu, budget);
+    }
+
+    if !options.quiet {
+        let stopped = match reason {
+            Some(StopReason::Breakpoint(_)) => " at the --until address",
+            Some(_) => " early",
+            None => "",
+        };
+        println!(
+            "stopped{} after {} frames, {} cycles: PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X}",
+            stopped,
+            clock(&debugger) / CPU_CYCLES_PER_FRAME,
+            clock(&debugger),
+            cpu.pc,
+            cpu.a,
+            cpu.x,
+            cpu.y,
+            cpu.sp,
+            cpu.sr
+        );
+    }
+
+    if let Some(video) = debugger.set_video(None) {
+        let frames = video.finish(&mut cpu).map_err(|e| e.to_string())?;
+        if !options.quiet {
+            println!("recorded {} frames", frames);
+        }
+    }
+    if let Some(mut audio) = cpu.bus.audio.take() {
+        let path = options.wav.as_deref().unwrap_or_default();
+        audio.finish().map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let Some(mut battery) = debugger.set_battery(None) {
+        if let Some(e) = battery.error() {
+            return Err(e.to_string());
+        }
+        if battery.flush(&cpu.bus)? && !options.quiet {
+            println!("saved {}", battery.path().display());
+        }
+    }
+
+    if let Some(path) = &options.dump_ram {
+        std::fs::write(path, &cpu.bus.ram[..0x0800]).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    for (path, start, end) in &options.dumps {
+        let data: Vec<u8> = (*start..=*end).map(|a| cpu.bus.read(&a, true)).collect();
+        std::fs::write(path, data).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    for (addr, expected) in &options.expects {
+        let actual = cpu.bus.read(addr, true);
+        if actual != *expected {
+            println!(
+                "expected ${:02X} at ${:04X}, found ${:02X}",
+                expected, addr, actual
+            );
+            status = 1;
+        }
+    }
+    if let Some(addr) = options.status {
+        status = cpu.bus.read(&addr, true) as i32;
+    }
+    Ok(status)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+    match run(&options) {
+        Ok(status) => exit(status),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(2);
+        }
+    }
+}
@@ -0,0 +1,1505 @@
+use nes::assembler::Assembler;
+use nes::audio;
+use nes::battery::BatterySave;
+use nes::cartridge::Cartridge;
+use nes::cheat_search::{CheatSearch, Filter};
+use nes::cheats::{self, Cheat, CheatKind, CheatList};
+use nes::code_data_logger::CodeDataLogger;
+use nes::controller::buttons_to_fm2;
+use nes::cpu_6502::Cpu6502;
+use nes::debugger::{AccessKind, Debugger, StopReason, CPU_CYCLES_PER_FRAME};
+use nes::disassembler::{syntax_by_name, JumpTableKind, Tracer};
+use nes::expression::Expression;
+use nes::frame_buffer::Overscan;
+use nes::gdb_stub::GdbStub;
+use nes::memory_editor::{self, parse_pattern, AddressSpace, Freeze, MemoryEditor};
+use nes::movie::{Movie, MovieMode, MovieSession};
+use nes::ntsc::{self, NtscFilter, NtscParams};
+use nes::palette::Palette;
+use nes::ppu_viewer::{self, PpuMemory};
+use nes::profiler::Profiler;
+use nes::rewind::{self, Rewind};
+use nes::save_state;
+use nes::symbols::SymbolTable;
+use nes::trace_logger::{TraceCondition, TraceFormat, TraceLogger};
+use nes::video_filter::FilterChain;
+use nes::video_recorder::VideoRecorder;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+// Text mode monitor for the 6502 core, in the spirit of the VICE and Mesen
+// monitors. It only uses stdin and stdout, so it can be scripted or used
+// over ssh:
+//
+//     cargo run --bin monitor -- program.bin '$8000' < script.txt
+//     cargo run --bin monitor -- game.nes
+//
+// Addresses and values are debugger expressions, so hex needs a $ prefix
+// and things like "pc+3" or "[$FFFC]" work too.
+
+const HELP: &str = "\
+r [reg=value ...]        show or set registers (a x y sp pc p)
+m [space] [start [end]]  dump memory, * marks bytes changed by the last run; space
+                         is cpu (the default), ppu, oam, prg, chr or sram
+e [space] addr byte ...  write bytes to memory
+find [space] bytes       search for a byte pattern, e.g. find A9 ?? 8D (?? is any byte)
+freeze [space] addr [value]  hold an address at a value (default what it holds now),
+                         or list the frozen addresses
+unfreeze [space] addr | all  let frozen addresses change again
+cs reset                 start a cheat search over the 2K of RAM
+cs [filter]              narrow the search down, or list what is left; filter is eq, ne,
+                         gt or lt [value] (default: the last snapshot), changed,
+                         unchanged or by n (n signed, e.g. by -1)
+cs cheat [value [name]]  make cheats of what is left (default value: what it holds now)
+cheat [add addr value [name]]  list cheats, or add one
+cheat code code [name]   add a Game Genie (6 or 8 letters) or Pro Action Replay code
+cheat del|on|off n       remove, enable or disable cheat n
+cheat load file | save [file]  read or write an FCEUX .cht cheat file; a game's own
+                         cheats are in game.cht, loaded with it and saved on exit
+d [start [count [syn]]]  disassemble, syn = ca65, asm6 or nesasm
+a start instruction      assemble one instruction, e.g. a $8000 LDA #$10
+b [addr [if condition]]  add a breakpoint, or list them
+w [start[-end] [rwx]]    add read/write/execute watchpoints, or list them
+del id | en id | dis id  delete, enable or disable a break/watchpoint
+g [addr]                 go until something stops the cpu
+s [count]                step instructions
+n [count]                step over subroutine calls
+ret                      run until the current routine returns
+until addr | nmi         run to an address or to the NMI handler
+frame                    run one frame
+limit [frames]           show or set how long g, n, ret and until may run before
+                         giving up (default 3600 frames, 0 for no limit)
+reset | irq | nmi        signal the cpu
+load file addr           load a binary file into memory
+asm file [listing]       assemble a source file into memory, optionally writing a listing
+sym [file]               load .dbg, .nl, .mlb or .sym symbols, or list the labels
+rewind on [seconds] | off  keep a state every frame (default 60 seconds)
+rewind [back [count]]    show the rewind buffer, or go back count frames
+state save|load file     snapshot the whole machine, or restore a snapshot
+pad 1|2 [buttons]        hold buttons from RLDUTSBA (T = start, S = select), . for none
+movie record file [state]  record input from power on (or from now) to an .fm2 or .bk2
+movie play file | stop   play a movie back, or stop (a recording is written out)
+save file start end      save a memory range to a file
+screenshot file [crop] [ntsc]  save the picture as .png or .ppm, crop cuts the
+                         overscan lines, ntsc puts it through the composite filter
+                         (blank until there is a PPU)
+palette [file]           use a .pal file for pictures, or go back to the built in one
+palette ntsc [hue [sat [contrast [bright [gamma]]]]]  work the palette out from
+                         the NTSC signal (defaults 0 1 1 0 2.2)
+palette save file        write the palette in use as a .pal file
+filter [names | none]    show or set the upscaling filters for screenshots, a comma
+                         separated chain of nearest2-4 scale2x scale3x hq2x hq3x blend2x
+                         blend3x xbr2x
+ppu view file [table [palette]]  save a PPU view as a picture, view is one of
+                         nametables, patterns (table 0-1, palette 0-7), palette or oam
+record file [crop]       record a .y4m video, with the sound next to it as .wav
+record [stop]            show how much has been recorded, or stop
+entry addr               tell listing about code it cannot find on its own
+table addr count [rts]   tell listing about a jump table (rts: entries are address-1)
+listing file [syn [start [end]]]  trace the code from the vectors and write a
+                         source listing that reassembles (default ca65 $8000-$FFFF)
+trace file [fmt]         log every instruction to a file, fmt = nestest or mesen
+trace ring count [fmt]   keep only the last count instructions in memory
+trace from|to addr | frame n  start or stop the trace when addr runs or at frame n
+trace dump file | off    write out the ring buffer, or stop tracing
+cdl [on [prg [chr]]]     start a code/data log (sized for the cartridge, or 32K PRG and
+                         8K CHR without one), or show coverage
+cdl save|load file | off  write or merge an FCEUX .cdl file, or stop logging
+prof on | off            start or stop the cycle profiler
+prof [file] | flame file  show the per routine report, or write collapsed stacks
+bt [problems]            show the call stack, or the stack problems caught so far
+bt break on|off          stop on bad returns and stack wraps
+gdb [port]               serve the gdb remote protocol on localhost (default 6502)
+x                        exit";
+
+// A minute of emulated time
+const DEFAULT_LIMIT: u64 = 3600;
+
+struct Monitor {
+    cpu: Cpu6502,
+    debugger: Debugger,
+    // Where m and d carry on from when they are given no address
+    next_mem: usize,
+    next_dis: u16,
+    // Hints for the tracing disassembler behind the listing command
+    entries: Vec<u16>,
+    tables: Vec<(u16, u16, JumpTableKind)>,
+    symbols: SymbolTable,
+    // Where the movie being recorded is written when it stops
+    movie_path: Option<String>,
+    // Colours for screenshots and video
+    palette: Palette,
+    // Which memory m shows, and what it held before the cpu last ran
+    editor: MemoryEditor,
+    // Upscaling for screenshots
+    filters: FilterChain,
+    search: Option<CheatSearch>,
+    // The cheats file of the game loaded, and what was last saved to it
+    cheats_path: Option<PathBuf>,
+    saved_cheats: String,
+    // Frames a run may take before the monitor pauses it, 0 for no limit
+    limit: u64,
+    quit: bool,
+}
+
+impl Monitor {
+    fn new() -> Self {
+        Self {
+            cpu: Cpu6502::new(),
+            debugger: Debugger::new(),
+            next_mem: 0x0000,
+            next_dis: 0x0000,
+            entries: Vec::new(),
+            tables: Vec::new(),
+            symbols: SymbolTable::new(),
+            movie_path: None,
+            palette: Palette::new(),
+            editor: MemoryEditor::new(8),
+            filters: FilterChain::new(),
+            search: None,
+            cheats_path: None,
+            saved_cheats: String::new(),
+            limit: DEFAULT_LIMIT,
+            quit: false,
+        }
+    }
+
+    fn execute(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            return Ok(());
+        }
+
+        let (cmd, rest) = split_first(line);
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        if ["g", "s", "n", "ret", "until", "frame"].contains(&cmd.to_ascii_lowercase().as_str()) {
+            self.editor.begin_frame(&self.cpu);
+        }
+        match cmd.to_ascii_lowercase().as_str() {
+            "r" => self.registers(&args),
+            "m" => self.memory(&args),
+            "e" => self.edit(&args),
+            "find" => self.find(&args),
+            "freeze" => self.freeze(&args),
+            "unfreeze" => self.unfreeze(&args),
+            "cs" => self.cheat_search(&args),
+            "cheat" => self.cheat(&args),
+            "d" => self.disassemble(&args),
+            "a" => self.assemble(rest),
+            "b" => self.breakpoint(rest),
+            "w" => self.watchpoint(&args),
+            "del" => {
+                let id = self.id(&args)?;
+                self.debugger
+                    .remove(id)
+                    .then_some(())
+                    .ok_or(format!("no #{}", id))
+            }
+            "en" | "dis" => {
+                let id = self.id(&args)?;
+                let enabled = cmd.eq_ignore_ascii_case("en");
+                self.debugger
+                    .set_enabled(id, enabled)
+                    .then_some(())
+                    .ok_or(format!("no #{}", id))
+            }
+            "g" => {
+                if let Some(addr) = args.first() {
+                    self.cpu.pc = self.value(addr)?;
+                }
+                self.debugger.resume();
+                self.run();
+                Ok(())
+            }
+            "s" => {
+                for _ in 0..self.count(&args, 0, 1)? {
+                    let reason = self.debugger.step(&mut self.cpu);
+                    if reason != StopReason::Step {
+                        self.report(reason);
+                        break;
+                    }
+                }
+                self.show_current();
+                Ok(())
+            }
+            "n" => {
+                for _ in 0..self.count(&args, 0, 1)? {
+                    self.debugger.step_over(&self.cpu);
+                    match self.wait() {
+                        Some(StopReason::Step | StopReason::Reached(_)) => (),
+                        Some(reason) => {
+                            self.report(reason);
+                            break;
+                        }
+                        None => break,
+                    }
+                }
+                self.show_current();
+                Ok(())
+            }
+            "ret" => {
+                self.debugger.step_out(&self.cpu);
+                self.run();
+                Ok(())
+            }
+            "until" => {
+                match args.first() {
+                    Some(arg) if arg.eq_ignore_ascii_case("nmi") => self.debugger.run_until_nmi(),
+                    Some(arg) => {
+                        let addr = self.value(arg)?;
+                        self.debugger.run_to(addr)
+                    }
+                    None => return Err("until needs an address or nmi".to_string()),
+                }
+                self.run();
+                Ok(())
+            }
+            "frame" => {
+                self.debugger.run_frame(&self.cpu);
+                self.run();
+                Ok(())
+            }
+            "limit" => {
+                match args.first() {
+                    Some(arg) => self.limit = self.value(arg)? as u64,
+                    None if self.limit == 0 => println!("no limit"),
+                    None => println!("{} frames", self.limit),
+                }
+                Ok(())
+            }
+            "reset" => {
+                self.cpu.reset();
+                self.debugger.clear_call_stack();
+                self.show_current();
+                Ok(())
+            }
+            "irq" => {
+                self.cpu.irq();
+                self.show_current();
+                Ok(())
+            }
+            "nmi" => {
+                self.cpu.nmi();
+                self.show_current();
+                Ok(())
+            }
+            "load" => {
+                let path = unquote(args.first().ok_or("load needs a file name")?);
+                let addr = self.value(args.get(1).ok_or("load needs an address")?)?;
+                let size = self.load(path, addr)?;
+                println!("loaded {} bytes at ${:04X}", size, addr);
+                Ok(())
+            }
+            "asm" => {
+                let path = unquote(args.first().ok_or("asm needs a file name")?);
+                let assembly = Assembler::new().assemble_file(Path::new(path))?;
+                assembly.load(&mut self.cpu.bus);
+                self.symbols.add_assembly(&assembly);
+                if let Some(listing) = args.get(1) {
+                    std::fs::write(unquote(listing), assembly.listing())
+                        .map_err(|e| e.to_string())?;
+                }
+                let size: usize = assembly.segments.iter().map(|s| s.data.len()).sum();
+                println!(
+                    "assembled {} bytes, {} symbols",
+                    size,
+                    assembly.symbols.len()
+                );
+                Ok(())
+            }
+            "sym" => {
+                match args.first() {
+                    Some(path) => {
+                        let count = self
+                            .symbols
+                            .load(Path::new(unquote(path)), self.cpu.bus.cartridge.as_ref())?;
+                        println!("loaded {} symbols", count);
+                    }
+                    None => {
+                        for (addr, name) in self.symbols.labels() {
+                            println!("${:04X} {}", addr, name);
+                        }
+                    }
+                }
+                Ok(())
+            }
+            "state" => {
+                let path = unquote(args.get(1).ok_or("state needs save or load and a file")?);
+                match args.first().copied() {
+                    Some("save") => save_state::save_file(&self.cpu, Path::new(path))?,
+                    Some("load") => {
+                        save_state::load_file(&mut self.cpu, Path::new(path))?;
+                        self.debugger.clear_call_stack();
+                        if let Some(movie) = self.debugger.movie_mut() {
+                            movie.rewound(&self.cpu);
+                        }
+                        self.show_current();
+                    }
+                    _ => return Err("state needs save or load".to_string()),
+                }
+                Ok(())
+            }
+            "save" => {
+                if args.len() < 3 {
+                    return Err("save needs a file name, start and end".to_string());
+                }
+                let start = self.value(args[1])?;
+                let end = self.value(args[2])?;
+                let data: Vec<u8> = (start..=end).map(|a| self.cpu.bus.read(&a, true)).collect();
+                std::fs::write(unquote(args[0]), &data).map_err(|e| e.to_string())?;
+                println!("saved {} bytes", data.len());
+                Ok(())
+            }
+            "screenshot" => {
+                let path = unquote(args.first().ok_or("screenshot needs a file name")?);
+                let mut overscan = Overscan::NONE;
+                let mut filter = None;
+                for arg in &args[1..] {
+                    match *arg {
+                        "crop" => overscan = Overscan::NTSC,
+                        "ntsc" => filter = Some(NtscFilter::new(NtscParams::new())),
+                        _ => return Err(format!("unknown screenshot option '{}'", arg)),
+                    }
+                }
+                let screen = &self.cpu.bus.screen;
+                let image = match filter {
+                    Some(filter) => {
+                        let frame = self.cpu.clock_count / CPU_CYCLES_PER_FRAME;
+                        filter.apply(screen, overscan, frame)
+                    }
+                    None => screen.screenshot(&self.palette, overscan),
+                };
+                self.filters.apply(&image).save(Path::new(path))
+            }
+            "palette" => self.set_palette(&args),
+            "record" => self.record(&args),
+            "ppu" => {
+                let view = args.first().ok_or("ppu needs a view")?;
+                let path = unquote(args.get(1).ok_or("ppu needs a file name")?);
+                let memory = PpuMemory::from_bus(&self.cpu.bus);
+                let image = match *view {
+                    "nametables" => ppu_viewer::nametables(&memory, &self.palette),
+                    "patterns" => {
+                        let table = match args.get(2) {
+                            Some(arg) => self.value(arg)? as usize,
+                            None => 0,
+                        };
+                        let palette = match args.get(3) {
+                            Some(arg) => self.value(arg)? as usize,
+                            None => 0,
+                        };
+                        ppu_viewer::pattern_table(&memory, table, palette, &self.palette)
+                    }
+                    "palette" => ppu_viewer::palette_ram(&memory, &self.palette),
+                    "oam" => ppu_viewer::oam_view(&memory, &self.palette),
+                    _ => return Err(format!("unknown ppu view '{}'", view)),
+                };
+                image.save(Path::new(path))
+            }
+            "filter" => {
+                if !args.is_empty() {
+                    self.filters = FilterChain::parse(&args.join(" "))?;
+                }
+                println!(
+                    "filters: {} ({}x)",
+                    self.filters.describe(),
+                    self.filters.scale()
+                );
+                Ok(())
+            }
+            "entry" => {
+                let addr = self.value(args.first().ok_or("entry needs an address")?)?;
+                self.entries.push(addr);
+                Ok(())
+            }
+            "table" => {
+                if args.len() < 2 {
+                    return Err("table needs an address and a count".to_string());
+                }
+                let kind = match args.get(2) {
+                    Some(arg) if arg.eq_ignore_ascii_case("rts") => JumpTableKind::RtsWords,
+                    Some(arg) => return Err(format!("unknown table kind '{}'", arg)),
+                    None => JumpTableKind::Words,
+                };
+                let addr = self.value(args[0])?;
+                let entries = self.value(args[1])?;
+                self.tables.push((addr, entries, kind));
+                Ok(())
+            }
+            "listing" => self.listing(&args),
+            "trace" => self.trace(&args),
+            "cdl" => self.code_data_log(&args),
+            "prof" => self.profile(&args),
+            "bt" => self.backtrace(&args),
+            "rewind" => self.rewind(&args),
+            "pad" => self.pad(&args),
+            "movie" => self.movie(&args),
+            "gdb" => {
+                let port = match args.first() {
+                    Some(arg) => arg.parse().map_err(|_| format!("bad port '{}'", arg))?,
+                    None => 6502,
+                };
+                let listener = GdbStub::bind(port).map_err(|e| e.to_string())?;
+                println!("waiting for gdb on 127.0.0.1:{}", port);
+                let mut stub = GdbStub::accept(&listener).map_err(|e| e.to_string())?;
+                stub.serve(&mut self.cpu, &mut self.debugger)
+                    .map_err(|e| e.to_string())?;
+                println!("gdb detached");
+                self.show_current();
+                Ok(())
+            }
+            "help" | "?" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            "x" | "q" | "quit" | "exit" => {
+                self.quit = true;
+                Ok(())
+            }
+            _ => Err(format!("unknown command '{}', try help", cmd)),
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // COMMANDS
+
+    fn registers(&mut self, args: &[&str]) -> Result<(), String> {
+        for arg in args {
+            let (reg, value) = arg
+                .split_once('=')
+                .ok_or(format!("expected reg=value, got '{}'", arg))?;
+            let value = self.value(value)?;
+            match reg.to_ascii_lowercase().as_str() {
+                "a" => self.cpu.a = value as u8,
+                "x" => self.cpu.x = value as u8,
+                "y" => self.cpu.y = value as u8,
+                "sp" => self.cpu.sp = value as u8,
+                "p" => self.cpu.sr = value as u8,
+                "pc" => self.cpu.pc = value,
+                _ => return Err(format!("unknown register '{}'", reg)),
+            }
+        }
+        self.show_registers();
+        Ok(())
+    }
+
+    fn memory(&mut self, args: &[&str]) -> Result<(), String> {
+        let (space, args) = space_arg(args);
+        if space != self.editor.space() {
+            self.editor.set_space(space);
+            self.next_mem = 0;
+        }
+        let size = space.size(&self.cpu);
+        if size == 0 {
+            return Err(format!("there is no {} memory", space.name()));
+        }
+        let start = match args.first() {
+            Some(arg) => self.offset(arg)?,
+            None => self.next_mem,
+        }
+        .min(size - 1);
+        let end = match args.get(1) {
+            Some(arg) => self.offset(arg)?,
+            None => start + 0x7F,
+        }
+        .min(size - 1);
+
+        // Bytes changed since the cpu last ran are marked with a *
+        let mut addr = start;
+        while addr <= end {
+            let row: Vec<usize> = (addr..(addr + 16).min(end + 1)).collect();
+            let bytes: Vec<u8> = row.iter().map(|a| space.read(&self.cpu, *a)).collect();
+            let hex: String = row
+                .iter()
+                .zip(&bytes)
+                .map(|(a, b)| {
+                    let mark = if self.editor.changed(&self.cpu, *a) {
+                        '*'
+                    } else {
+                        ' '
+                    };
+                    format!("{}{:02X}", mark, b)
+                })
+                .collect();
+            let text: String = bytes
+                .iter()
+                .map(|b| {
+                    if b.is_ascii_graphic() {
+                        *b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            let names: Vec<&str> = match space {
+                AddressSpace::Cpu => self
+                    .symbols
+                    .labels()
+                    .range(addr as u16..=(addr + row.len() - 1) as u16)
+                    .map(|(_, name)| name.as_str())
+                    .collect(),
+                _ => Vec::new(),
+            };
+            println!(
+                "${:04X}:{:<48} |{:<16}| {}",
+                addr,
+                hex,
+                text,
+                names.join(" ")
+            );
+            addr += 16;
+        }
+        self.next_mem = (end + 1) % size;
+        Ok(())
+    }
+
+    fn edit(&mut self, args: &[&str]) -> Result<(), String> {
+        let (space, args) = space_arg(args);
+        let addr = self.offset(args.first().ok_or("e needs an address")?)?;
+        if args.len() < 2 {
+            return Err("e needs the bytes to write".to_string());
+        }
+        for (i, arg) in args[1..].iter().enumerate() {
+            let value = self.value(arg)?;
+            space.write(&mut self.cpu, addr + i, value as u8)?;
+        }
+        Ok(())
+    }
+
+    fn find(&mut self, args: &[&str]) -> Result<(), String> {
+        let (space, args) = space_arg(args);
+        let pattern = parse_pattern(&args.join(" "))?;
+        let mut found = Vec::new();
+        let mut from = 0;
+        while let Some(addr) = memory_editor::search(&self.cpu, space, &pattern, from) {
+            if addr < from || found.len() == 64 {
+                break;
+            }
+            found.push(format!("${:04X}", addr));
+            from = addr + 1;
+        }
+        if found.is_empty() {
+            println!("not found");
+        } else {
+            println!("{}", found.join(" "));
+        }
+        Ok(())
+    }
+
+    fn freeze(&mut self, args: &[&str]) -> Result<(), String> {
+        let (space, args) = space_arg(args);
+        match args.first() {
+            Some(arg) => {
+                let addr = self.offset(arg)?;
+                if addr >= space.size(&self.cpu) {
+                    return Err(format!("${:X} is past the end of {}", addr, space.name()));
+                }
+                let value = match args.get(1) {
+                    Some(arg) => self.value(arg)? as u8,
+                    None => space.read(&self.cpu, addr),
+                };
+                self.debugger.freeze(space, addr, value);
+                Freeze { space, addr, value }.apply(&mut self.cpu);
+            }
+            None => {
+                for f in self.debugger.freezes() {
+                    println!("{} ${:04X} = ${:02X}", f.space.name(), f.addr, f.value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn unfreeze(&mut self, args: &[&str]) -> Result<(), String> {
+        if args
+            .first()
+            .is_some_and(|arg| arg.eq_ignore_ascii_case("all"))
+        {
+            self.debugger.clear_freezes();
+            return Ok(());
+        }
+        let (space, args) = space_arg(args);
+        let addr = self.offset(args.first().ok_or("unfreeze needs an address or all")?)?;
+        self.debugger
+            .unfreeze(space, addr)
+            .then_some(())
+            .ok_or(format!("{} ${:04X} is not frozen", space.name(), addr))
+    }
+
+    fn cheat_search(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first().map(|a| a.to_ascii_lowercase()).as_deref() {
+            Some("reset") => {
+                self.search = Some(CheatSearch::new(&self.cpu));
+                println!("{} candidates", self.search.as_ref().map_or(0, |s| s.len()));
+                return Ok(());
+            }
+            Some("cheat") => {
+                let search = self
+                    .search
+                    .as_ref()
+                    .ok_or("no cheat search, try cs reset")?;
+                let value = match args.get(1) {
+                    Some(arg) => Some(self.value(arg)? as u8),
+                    None => None,
+                };
+                let name = args.get(2..).map_or(String::new(), |words| words.join(" "));
+                let name = if name.is_empty() { "search" } else { &name };
+                let cheats = search.to_cheats(&self.cpu, value, name);
+                println!("added {} cheats", cheats.len());
+                for cheat in cheats {
+                    self.debugger.cheats_mut().add(cheat);
+                }
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        let search = self
+            .search
+            .as_mut()
+            .ok_or("no cheat search, try cs reset")?;
+        if !args.is_empty() {
+            let left = search.filter(&self.cpu, Filter::parse(&args.join(" "))?);
+            println!("{} candidates", left);
+            if left > 16 {
+                return Ok(());
+            }
+        }
+        let candidates = search.candidates(&self.cpu);
+        for (addr, previous, now) in candidates.iter().take(64) {
+            println!("${:04X}: ${:02X} -> ${:02X}", addr, previous, now);
+        }
+        if candidates.len() > 64 {
+            println!("... {} more", candidates.len() - 64);
+        }
+        Ok(())
+    }
+
+    fn cheat(&mut self, args: &[&str]) -> Result<(), String> {
+        let index = |args: &[&str]| -> Result<usize, String> {
+            let arg = args.get(1).ok_or("missing cheat number")?;
+            arg.parse()
+                .map_err(|_| format!("bad cheat number '{}'", arg))
+        };
+        match args.first().map(|a| a.to_ascii_lowercase()).as_deref() {
+            None => {
+                for (i, c) in self.debugger.cheats().cheats().iter().enumerate() {
+                    let compare = c
+                        .compare
+                        .map_or(String::new(), |v| format!(" if ${:02X}", v));
+                    let state = if c.enabled { "on " } else { "off" };
+                    let kind = match c.kind {
+                        CheatKind::Ram => "ram",
+                        CheatKind::Rom => "rom",
+                    };
+                    println!(
+                        "{}: {} {} ${:04X} = ${:02X}{} {}",
+                        i, state, kind, c.addr, c.value, compare, c.name
+                    );
+                }
+                Ok(())
+            }
+            Some("add") => {
+                let addr = self.value(args.get(1).ok_or("cheat add needs an address")?)?;
+                let value = self.value(args.get(2).ok_or("cheat add needs a value")?)?;
+                let name = args.get(3..).map_or(String::new(), |words| words.join(" "));
+                let cheat = Cheat::new(&name, addr, value as u8);
+                self.debugger.cheats_mut().add(cheat);
+                Ok(())
+            }
+            Some("code") => {
+                let code = args.get(1).ok_or("cheat code needs a code")?;
+                let mut cheat = Cheat::from_code(code)?;
+                if args.len() > 2 {
+                    cheat.name = args[2..].join(" ");
+                }
+                self.debugger.cheats_mut().add(cheat);
+                Ok(())
+            }
+            Some("del") => {
+                let i = index(args)?;
+                self.debugger
+                    .cheats_mut()
+                    .remove(i)
+                    .map(|_| ())
+                    .ok_or(format!("no cheat {}", i))
+            }
+            Some(state @ ("on" | "off")) => {
+                let i = index(args)?;
+                self.debugger
+                    .cheats_mut()
+                    .set_enabled(i, state == "on")
+                    .then_some(())
+                    .ok_or(format!("no cheat {}", i))
+            }
+            Some("load") => {
+                let path = unquote(args.get(1).ok_or("cheat load needs a file name")?);
+                let list = CheatList::load(Path::new(path))?;
+                println!("loaded {} cheats", list.cheats().len());
+                for cheat in list.cheats() {
+                    self.debugger.cheats_mut().add(cheat.clone());
+                }
+                Ok(())
+            }
+            Some("save") => {
+                let path = match args.get(1) {
+                    Some(path) => PathBuf::from(unquote(path)),
+                    None => self
+                        .cheats_path
+                        .clone()
+                        .ok_or("cheat save needs a file name")?,
+                };
+                self.debugger.cheats().save(&path)?;
+                if Some(&path) == self.cheats_path.as_ref() {
+                    self.saved_cheats = self.debugger.cheats().to_cht();
+                }
+                Ok(())
+            }
+            Some(other) => Err(format!("unknown cheat command '{}'", other)),
+        }
+    }
+
+    fn disassemble(&mut self, args: &[&str]) -> Result<(), String> {
+        let mut addr = match args.first() {
+            Some(arg) => self.value(arg)?,
+            None => self.next_dis,
+        };
+        let syntax = match args.get(2) {
+            Some(name) => Some(syntax_by_name(name).ok_or(format!("unknown syntax '{}'", name))?),
+            None => None,
+        };
+        for _ in 0..self.count(args, 1, 16)? {
+            let instruction = self.cpu.decode(addr);
+            if let Some(name) = self.symbols.label(addr) {
+                println!("{}:", name);
+            }
+            match &syntax {
+                Some(syntax) => {
+                    let bytes: Vec<String> = instruction
+                        .bytes
+                        .iter()
+                        .map(|b| format!("{:02X}", b))
+                        .collect();
+                    println!(
+                        "${:04X}  {:<9} {}",
+                        addr,
+                        bytes.join(" "),
+                        syntax.format(&instruction)
+                    );
+                }
+                None => println!("{}", self.line_at(addr)),
+            }
+            addr = addr.wrapping_add(instruction.bytes.len() as u16);
+        }
+        self.next_dis = addr;
+        Ok(())
+    }
+
+    fn listing(&mut self, args: &[&str]) -> Result<(), String> {
+        let path = unquote(args.first().ok_or("listing needs a file name")?);
+        let name = args.get(1).copied().unwrap_or("ca65");
+        let syntax = syntax_by_name(name).ok_or(format!("unknown syntax '{}'", name))?;
+        let start = match args.get(2) {
+            Some(arg) => self.value(arg)?,
+            None => 0x8000,
+        };
+        let end = match args.get(3) {
+            Some(arg) => self.value(arg)?,
+            None => 0xFFFF,
+        };
+
+        let mut tracer = Tracer::new(start, end);
+        for (addr, name) in self.symbols.labels() {
+            tracer.add_label(*addr, name);
+        }
+        for addr in &self.entries {
+            tracer.add_entry(*addr);
+        }
+        for (addr, entries, kind) in &self.tables {
+            tracer.add_jump_table(*addr, *entries, *kind);
+        }
+        if let Some(cdl) = &self.cpu.bus.cdl {
+            tracer.add_code_data_log(cdl);
+        }
+        let map = tracer.trace(&self.cpu);
+        std::fs::write(path, map.listing(&self.cpu, syntax.as_ref())).map_err(|e| e.to_string())?;
+        println!(
+            "{} instructions, {} labels written to {}",
+            map.instructions().len(),
+            map.labels().len(),
+            path
+        );
+        Ok(())
+    }
+
+    fn trace(&mut self, args: &[&str]) -> Result<(), String> {
+        let format = |arg: Option<&&str>| match arg.map(|a| a.to_ascii_lowercase()) {
+            None => Ok(TraceFormat::Nestest),
+            Some(name) if name == "nestest" => Ok(TraceFormat::Nestest),
+            Some(name) if name == "mesen" => Ok(TraceFormat::Mesen),
+            Some(name) => Err(format!("unknown trace format '{}'", name)),
+        };
+
+        match args.first().copied() {
+            None => match self.debugger.trace() {
+                Some(trace) if trace.is_active() => println!("tracing, {} lines", trace.lines()),
+                Some(trace) => println!("trace waiting or stopped, {} lines", trace.lines()),
+                None => println!("not tracing"),
+            },
+            Some("off") => {
+                if let Some(mut trace) = self.debugger.set_trace(None) {
+                    trace.flush();
+                    println!("{} lines traced", trace.lines());
+                    if let Some(e) = trace.error() {
+                        return Err(e.to_string());
+                    }
+                }
+            }
+            Some("ring") => {
+                let count = self.count(args, 1, 1000)? as usize;
+                let trace = TraceLogger::ring(count, format(args.get(2))?);
+                self.debugger.set_trace(Some(trace));
+            }
+            Some("dump") => {
+                let path = unquote(args.get(1).ok_or("trace dump needs a file name")?);
+                let trace = self.debugger.trace().ok_or("not tracing")?;
+                trace
+                    .save_recent(Path::new(path))
+                    .map_err(|e| e.to_string())?;
+            }
+            Some(which @ ("from" | "to")) => {
+                let condition = match args.get(1).copied() {
+                    Some("frame") => {
+                        let frame = args.get(2).ok_or("trace frame needs a number")?;
+                        TraceCondition::Frame(
+                            frame
+                                .parse()
+                                .map_err(|_| format!("bad frame '{}'", frame))?,
+                        )
+                    }
+                    Some(addr) => TraceCondition::Address(self.value(addr)?),
+                    None => return Err(format!("trace {} needs an address or frame", which)),
+                };
+                let trace = self.debugger.trace_mut().ok_or("not tracing")?;
+                if which == "from" {
+                    trace.start_when(condition);
+                } else {
+                    trace.stop_when(condition);
+                }
+            }
+            Some(path) => {
+                let path = unquote(path);
+                let trace = TraceLogger::to_file(Path::new(path), format(args.get(1))?)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                self.debugger.set_trace(Some(trace));
+            }
+        }
+        Ok(())
+    }
+
+    fn code_data_log(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            None => {
+                let cdl = self.cpu.bus.cdl.as_ref().ok_or("not logging")?;
+                let (code, data, unknown) = cdl.coverage();
+                println!(
+                    "{} bytes code, {} data, {} unused of {}",
+                    code,
+                    data,
+                    unknown,
+                    cdl.prg_size()
+                );
+            }
+            Some("on") => {
+                // Sized for the cartridge unless told otherwise
+                let cdl = match &self.cpu.bus.cartridge {
+                    Some(cartridge) if args.len() == 1 => CodeDataLogger::for_cartridge(cartridge),
+                    _ => {
+                        let prg = self.count(args, 1, 0x8000)? as usize;
+                        let chr = self.count(args, 2, 0x2000)? as usize;
+                        CodeDataLogger::new(prg, chr)
+                    }
+                };
+                self.cpu.bus.cdl = Some(cdl);
+            }
+            Some("off") => self.cpu.bus.cdl = None,
+            Some(which @ ("save" | "load")) => {
+                let path = unquote(
+                    args.get(1)
+                        .ok_or(format!("cdl {} needs a file name", which))?,
+                );
+                let cdl = self.cpu.bus.cdl.as_mut().ok_or("not logging")?;
+                if which == "save" {
+                    cdl.save(Path::new(path))?;
+                } else {
+                    cdl.load(Path::new(path))?;
+                }
+            }
+            Some(arg) => return Err(format!("unknown cdl command '{}'", arg)),
+        }
+        Ok(())
+    }
+
+    fn backtrace(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            None => {
+                for line in self
+                    .debugger
+                    .call_stack()
+                    .backtrace(self.cpu.pc, &self.symbols)
+                {
+                    println!("{}", line);
+                }
+            }
+            Some("problems") => {
+                for problem in self.debugger.call_stack().problems() {
+                    println!("{}", problem.describe(&self.symbols));
+                }
+            }
+            Some("break") => match args.get(1).copied() {
+                Some("on") => self.debugger.set_stop_on_stack_problems(true),
+                Some("off") => self.debugger.set_stop_on_stack_problems(false),
+                _ => return Err("bt break needs on or off".to_string()),
+            },
+            Some(arg) => return Err(format!("unknown bt command '{}'", arg)),
+        }
+        Ok(())
+    }
+
+    fn rewind(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            None => match self.debugger.rewind() {
+                Some(rewind) => println!("{} frames, {} bytes", rewind.len(), rewind.bytes()),
+                None => println!("rewind is off"),
+            },
+            Some("on") => {
+                let seconds = self.count(args, 1, 60)? as usize;
+                self.debugger.set_rewind(Some(Rewind::new(
+                    seconds * 60,
+                    rewind::DEFAULT_KEYFRAME_INTERVAL,
+                    rewind::DEFAULT_BUDGET,
+                )));
+            }
+            Some("off") => self.debugger.set_rewind(None),
+            Some("back") => {
+                for _ in 0..self.count(args, 1, 1)? {
+                    if !self.debugger.step_back(&mut self.cpu)? {
+                        println!("nothing left to rewind");
+                        break;
+                    }
+                }
+                self.show_current();
+            }
+            Some(arg) => return Err(format!("unknown rewind command '{}'", arg)),
+        }
+        Ok(())
+    }
+
+    fn pad(&mut self, args: &[&str]) -> Result<(), String> {
+        let index = match args.first().copied() {
+            Some("1") => 0,
+            Some("2") => 1,
+            _ => return Err("pad needs 1 or 2".to_string()),
+        };
+        if let Some(text) = args.get(1) {
+            let mut buttons = 0;
+            for c in text.chars().filter(|c| *c != '.') {
+                let slot = "RLDUTSBA"
+                    .find(c.to_ascii_uppercase())
+                    .ok_or(format!("unknown button '{}'", c))?;
+                buttons |= 0x80 >> slot;
+            }
+            self.cpu.bus.controllers[index].buttons = buttons;
+        }
+        println!(
+            "pad {}: {}",
+            index + 1,
+            buttons_to_fm2(self.cpu.bus.controllers[index].buttons)
+        );
+        Ok(())
+    }
+
+    fn movie(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            None => match self.debugger.movie() {
+                Some(movie) => println!(
+                    "{:?}, frame {} of {}, {} rerecords",
+                    movie.mode(),
+                    movie.frame(),
+                    movie.movie.frames.len(),
+                    movie.movie.rerecords
+                ),
+                None => println!("no movie"),
+            },
+            Some("record") => {
+                let path = unquote(args.get(1).ok_or("movie record needs a file name")?);
+                let from_state = match args.get(2).copied() {
+                    Some("state") => true,
+                    Some(arg) => return Err(format!("unknown movie start '{}'", arg)),
+                    None => false,
+                };
+                self.stop_movie()?;
+                let session = MovieSession::record(&mut self.cpu, from_state);
+                self.debugger.set_movie(Some(session));
+                self.debugger.clear_call_stack();
+                self.movie_path = Some(path.to_string());
+                self.show_current();
+            }
+            Some("play") => {
+                let path = unquote(args.get(1).ok_or("movie play needs a file name")?);
+                let movie = Movie::load(Path::new(path))?;
+                self.stop_movie()?;
+                let session = MovieSession::play(movie, &mut self.cpu)?;
+                self.debugger.set_movie(Some(session));
+                self.debugger.clear_call_stack();
+                self.show_current();
+            }
+            Some("stop") => self.stop_movie()?,
+            Some(arg) => return Err(format!("unknown movie command '{}'", arg)),
+        }
+        Ok(())
+    }
+
+    fn set_palette(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            None => self.palette = Palette::new(),
+            Some("ntsc") => {
+                let mut params = NtscParams::new();
+                let knobs = [
+                    &mut params.hue,
+                    &mut params.saturation,
+                    &mut params.contrast,
+                    &mut params.brightness,
+                    &mut params.gamma,
+                ];
+                for (knob, arg) in knobs.into_iter().zip(&args[1..]) {
+                    *knob = arg.parse().map_err(|_| format!("bad number '{}'", arg))?;
+                }
+                self.palette = ntsc::generate_palette(&params);
+            }
+            Some("save") => {
+                let path = unquote(args.get(1).ok_or("palette save needs a file name")?);
+                std::fs::write(path, self.palette.to_pal()).map_err(|e| e.to_string())?;
+            }
+            Some(path) => self.palette = Palette::load(Path::new(unquote(path)))?,
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            None => match self.debugger.video() {
+                Some(video) => println!("{} frames recorded", video.frames()),
+                None => println!("not recording"),
+            },
+            Some("stop") => self.stop_video()?,
+            Some(path) => {
+                let path = Path::new(unquote(path));
+                let overscan = match args.get(1).copied() {
+                    Some("crop") => Overscan::NTSC,
+                    Some(arg) => return Err(format!("unknown record option '{}'", arg)),
+                    None => Overscan::NONE,
+                };
+                self.stop_video()?;
+                let wav = path.with_extension("wav");
+                let video = VideoRecorder::create(
+                    path,
+                    self.palette.clone(),
+                    overscan,
+                    Some((&wav, audio::DEFAULT_RATE)),
+                )
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+                self.debugger.set_video(Some(video));
+            }
+        }
+        Ok(())
+    }
+
+    fn save_cheats(&mut self) -> Result<(), String> {
+        let text = self.debugger.cheats().to_cht();
+        match &self.cheats_path {
+            Some(path) if text != self.saved_cheats => {
+                self.debugger.cheats().save(path)?;
+                println!("saved cheats to {}", path.display());
+                self.saved_cheats = text;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn save_battery(&mut self) -> Result<(), String> {
+        if let Some(battery) = self.debugger.battery_mut() {
+            if let Some(e) = battery.error() {
+                println!("error: {}", e);
+            }
+            if battery.flush(&self.cpu.bus)? {
+                println!("saved {}", battery.path().display());
+            }
+        }
+        Ok(())
+    }
+
+    fn stop_video(&mut self) -> Result<(), String> {
+        if let Some(video) = self.debugger.set_video(None) {
+            let frames = video.finish(&mut self.cpu).map_err(|e| e.to_string())?;
+            println!("{} frames recorded", frames);
+        }
+        Ok(())
+    }
+
+    // Ends the current movie, writing it out if it was being recorded
+    fn stop_movie(&mut self) -> Result<(), String> {
+        let session = self.debugger.set_movie(None);
+        if let (Some(session), Some(path)) = (session, self.movie_path.take()) {
+            if session.mode() == MovieMode::Recording {
+                session.movie.save(Path::new(&path))?;
+                println!("{} frames written to {}", session.movie.frames.len(), path);
+            }
+        }
+        Ok(())
+    }
+
+    fn profile(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first().copied() {
+            Some("on") => {
+                self.debugger.set_profiler(Some(Profiler::new()));
+            }
+            Some("off") => {
+                self.debugger.set_profiler(None);
+            }
+            Some("flame") => {
+                let path = unquote(args.get(1).ok_or("prof flame needs a file name")?);
+                let profiler = self.debugger.profiler().ok_or("not profiling")?;
+                std::fs::write(path, profiler.collapsed(&self.symbols))
+                    .map_err(|e| e.to_string())?;
+            }
+            path => {
+                let profiler = self.debugger.profiler().ok_or("not profiling")?;
+                let report = profiler.report(&self.symbols);
+                match path {
+                    Some(path) => {
+                        std::fs::write(unquote(path), report).map_err(|e| e.to_string())?
+                    }
+                    None => print!("{}", report),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn assemble(&mut self, rest: &str) -> Result<(), String> {
+        let (addr, instruction) = split_first(rest);
+        let addr = self.value(addr)?;
+        let bytes = Assembler::new().assemble_at(addr, instruction, self.symbols.names())?;
+        for (i, b) in bytes.iter().enumerate() {
+            self.cpu.bus.poke(addr.wrapping_add(i as u16), *b);
+        }
+        println!("{}", self.line_at(addr));
+        self.next_dis = addr.wrapping_add(bytes.len() as u16);
+        Ok(())
+    }
+
+    fn breakpoint(&mut self, rest: &str) -> Result<(), String> {
+        if rest.trim().is_empty() {
+            for b in self.debugger.breakpoints() {
+                let condition = b.condition.as_ref().map(|c| format!(" if {}", c.source()));
+                println!(
+                    "#{} {}{}{}",
+                    b.id,
+                    self.describe(b.addr),
+                    condition.unwrap_or_default(),
+                    if b.enabled { "" } else { " (disabled)" }
+                );
+            }
+            return Ok(());
+        }
+
+        let (addr, condition) = match rest.find(" if ") {
+            Some(i) => (
+                &rest[..i],
+                Some(Expression::parse_with_symbols(
+                    &rest[i + 4..],
+                    &self.symbols,
+                )?),
+            ),
+            None => (rest, None),
+        };
+        let addr = self.value(addr.trim())?;
+        let id = self.debugger.add_breakpoint(addr, condition);
+        println!("breakpoint #{} at {}", id, self.describe(addr));
+        Ok(())
+    }
+
+    fn watchpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let Some(range) = args.first() else {
+            for w in self.debugger.watchpoints() {
+                println!(
+                    "#{} ${:04X}-${:04X} {:?}{}",
+                    w.id,
+                    w.start,
+                    w.end,
+                    w.kind,
+                    if w.enabled { "" } else { " (disabled)" }
+                );
+            }
+            return Ok(());
+        };
+
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (self.value(start)?, self.value(end)?),
+            None => {
+                let addr = self.value(range)?;
+                (addr, addr)
+            }
+        };
+        for c in args.get(1).unwrap_or(&"rw").chars() {
+            let kind = match c.to_ascii_lowercase() {
+                'r' => AccessKind::Read,
+                'w' => AccessKind::Write,
+                'x' => AccessKind::Execute,
+                _ => return Err(format!("unknown access '{}', use r, w or x", c)),
+            };
+            let id = self.debugger.add_watchpoint(start, end, kind);
+            println!("watchpoint #{} ${:04X}-${:04X} {:?}", id, start, end, kind);
+        }
+        Ok(())
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // HELPERS
+
+    // Keeps the debugger going until it stops, one frame worth at a time.
+    // A run that goes on past the limit is paused, so a breakpoint that is
+    // never reached does not hang a script.
+    fn wait(&mut self) -> Option<StopReason> {
+        let mut frames = 0;
+        loop {
+            if let Some(reason) = self.debugger.update(&mut self.cpu, CPU_CYCLES_PER_FRAME) {
+                return Some(reason);
+            }
+            frames += 1;
+            if self.limit != 0 && frames >= self.limit {
+                self.debugger.pause();
+                println!("still running after {} frames, paused (see limit)", frames);
+                return None;
+            }
+        }
+    }
+
+    fn run(&mut self) {
+        if let Some(reason) = self.wait() {
+            self.report(reason);
+        }
+        self.show_current();
+    }
+
+    fn report(&self, reason: StopReason) {
+        match reason {
+            StopReason::Step | StopReason::Reached(_) | StopReason::Returned => (),
+            StopReason::Breakpoint(id) => println!("breakpoint #{} hit", id),
+            StopReason::Watchpoint { id, addr, kind } => {
+                println!("watchpoint #{}: {:?} {}", id, kind, self.describe(addr))
+            }
+            StopReason::Nmi => println!("entered NMI handler"),
+            StopReason::Frame => println!("frame {}", self.cpu.clock_count / CPU_CYCLES_PER_FRAME),
+            StopReason::Stack(problem) => println!("{}", problem.describe(&self.symbols)),
+        }
+    }
+
+    fn show_registers(&self) {
+        let names = "NV-BDIZC";
+        let flags: String = names
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                if self.cpu.sr & (0x80 >> i) != 0 {
+                    c
+                } else {
+                    c.to_ascii_lowercase()
+                }
+            })
+            .collect();
+        println!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {} CYC:{}",
+            self.cpu.pc,
+            self.cpu.a,
+            self.cpu.x,
+            self.cpu.y,
+            self.cpu.sp,
+            self.cpu.sr,
+            flags,
+            self.cpu.clock_count
+        );
+    }
+
+    fn show_current(&mut self) {
+        self.show_registers();
+        println!("{}", self.line_at(self.cpu.pc));
+        self.next_dis = self.cpu.pc;
+    }
+
+    // Disassembled line with symbol names, and the source line it came from
+    // when debug info was loaded
+    fn line_at(&self, addr: u16) -> String {
+        let line = self.symbols.format_instruction(&self.cpu.decode(addr));
+        match self.symbols.source(addr) {
+            Some(source) => format!("{:<32} ; {}:{}", line, source.file, source.line),
+            None => line,
+        }
+    }
+
+    // $xxxx followed by the name of the address if it has one
+    fn describe(&self, addr: u16) -> String {
+        match self.symbols.label(addr) {
+            Some(name) => format!("${:04X} ({})", addr, name),
+            None => format!("${:04X}", addr),
+        }
+    }
+
+    // An address in a space other than the cpu's, which can be past $FFFF
+    fn offset(&self, text: &str) -> Result<usize, String> {
+        let expression = Expression::parse_with_symbols(text, &self.symbols)?;
+        usize::try_from(expression.evaluate(&self.cpu))
+            .map_err(|_| format!("bad address '{}'", text))
+    }
+
+    fn value(&self, text: &str) -> Result<u16, String> {
+        let expression = Expression::parse_with_symbols(text, &self.symbols)?;
+        Ok((expression.evaluate(&self.cpu) & 0xFFFF) as u16)
+    }
+
+    fn id(&self, args: &[&str]) -> Result<u32, String> {
+        let arg = args.first().ok_or("missing id")?;
+        arg.trim_start_matches('#')
+            .parse()
+            .map_err(|_| format!("bad id '{}'", arg))
+    }
+
+    fn count(&self, args: &[&str], index: usize, default: u16) -> Result<u16, String> {
+        match args.get(index) {
+            Some(arg) => self.value(arg),
+            None => Ok(default),
+        }
+    }
+
+    fn load(&mut self, path: &str, addr: u16) -> Result<usize, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        for (i, b) in data.iter().enumerate() {
+            self.cpu.bus.poke(addr.wrapping_add(i as u16), *b);
+        }
+        Ok(data.len())
+    }
+}
+
+// Takes a space name off the front of the arguments, the cpu's when there
+// is none
+fn space_arg<'a>(args: &'a [&'a str]) -> (AddressSpace, &'a [&'a str]) {
+    match args.first().and_then(|arg| AddressSpace::by_name(arg)) {
+        Some(space) => (space, &args[1..]),
+        None => (AddressSpace::Cpu, args),
+    }
+}
+
+fn split_first(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+fn unquote(text: &str) -> &str {
+    text.trim_matches('"')
+}
+
+// Loads the program named on the command line, if any
+fn start(monitor: &mut Monitor, args: &[String]) -> Result<(), String> {
+    // monitor [file [load address]], an iNES image starts from its reset
+    // vector
+    if let Some(path) = args
+        .get(1)
+        .filter(|p| p.to_ascii_lowercase().ends_with(".nes"))
+    {
+        let cartridge = Cartridge::load_file(Path::new(path))?;
+        let mut battery = BatterySave::for_cartridge(Path::new(path), &cartridge);
+        cartridge.insert(&mut monitor.cpu.bus);
+        monitor.cpu.power_on();
+        if let Some(battery) = battery.as_mut() {
+            match battery.load(&mut monitor.cpu.bus) {
+                Ok(true) => println!("loaded {}", battery.path().display()),
+                Ok(false) => {}
+                Err(e) => println!("error: {}", e),
+            }
+        }
+        monitor.debugger.set_battery(battery);
+
+        let cheats_path = cheats::path_for_rom(Path::new(path));
+        if cheats_path.exists() {
+            match CheatList::load(&cheats_path) {
+                Ok(list) => *monitor.debugger.cheats_mut() = list,
+                Err(e) => println!("error: {}", e),
+            }
+        }
+        monitor.saved_cheats = monitor.debugger.cheats().to_cht();
+        monitor.cheats_path = Some(cheats_path);
+    } else if let Some(path) = args.get(1) {
+        let addr = match args.get(2) {
+            Some(arg) => monitor.value(arg)?,
+            None => 0x8000,
+        };
+        monitor.load(path, addr)?;
+        monitor.cpu.reset();
+        monitor.cpu.pc = addr;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut monitor = Monitor::new();
+    if let Err(e) = start(&mut monitor, &args) {
+        eprintln!("error: {}", e);
+        std::process::exit(2);
+    }
+
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut failed = false;
+    let mut line = String::new();
+    loop {
+        if interactive {
+            print!("${:04X}> ", monitor.cpu.pc);
+            io::stdout().flush().expect("");
+        }
+        line.clear();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        if let Err(e) = monitor.execute(&line) {
+            println!("error: {}", e);
+            failed = true;
+        }
+        if monitor.quit {
+            break;
+        }
+    }
+    // Recordings in progress are not thrown away on the way out, nor are
+    // changes to the game's cheats or battery save. One failing does not
+    // stop the others.
+    let results = [
+        monitor.stop_movie(),
+        monitor.stop_video(),
+        monitor.save_cheats(),
+        monitor.save_battery(),
+    ];
+    for e in results.into_iter().filter_map(Result::err) {
+        println!("error: {}", e);
+        failed = true;
+    }
+
+    // Scripts get a failing exit status if any command went wrong
+    if failed && !interactive {
+        std::process::exit(1);
+    }
+}
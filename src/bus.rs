@@ -1,6 +1,26 @@
-pub(crate) struct Bus {
+use crate::audio::AudioOutput;
+use crate::cartridge::Cartridge;
+use crate::cheats::RomPatch;
+use crate::code_data_logger::CodeDataLogger;
+use crate::controller::Controller;
+use crate::frame_buffer::FrameBuffer;
+
+pub struct Bus {
     // pub(crate) cpu: Cpu6502,
     pub ram: [u8; 64 * 1024],
+    // The game plugged in, when it came from a ROM file
+    pub cartridge: Option<Cartridge>,
+    // Records how the cartridge is used when set
+    pub cdl: Option<CodeDataLogger>,
+    // Pads on $4016 and $4017
+    pub controllers: [Controller; 2],
+    // The last picture the PPU finished
+    pub screen: FrameBuffer,
+    // Sound out, a level every CPU cycle, when something is listening
+    pub audio: Option<AudioOutput>,
+    // Game Genie style substitutions for reads, put here by the debugger's
+    // cheat list
+    pub rom_patches: Vec<RomPatch>,
 }
 
 impl Bus {
@@ -8,17 +28,58 @@ impl Bus {
         // Self { cpu: Cpu6502::new(), ram: [0; 64 * 1024] }
         Self {
             ram: [0; 64 * 1024],
+            cartridge: None,
+            cdl: None,
+            controllers: [Controller::new(), Controller::new()],
+            screen: FrameBuffer::new(),
+            audio: None,
+            rom_patches: Vec::new(),
         }
     }
 
-    pub(crate) fn write(&mut self, addr: &u16, data: &u8) {
+    pub fn write(&mut self, addr: &u16, data: &u8) {
+        // One strobe latches both pads
+        if *addr == 0x4016 {
+            for pad in self.controllers.iter_mut() {
+                pad.write(*data);
+            }
+        }
+
+        // A cartridge's PRG is ROM, writes there go to the mapper (of which
+        // there are none yet) and never change what is read back
+        if *addr >= 0x8000 && self.cartridge.is_some() {
+            return;
+        }
+
         if addr >= &0x000 && addr <= &0xFFFF {
             self.ram[*addr as usize] = data.clone()
         }
     }
 
-    pub(crate) fn read(&self, addr: &u16, mut b_read_only: bool) -> u8 {
-        b_read_only = false;
+    // Writes from the debugger: straight into memory, ROM included, with
+    // nothing else on the bus seeing them
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+    }
+
+    // Reads with b_read_only set are peeks from the debugger views, they
+    // must not disturb anything that changes when it is read
+    pub fn read(&self, addr: &u16, b_read_only: bool) -> u8 {
+        if *addr == 0x4016 || *addr == 0x4017 {
+            let pad = &self.controllers[(*addr - 0x4016) as usize];
+            return if b_read_only { pad.peek() } else { pad.read() };
+        }
+
+        if !self.rom_patches.is_empty() {
+            let held = self.ram[*addr as usize];
+            let patch = self
+                .rom_patches
+                .iter()
+                .find(|p| p.addr == *addr && p.compare.is_none_or(|c| c == held));
+            if let Some(patch) = patch {
+                return patch.value;
+            }
+        }
 
         if addr >= &0x000 && addr <= &0xFFFF {
             return self.ram[*addr as usize];
@@ -27,3 +88,28 @@ impl Bus {
         return 0x0000;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cartridge_rom_cannot_be_written() {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.extend((0..0x4000).map(|i| i as u8));
+        rom.extend([0; 0x2000]);
+        let mut bus = Bus::new();
+        Cartridge::from_ines(&rom).unwrap().insert(&mut bus);
+
+        bus.write(&0x8005, &0xEA);
+        bus.write(&0xFFFF, &0xEA);
+        bus.write(&0x6005, &0xEA);
+        assert_eq!(bus.read(&0x8005, false), 0x05);
+        assert_eq!(bus.read(&0xFFFF, false), 0xFF);
+        assert_eq!(bus.read(&0x6005, false), 0xEA);
+
+        // The debugger still can
+        bus.poke(0x8005, 0xEA);
+        assert_eq!(bus.read(&0x8005, false), 0xEA);
+    }
+}
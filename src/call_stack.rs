@@ -0,0 +1,379 @@
+use crate::cpu_6502::Cpu6502;
+use crate::debugger::read_vector;
+use crate::symbols::SymbolTable;
+
+// Shadow copy of the 6502 call stack. The real stack is only bytes, so the
+// debugger keeps its own frame for every JSR, BRK and interrupt, pops it
+// again on RTS/RTI, and reports when the program does something the
+// frames do not agree with.
+
+// How many problems are kept, the oldest are dropped first
+const MAX_PROBLEMS: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameKind {
+    Call,
+    Brk,
+    Irq,
+    Nmi,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CallFrame {
+    pub kind: FrameKind,
+    // Address of the JSR or BRK, or of the instruction that was interrupted
+    pub caller: u16,
+    // Start of the routine or handler
+    pub target: u16,
+    // Where RTS/RTI should come back to
+    pub return_addr: u16,
+    // Stack pointer before anything was pushed
+    pub sp: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StackProblem {
+    // RTS or RTI at addr left the frame on top of the shadow stack but went
+    // to actual, or an RTS left an interrupt handler (or RTI a subroutine)
+    ReturnMismatch {
+        addr: u16,
+        expected: u16,
+        actual: u16,
+    },
+    // A push at addr wrapped the stack pointer below $0100
+    Overflow(u16),
+    // A pull at addr wrapped the stack pointer above $01FF
+    Underflow(u16),
+}
+
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+    problems: Vec<StackProblem>,
+    // The instruction being executed, set by before()
+    pc: u16,
+    sp: u8,
+    opcode: u8,
+    // Where the last instruction left pc and sp, a difference at the next
+    // before() means an interrupt came in between
+    next_pc: Option<u16>,
+    next_sp: u8,
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        CallStack::new()
+    }
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            problems: Vec::new(),
+            pc: 0,
+            sp: 0,
+            opcode: 0,
+            next_pc: None,
+            next_sp: 0,
+        }
+    }
+
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn problems(&self) -> &[StackProblem] {
+        &self.problems
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.problems.clear();
+        self.next_pc = None;
+    }
+
+    // Called before the instruction at pc runs
+    pub fn before(&mut self, cpu: &Cpu6502) {
+        if let Some(next_pc) = self.next_pc {
+            if cpu.sp == self.next_sp.wrapping_sub(3) && cpu.pc != next_pc {
+                let kind = if cpu.pc == read_vector(cpu, 0xFFFA) {
+                    Some(FrameKind::Nmi)
+                } else if cpu.pc == read_vector(cpu, 0xFFFE) {
+                    Some(FrameKind::Irq)
+                } else {
+                    None
+                };
+                if let Some(kind) = kind {
+                    self.frames.push(CallFrame {
+                        kind,
+                        caller: next_pc,
+                        target: cpu.pc,
+                        return_addr: next_pc,
+                        sp: self.next_sp,
+                    });
+                }
+            }
+        }
+
+        // Anything that moved the stack pointer back above a frame (TXS,
+        // returning through a pushed address) has left it. Pulling the
+        // return address off is not enough, the RTS after it is checked.
+        while self.frames.last().is_some_and(|f| f.sp < cpu.sp) {
+            self.frames.pop();
+        }
+
+        self.pc = cpu.pc;
+        self.sp = cpu.sp;
+        self.opcode = cpu.bus.read(&cpu.pc, true);
+    }
+
+    // Called once the instruction has finished. Returns the problem it
+    // caused, if any.
+    pub fn after(&mut self, cpu: &Cpu6502) -> Option<StackProblem> {
+        let name = cpu.instruction_name(self.opcode);
+        let mut problem = None;
+
+        match name {
+            "JSR" | "BRK" => {
+                self.frames.push(CallFrame {
+                    kind: if name == "JSR" {
+                        FrameKind::Call
+                    } else {
+                        FrameKind::Brk
+                    },
+                    caller: self.pc,
+                    target: cpu.pc,
+                    return_addr: self.pc.wrapping_add(if name == "JSR" { 3 } else { 2 }),
+                    sp: self.sp,
+                });
+            }
+            "RTS" | "RTI" => {
+                // A return that leaves the stack pointer below the frame is
+                // a jump through pushed addresses, not the end of the call
+                let returns = name == "RTS";
+                if let Some(frame) = self.frames.last().filter(|f| f.sp <= cpu.sp) {
+                    let kind_matches = (frame.kind == FrameKind::Call) == returns;
+                    if frame.return_addr != cpu.pc || !kind_matches {
+                        problem = Some(StackProblem::ReturnMismatch {
+                            addr: self.pc,
+                            expected: frame.return_addr,
+                            actual: cpu.pc,
+                        });
+                    }
+                }
+                self.unwind(cpu.sp);
+            }
+            _ => (),
+        }
+
+        let pushed = matches!(name, "PHA" | "PHP" | "JSR" | "BRK");
+        let pulled = matches!(name, "PLA" | "PLP" | "RTS" | "RTI");
+        if pushed && cpu.sp > self.sp {
+            problem = problem.or(Some(StackProblem::Overflow(self.pc)));
+        } else if pulled && cpu.sp < self.sp {
+            problem = problem.or(Some(StackProblem::Underflow(self.pc)));
+        }
+
+        if let Some(problem) = problem {
+            if self.problems.len() == MAX_PROBLEMS {
+                self.problems.remove(0);
+            }
+            self.problems.push(problem);
+        }
+
+        self.next_pc = Some(cpu.pc);
+        self.next_sp = cpu.sp;
+        problem
+    }
+
+    fn unwind(&mut self, sp: u8) {
+        while self.frames.last().is_some_and(|f| f.sp <= sp) {
+            self.frames.pop();
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // DISPLAY
+
+    // Innermost first, like gdb: where each routine is now and which
+    // routine it is in
+    pub fn backtrace(&self, pc: u16, symbols: &SymbolTable) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        let mut location = pc;
+        for frame in self.frames.iter().rev() {
+            let kind = match frame.kind {
+                FrameKind::Call => "",
+                FrameKind::Brk => " [BRK]",
+                FrameKind::Irq => " [IRQ]",
+                FrameKind::Nmi => " [NMI]",
+            };
+            lines.push(format!(
+                "#{} ${} in {}{}",
+                lines.len(),
+                Cpu6502::hex(location as u32, 4),
+                symbols.name_or_hex(frame.target),
+                kind
+            ));
+            location = frame.return_addr;
+        }
+        lines.push(format!(
+            "#{} ${}",
+            lines.len(),
+            Cpu6502::hex(location as u32, 4)
+        ));
+        lines
+    }
+
+    // Outermost first on one line, "RESET > update > helper"
+    pub fn summary(&self, symbols: &SymbolTable) -> String {
+        let names: Vec<String> = self
+            .frames
+            .iter()
+            .map(|f| symbols.name_or_hex(f.target))
+            .collect();
+        names.join(" > ")
+    }
+}
+
+impl StackProblem {
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        match self {
+            StackProblem::ReturnMismatch {
+                addr,
+                expected,
+                actual,
+            } => format!(
+                "return at {} went to {}, expected {}",
+                symbols.name_or_hex(*addr),
+                symbols.name_or_hex(*actual),
+                symbols.name_or_hex(*expected)
+            ),
+            StackProblem::Overflow(addr) => {
+                format!("stack overflow at {}", symbols.name_or_hex(*addr))
+            }
+            StackProblem::Underflow(addr) => {
+                format!("stack underflow at {}", symbols.name_or_hex(*addr))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu(code: &[(u16, &[u8])]) -> Cpu6502 {
+        let mut cpu = Cpu6502::new();
+        for (addr, bytes) in code {
+            for (i, b) in bytes.iter().enumerate() {
+                cpu.bus.write(&(addr + i as u16), b);
+            }
+        }
+        cpu.bus.write(&0xFFFE, &0x00);
+        cpu.bus.write(&0xFFFF, &0x90);
+        cpu.reset();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+        cpu.pc = 0x8000;
+        cpu
+    }
+
+    // Runs one instruction the way the debugger does
+    fn step(cpu: &mut Cpu6502, stack: &mut CallStack) -> Option<StackProblem> {
+        stack.before(cpu);
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+        stack.after(cpu)
+    }
+
+    #[test]
+    fn stack_wraps_are_reported() {
+        let program: &[u8] = &[
+            0xA2, 0x00, // LDX #$00
+            0x9A, // TXS
+            0x48, // PHA
+            0x68, // PLA
+            0x68, // PLA
+        ];
+        let mut cpu = cpu(&[(0x8000, program)]);
+        let mut stack = CallStack::new();
+        assert_eq!(step(&mut cpu, &mut stack), None);
+        assert_eq!(step(&mut cpu, &mut stack), None);
+        assert_eq!(
+            step(&mut cpu, &mut stack),
+            Some(StackProblem::Overflow(0x8003))
+        );
+        assert_eq!(cpu.sp, 0xFF);
+        assert_eq!(
+            step(&mut cpu, &mut stack),
+            Some(StackProblem::Underflow(0x8004))
+        );
+        assert_eq!(cpu.sp, 0x00);
+        assert_eq!(step(&mut cpu, &mut stack), None);
+        assert_eq!(
+            stack.problems(),
+            [
+                StackProblem::Overflow(0x8003),
+                StackProblem::Underflow(0x8004)
+            ]
+        );
+    }
+
+    #[test]
+    fn brk_and_jsr_come_back_where_expected() {
+        let program: &[u8] = &[
+            0x00, 0x00, // BRK
+            0x20, 0x00, 0x91, // JSR $9100
+            0xEA, // NOP
+        ];
+        let handler: &[u8] = &[0x40]; // RTI
+        let routine: &[u8] = &[0x60]; // RTS
+        let mut cpu = cpu(&[(0x8000, program), (0x9000, handler), (0x9100, routine)]);
+        let mut stack = CallStack::new();
+
+        assert_eq!(step(&mut cpu, &mut stack), None);
+        assert_eq!(cpu.pc, 0x9000);
+        let frame = stack.frames()[0];
+        assert_eq!((frame.kind, frame.return_addr), (FrameKind::Brk, 0x8002));
+        assert_eq!(step(&mut cpu, &mut stack), None);
+        assert_eq!(cpu.pc, 0x8002);
+        assert!(stack.frames().is_empty());
+
+        assert_eq!(step(&mut cpu, &mut stack), None);
+        assert_eq!(stack.frames()[0].return_addr, 0x8005);
+        assert_eq!(step(&mut cpu, &mut stack), None);
+        assert_eq!(cpu.pc, 0x8005);
+        assert!(stack.frames().is_empty());
+        assert!(stack.problems().is_empty());
+    }
+
+    #[test]
+    fn a_changed_return_address_is_reported() {
+        let program: &[u8] = &[0x20, 0x00, 0x91]; // JSR $9100
+        let routine: &[u8] = &[
+            0x68, // PLA
+            0x68, // PLA
+            0xA9, 0x12, // LDA #$12
+            0x48, // PHA
+            0xA9, 0x34, // LDA #$34
+            0x48, // PHA
+            0x60, // RTS
+        ];
+        let mut cpu = cpu(&[(0x8000, program), (0x9100, routine)]);
+        let mut stack = CallStack::new();
+        for _ in 0..7 {
+            assert_eq!(step(&mut cpu, &mut stack), None);
+        }
+        assert_eq!(
+            step(&mut cpu, &mut stack),
+            Some(StackProblem::ReturnMismatch {
+                addr: 0x9108,
+                expected: 0x8003,
+                actual: 0x1235,
+            })
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::bus::Bus;
+use std::path::Path;
+
+// A game in the iNES format (NES 2.0 headers are read as far as iNES
+// goes):
+//
+//     "NES" $1A  prg:u8 (16K units)  chr:u8 (8K units)  flags6  flags7  ...
+//     header (16 bytes), trainer (512 bytes, optional), PRG, CHR
+//
+// flags6 holds the mirroring, battery and trainer bits and the low nibble
+// of the mapper number, flags7 the high nibble.
+
+pub struct Flags6;
+impl Flags6 {
+    pub const VERTICAL: u8 = 1 << 0;
+    pub const BATTERY: u8 = 1 << 1;
+    pub const TRAINER: u8 = 1 << 2;
+    pub const FOUR_SCREEN: u8 = 1 << 3;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cartridge {
+    pub prg: Vec<u8>,
+    // Empty when the board has CHR RAM instead
+    pub chr: Vec<u8>,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    // The RAM at $6000-$7FFF keeps its contents with the power off
+    pub battery: bool,
+    // Loaded to $7000 before the game starts, for old copier hacks
+    pub trainer: Option<Vec<u8>>,
+}
+
+impl Cartridge {
+    pub fn from_ines(data: &[u8]) -> Result<Self, String> {
+        if data.len() < 16 || &data[0..4] != b"NES\x1A" {
+            return Err("not an iNES file".to_string());
+        }
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let prg_size = data[4] as usize * 0x4000;
+        let chr_size = data[5] as usize * 0x2000;
+
+        // Old dumping tools wrote their name over bytes 7-15, whose mapper
+        // nibble is then garbage
+        let dirty = flags7 & 0x0C == 0 && data[12..16].iter().any(|b| *b != 0);
+        let mapper_hi = if dirty { 0 } else { flags7 & 0xF0 };
+        let mapper = mapper_hi | (flags6 >> 4);
+
+        let mut offset = 16;
+        let trainer = if flags6 & Flags6::TRAINER != 0 {
+            let trainer = data
+                .get(offset..offset + 512)
+                .ok_or("trainer is cut short")?;
+            offset += 512;
+            Some(trainer.to_vec())
+        } else {
+            None
+        };
+        let prg = data
+            .get(offset..offset + prg_size)
+            .ok_or("PRG ROM is cut short")?
+            .to_vec();
+        offset += prg_size;
+        let chr = data
+            .get(offset..offset + chr_size)
+            .ok_or("CHR ROM is cut short")?
+            .to_vec();
+        if prg.is_empty() {
+            return Err("no PRG ROM".to_string());
+        }
+
+        let mirroring = if flags6 & Flags6::FOUR_SCREEN != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & Flags6::VERTICAL != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        Ok(Self {
+            prg,
+            chr,
+            mapper,
+            mirroring,
+            battery: flags6 & Flags6::BATTERY != 0,
+            trainer,
+        })
+    }
+
+    pub fn load_file(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Cartridge::from_ines(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Copies the game into the bus memory. The bus is flat memory with no
+    // bank switching yet, so only NROM (mapper 0) is complete: other
+    // mappers get their first and last 16K of PRG, which is where they
+    // start up. 16K games are mirrored into both halves.
+    pub fn insert(self, bus: &mut Bus) {
+        let high = self.high_bank();
+        let low = &self.prg[..0x4000.min(self.prg.len())];
+        let high = &self.prg[high..(high + 0x4000).min(self.prg.len())];
+        bus.ram[0x8000..0x8000 + low.len()].copy_from_slice(low);
+        bus.ram[0xC000..0xC000 + high.len()].copy_from_slice(high);
+        if let Some(trainer) = &self.trainer {
+            bus.ram[0x7000..0x7200].copy_from_slice(trainer);
+        }
+        bus.cartridge = Some(self);
+    }
+
+    // Offset in PRG of the 16K that insert puts at $C000
+    pub fn high_bank(&self) -> usize {
+        if self.mapper == 0 && self.prg.len() >= 0x8000 {
+            0x4000
+        } else {
+            self.prg.len() - 0x4000.min(self.prg.len())
+        }
+    }
+
+    // Where a byte of PRG shows up in the CPU's memory, if anywhere
+    pub fn cpu_addresses(&self, offset: usize) -> Vec<u16> {
+        let high = self.high_bank();
+        let mut addresses = Vec::new();
+        if offset < 0x4000 {
+            addresses.push(0x8000 + offset as u16);
+        }
+        if (high..high + 0x4000).contains(&offset) {
+            addresses.push(0xC000 + (offset - high) as u16);
+        }
+        addresses
+    }
+}
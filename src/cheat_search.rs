@@ -0,0 +1,240 @@
+use crate::cheats::Cheat;
+use crate::cpu_6502::Cpu6502;
+
+// Finds where a game keeps something (lives, health, a timer) in the 2K of
+// work RAM, the way FCEUX's Cheat Search does. Every address starts as a
+// candidate. Each filter compares what the candidates hold now with what
+// they held at the last snapshot, or with a value, keeps those that pass,
+// and takes a new snapshot:
+//
+//     reset          lives = 3
+//     eq 3           ...lose a life...
+//     by -1          ...lose another...
+//     lt             -> a handful of addresses, one of them the lives
+//
+// What is left can then be made into cheats.
+
+pub const RAM_SIZE: usize = 0x800;
+
+// What a candidate is compared with
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+    Previous,
+    Value(u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    Equal(Operand),
+    NotEqual(Operand),
+    Greater(Operand),
+    Less(Operand),
+    Changed,
+    Unchanged,
+    // Now minus before, wrapping, so -1 is "went down by one"
+    ChangedBy(i16),
+}
+
+impl Filter {
+    // Text as typed in the frontends: eq, ne, gt or lt with an optional
+    // value (without one they compare with the snapshot), changed,
+    // unchanged, or by and a signed amount
+    pub fn parse(text: &str) -> Result<Filter, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let number = |word: &str| -> Result<i64, String> {
+            let (negative, digits) = match word.strip_prefix('-') {
+                Some(digits) => (true, digits),
+                None => (false, word.strip_prefix('+').unwrap_or(word)),
+            };
+            let value = match digits.strip_prefix('$') {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => digits.parse(),
+            }
+            .map_err(|_| format!("bad number '{}'", word))?;
+            Ok(if negative { -value } else { value })
+        };
+        let operand = |word: Option<&&str>| -> Result<Operand, String> {
+            match word {
+                None => Ok(Operand::Previous),
+                Some(word) => match number(word)? {
+                    value @ 0..=255 => Ok(Operand::Value(value as u8)),
+                    _ => Err(format!("'{}' is not a byte", word)),
+                },
+            }
+        };
+        let name = words.first().ok_or("missing filter")?.to_ascii_lowercase();
+        match name.as_str() {
+            "eq" | "=" => Ok(Filter::Equal(operand(words.get(1))?)),
+            "ne" | "!=" => Ok(Filter::NotEqual(operand(words.get(1))?)),
+            "gt" | ">" => Ok(Filter::Greater(operand(words.get(1))?)),
+            "lt" | "<" => Ok(Filter::Less(operand(words.get(1))?)),
+            "changed" => Ok(Filter::Changed),
+            "unchanged" => Ok(Filter::Unchanged),
+            "by" => {
+                let word = words.get(1).ok_or("by needs an amount")?;
+                match number(word)? {
+                    amount @ -255..=255 => Ok(Filter::ChangedBy(amount as i16)),
+                    _ => Err(format!("'{}' is out of range", word)),
+                }
+            }
+            _ => Err(format!("unknown filter '{}'", name)),
+        }
+    }
+
+    pub fn matches(&self, previous: u8, current: u8) -> bool {
+        let value = |operand: &Operand| match operand {
+            Operand::Previous => previous,
+            Operand::Value(value) => *value,
+        };
+        match self {
+            Filter::Equal(operand) => current == value(operand),
+            Filter::NotEqual(operand) => current != value(operand),
+            Filter::Greater(operand) => current > value(operand),
+            Filter::Less(operand) => current < value(operand),
+            Filter::Changed => current != previous,
+            Filter::Unchanged => current == previous,
+            Filter::ChangedBy(amount) => current.wrapping_sub(previous) == *amount as u8,
+        }
+    }
+}
+
+pub struct CheatSearch {
+    candidates: Vec<u16>,
+    snapshot: Vec<u8>,
+}
+
+impl CheatSearch {
+    // Starts with every address a candidate and a snapshot of now
+    pub fn new(cpu: &Cpu6502) -> Self {
+        Self {
+            candidates: (0..RAM_SIZE as u16).collect(),
+            snapshot: ram(cpu),
+        }
+    }
+
+    pub fn reset(&mut self, cpu: &Cpu6502) {
+        *self = CheatSearch::new(cpu);
+    }
+
+    // Keeps the candidates that pass, returning how many are left
+    pub fn filter(&mut self, cpu: &Cpu6502, filter: Filter) -> usize {
+        let now = ram(cpu);
+        self.candidates
+            .retain(|addr| filter.matches(self.snapshot[*addr as usize], now[*addr as usize]));
+        self.snapshot = now;
+        self.candidates.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    // Address, value at the snapshot and value now of each candidate
+    pub fn candidates(&self, cpu: &Cpu6502) -> Vec<(u16, u8, u8)> {
+        self.candidates
+            .iter()
+            .map(|addr| {
+                let previous = self.snapshot[*addr as usize];
+                (*addr, previous, cpu.bus.ram[*addr as usize])
+            })
+            .collect()
+    }
+
+    // A cheat for each candidate holding it at value, or at what it holds
+    // now when there is no value
+    pub fn to_cheats(&self, cpu: &Cpu6502, value: Option<u8>, name: &str) -> Vec<Cheat> {
+        self.candidates
+            .iter()
+            .map(|addr| {
+                let value = value.unwrap_or(cpu.bus.ram[*addr as usize]);
+                Cheat::new(name, *addr, value)
+            })
+            .collect()
+    }
+}
+
+fn ram(cpu: &Cpu6502) -> Vec<u8> {
+    cpu.bus.ram[..RAM_SIZE].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_filters() {
+        let parsed = [
+            ("eq", Filter::Equal(Operand::Previous)),
+            ("EQ 3", Filter::Equal(Operand::Value(3))),
+            ("!= $1f", Filter::NotEqual(Operand::Value(0x1F))),
+            ("gt +7", Filter::Greater(Operand::Value(7))),
+            ("<", Filter::Less(Operand::Previous)),
+            ("changed", Filter::Changed),
+            ("unchanged", Filter::Unchanged),
+            ("by -1", Filter::ChangedBy(-1)),
+            ("by $FF", Filter::ChangedBy(255)),
+            ("by -$FF", Filter::ChangedBy(-255)),
+        ];
+        for (text, filter) in parsed {
+            assert_eq!(Filter::parse(text), Ok(filter), "{}", text);
+        }
+        for bad in [
+            "", "eq 256", "lt -1", "gt x", "by", "by 256", "by -256", "up",
+        ] {
+            assert!(Filter::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn filters_compare_with_the_snapshot_or_a_value() {
+        assert!(Filter::Equal(Operand::Previous).matches(5, 5));
+        assert!(Filter::Equal(Operand::Value(3)).matches(5, 3));
+        assert!(!Filter::NotEqual(Operand::Value(3)).matches(5, 3));
+        assert!(Filter::Greater(Operand::Previous).matches(5, 6));
+        assert!(!Filter::Greater(Operand::Value(6)).matches(0, 6));
+        assert!(Filter::Less(Operand::Previous).matches(5, 4));
+        assert!(Filter::Changed.matches(5, 4));
+        assert!(!Filter::Unchanged.matches(5, 4));
+    }
+
+    #[test]
+    fn changed_by_wraps() {
+        let down = Filter::ChangedBy(-1);
+        assert!(down.matches(5, 4));
+        assert!(down.matches(0, 255));
+        assert!(!down.matches(4, 5));
+        assert!(Filter::ChangedBy(1).matches(255, 0));
+        assert!(Filter::ChangedBy(-255).matches(0, 1));
+        assert!(Filter::ChangedBy(255).matches(1, 0));
+        assert!(Filter::ChangedBy(-16).matches(8, 248));
+        assert!(Filter::ChangedBy(0).matches(9, 9));
+    }
+
+    #[test]
+    fn narrows_down_to_the_lives_counter() {
+        let mut cpu = Cpu6502::new();
+        cpu.bus.ram[0x33] = 3;
+        cpu.bus.ram[0x40] = 3;
+        let mut search = CheatSearch::new(&cpu);
+        assert_eq!(search.len(), RAM_SIZE);
+
+        assert_eq!(search.filter(&cpu, Filter::parse("eq 3").unwrap()), 2);
+        cpu.bus.ram[0x33] = 2;
+        cpu.bus.ram[0x40] = 4;
+        assert_eq!(search.filter(&cpu, Filter::parse("by -1").unwrap()), 1);
+        assert_eq!(search.candidates(&cpu), [(0x33, 2, 2)]);
+
+        let cheats = search.to_cheats(&cpu, Some(9), "lives");
+        assert_eq!(cheats, [Cheat::new("lives", 0x33, 9)]);
+
+        cpu.bus.ram[0x33] = 1;
+        assert_eq!(search.filter(&cpu, Filter::Greater(Operand::Previous)), 0);
+        assert!(search.is_empty());
+        search.reset(&cpu);
+        assert_eq!(search.len(), RAM_SIZE);
+    }
+}
@@ -0,0 +1,438 @@
+use crate::cpu_6502::Cpu6502;
+use crate::debugger::CPU_CYCLES_PER_FRAME;
+use std::path::{Path, PathBuf};
+
+// Cheats, kept in FCEUX's .cht format so lists can be shared with it. One
+// cheat a line:
+//
+//     [S][C][:]AAAA:VV[:CC]:name
+//
+// AAAA is the address and VV the value, both hex. C means a compare value
+// CC follows, and the cheat only acts while the address holds CC. A : in
+// front marks a cheat that is off.
+//
+// Without S a cheat writes its value to RAM at the start of every frame,
+// as a Pro Action Replay did (on each NMI). With S it is a ROM
+// substitution, as a Game Genie did: reads of the address return the
+// value instead, without changing what is stored there. FCEUX allows
+// these at any address, RAM included, and so does the bus.
+//
+// A game's cheats are kept next to it, game.nes having game.cht.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CheatKind {
+    Ram,
+    Rom,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub kind: CheatKind,
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+    pub enabled: bool,
+}
+
+// What the bus checks reads against
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RomPatch {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl Cheat {
+    // A RAM cheat
+    pub fn new(name: &str, addr: u16, value: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: CheatKind::Ram,
+            addr,
+            value,
+            compare: None,
+            enabled: true,
+        }
+    }
+
+    // A Game Genie code (6 or 8 letters) or a Pro Action Replay code (8
+    // hex digits, or AAAA:VV), named after itself
+    pub fn from_code(code: &str) -> Result<Self, String> {
+        let code = code.trim();
+        if let Ok((addr, value, compare)) = decode_game_genie(code) {
+            return Ok(Self {
+                kind: CheatKind::Rom,
+                compare,
+                ..Cheat::new(&code.to_ascii_uppercase(), addr, value)
+            });
+        }
+        if let Ok((addr, value)) = decode_par(code) {
+            return Ok(Cheat::new(&code.to_ascii_uppercase(), addr, value));
+        }
+        Err(format!("'{}' is not a Game Genie or PAR code", code))
+    }
+
+    // RAM cheats write their value, ROM ones are left to the bus
+    pub fn apply(&self, cpu: &mut Cpu6502) {
+        if self.kind == CheatKind::Rom || !self.enabled {
+            return;
+        }
+        let held = &mut cpu.bus.ram[self.addr as usize];
+        if self.compare.is_none_or(|c| *held == c) {
+            *held = self.value;
+        }
+    }
+
+    pub fn from_cht(line: &str) -> Result<Self, String> {
+        let mut rest = line.trim();
+        let kind = match rest.strip_prefix('S') {
+            Some(after) => {
+                rest = after;
+                CheatKind::Rom
+            }
+            None => CheatKind::Ram,
+        };
+        let has_compare = rest.starts_with('C');
+        if has_compare {
+            rest = &rest[1..];
+        }
+        let enabled = !rest.starts_with(':');
+        if !enabled {
+            rest = &rest[1..];
+        }
+
+        let fields = if has_compare { 4 } else { 3 };
+        let parts: Vec<&str> = rest.splitn(fields, ':').collect();
+        if parts.len() < fields {
+            return Err(format!("'{}' is not a cheat", line));
+        }
+        let hex = |text: &str| {
+            u16::from_str_radix(text, 16).map_err(|_| format!("bad hex '{}' in '{}'", text, line))
+        };
+        let byte = |text: &str| match hex(text)? {
+            value @ 0..=0xFF => Ok(value as u8),
+            _ => Err(format!("'{}' is not a byte in '{}'", text, line)),
+        };
+        Ok(Self {
+            name: parts[fields - 1].to_string(),
+            kind,
+            addr: hex(parts[0])?,
+            value: byte(parts[1])?,
+            compare: if has_compare {
+                Some(byte(parts[2])?)
+            } else {
+                None
+            },
+            enabled,
+        })
+    }
+
+    pub fn to_cht(&self) -> String {
+        let mut line = String::new();
+        if self.kind == CheatKind::Rom {
+            line.push('S');
+        }
+        if self.compare.is_some() {
+            line.push('C');
+        }
+        if !self.enabled {
+            line.push(':');
+        }
+        line += &format!("{:04x}:{:02x}:", self.addr, self.value);
+        if let Some(compare) = self.compare {
+            line += &format!("{:02x}:", compare);
+        }
+        line + &self.name
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CODES
+
+const GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+
+// Address, value and (for 8 letter codes) compare value of a Game Genie
+// code. Each letter is four bits, scrambled into the fields as on
+// nesdev's "Game Genie" page.
+pub fn decode_game_genie(code: &str) -> Result<(u16, u8, Option<u8>), String> {
+    let n: Vec<u16> = code
+        .chars()
+        .map(|c| {
+            GENIE_LETTERS
+                .find(c.to_ascii_uppercase())
+                .map(|i| i as u16)
+                .ok_or(format!("'{}' is not a Game Genie letter", c))
+        })
+        .collect::<Result<_, _>>()?;
+    if n.len() != 6 && n.len() != 8 {
+        return Err(format!("'{}' is not 6 or 8 letters long", code));
+    }
+
+    let addr = 0x8000
+        | ((n[3] & 7) << 12)
+        | ((n[5] & 7) << 8)
+        | ((n[4] & 8) << 8)
+        | ((n[2] & 7) << 4)
+        | ((n[1] & 8) << 4)
+        | (n[4] & 7)
+        | (n[3] & 8);
+    let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+    if n.len() == 6 {
+        return Ok((addr, (value | (n[5] & 8)) as u8, None));
+    }
+    let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+    Ok((addr, (value | (n[7] & 8)) as u8, Some(compare as u8)))
+}
+
+// Address and value of a Pro Action Replay code. The NES ones are 8 hex
+// digits, a byte FCEUX ignores and then AAAAVV; AAAA:VV and AAAAVV are
+// taken too.
+pub fn decode_par(code: &str) -> Result<(u16, u8), String> {
+    let digits: String = code.chars().filter(|c| *c != ':').collect();
+    let digits = match digits.len() {
+        8 => &digits[2..],
+        6 => &digits[..],
+        _ => return Err(format!("'{}' is not a PAR code", code)),
+    };
+    let number = u32::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not hex", code))?;
+    Ok(((number >> 8) as u16, number as u8))
+}
+
+// Where the cheats for a ROM file are kept
+pub fn path_for_rom(rom: &Path) -> PathBuf {
+    rom.with_extension("cht")
+}
+
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+    // Whether the bus has this list's ROM patches
+    installed: bool,
+    // Frame the RAM cheats were last written on
+    last_frame: Option<u64>,
+}
+
+impl Default for CheatList {
+    fn default() -> Self {
+        CheatList::new()
+    }
+}
+
+impl CheatList {
+    pub fn new() -> Self {
+        Self {
+            cheats: Vec::new(),
+            installed: false,
+            last_frame: None,
+        }
+    }
+
+    // Patches go to the bus again, and RAM cheats are written straight away
+    // rather than at the next frame
+    fn changed(&mut self) {
+        self.installed = false;
+        self.last_frame = None;
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    // A cheat doing the same thing to the same address replaces the old one
+    pub fn add(&mut self, cheat: Cheat) {
+        let same = |c: &&mut Cheat| {
+            c.addr == cheat.addr && c.kind == cheat.kind && c.compare == cheat.compare
+        };
+        match self.cheats.iter_mut().find(same) {
+            Some(old) => *old = cheat,
+            None => self.cheats.push(cheat),
+        }
+        self.changed();
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        self.changed();
+        (index < self.cheats.len()).then(|| self.cheats.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.changed();
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        self.changed();
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Called before every instruction. RAM cheats are written on the first
+    // instruction of each frame, and the ROM ones handed to the bus
+    // whenever the list has changed.
+    pub fn apply(&mut self, cpu: &mut Cpu6502) {
+        if !self.installed {
+            cpu.bus.rom_patches = self
+                .cheats
+                .iter()
+                .filter(|c| c.enabled && c.kind == CheatKind::Rom)
+                .map(|c| RomPatch {
+                    addr: c.addr,
+                    value: c.value,
+                    compare: c.compare,
+                })
+                .collect();
+            self.installed = true;
+        }
+
+        let frame = cpu.clock_count / CPU_CYCLES_PER_FRAME;
+        if self.last_frame == Some(frame) {
+            return;
+        }
+        self.last_frame = Some(frame);
+        for cheat in &self.cheats {
+            cheat.apply(cpu);
+        }
+    }
+
+    // Blank lines and lines starting with # are skipped
+    pub fn from_cht(text: &str) -> Result<Self, String> {
+        let mut list = CheatList::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                list.add(Cheat::from_cht(line)?);
+            }
+        }
+        Ok(list)
+    }
+
+    pub fn to_cht(&self) -> String {
+        self.cheats.iter().map(|c| c.to_cht() + "\n").collect()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        CheatList::from_cht(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_cht()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_genie_codes_decode() {
+        // nesdev's examples
+        assert_eq!(decode_game_genie("SXIOPO").unwrap(), (0x91D9, 0xAD, None));
+        assert_eq!(decode_game_genie("sxiopo").unwrap(), (0x91D9, 0xAD, None));
+        assert_eq!(
+            decode_game_genie("ZEXPYGLA").unwrap(),
+            (0x94A7, 0x02, Some(0x03))
+        );
+        assert!(decode_game_genie("SXIOPB").is_err());
+        assert!(decode_game_genie("SXIOP").is_err());
+
+        let cheat = Cheat::from_code("ZEXPYGLA").unwrap();
+        assert_eq!(cheat.kind, CheatKind::Rom);
+        assert_eq!(
+            (cheat.addr, cheat.value, cheat.compare),
+            (0x94A7, 0x02, Some(0x03))
+        );
+        assert_eq!(cheat.name, "ZEXPYGLA");
+    }
+
+    #[test]
+    fn par_codes_decode() {
+        assert_eq!(decode_par("00006A09").unwrap(), (0x006A, 0x09));
+        assert_eq!(decode_par("07A0:63").unwrap(), (0x07A0, 0x63));
+        assert_eq!(decode_par("07A063").unwrap(), (0x07A0, 0x63));
+        assert!(decode_par("07A0:6").is_err());
+        assert!(decode_par("07G063").is_err());
+
+        let cheat = Cheat::from_code("07a0:63").unwrap();
+        assert_eq!(cheat.kind, CheatKind::Ram);
+        assert_eq!(
+            (cheat.addr, cheat.value, cheat.compare),
+            (0x07A0, 0x63, None)
+        );
+    }
+
+    #[test]
+    fn cht_files_round_trip() {
+        let text = "\
+# lives
+075a:09:Infinite lives
+SC91d9:ad:03:Genie: with compare
+:0300:ff:Off for now
+";
+        let list = CheatList::from_cht(text).unwrap();
+        let cheats = list.cheats();
+        assert_eq!(cheats.len(), 3);
+        assert_eq!(cheats[0], Cheat::new("Infinite lives", 0x075A, 0x09));
+        assert_eq!(cheats[1].kind, CheatKind::Rom);
+        assert_eq!(cheats[1].compare, Some(0x03));
+        assert_eq!(cheats[1].name, "Genie: with compare");
+        assert!(!cheats[2].enabled);
+
+        let again = CheatList::from_cht(&list.to_cht()).unwrap();
+        assert_eq!(again.cheats(), cheats);
+        assert!(Cheat::from_cht("075a:9z:bad").is_err());
+        assert!(Cheat::from_cht("075a").is_err());
+    }
+
+    #[test]
+    fn ram_cheats_go_in_once_a_frame() {
+        let mut cpu = Cpu6502::new();
+        let mut list = CheatList::new();
+        list.add(Cheat::new("lives", 0x0010, 0x05));
+        list.add(Cheat::from_code("SXIOPO").unwrap());
+
+        list.apply(&mut cpu);
+        assert_eq!(cpu.bus.ram[0x10], 0x05);
+        assert_eq!(cpu.bus.read(&0x91D9, false), 0xAD);
+
+        // The game is free to change it until the next frame starts
+        cpu.bus.ram[0x10] = 0x01;
+        cpu.clock_count += CPU_CYCLES_PER_FRAME - 1;
+        list.apply(&mut cpu);
+        assert_eq!(cpu.bus.ram[0x10], 0x01);
+        cpu.clock_count += 1;
+        list.apply(&mut cpu);
+        assert_eq!(cpu.bus.ram[0x10], 0x05);
+
+        // A change to the list goes in straight away
+        cpu.bus.ram[0x10] = 0x01;
+        list.add(Cheat::new("lives", 0x0010, 0x07));
+        list.apply(&mut cpu);
+        assert_eq!(cpu.bus.ram[0x10], 0x07);
+    }
+
+    #[test]
+    fn substitutions_work_below_rom_too() {
+        let mut cpu = Cpu6502::new();
+        let mut list = CheatList::from_cht("S0300:42:Sub\nSC6000:99:01:Sub compare\n").unwrap();
+        cpu.bus.ram[0x0300] = 0x10;
+        cpu.bus.ram[0x6000] = 0x01;
+        list.apply(&mut cpu);
+
+        assert_eq!(cpu.bus.read(&0x0300, false), 0x42);
+        assert_eq!(cpu.bus.ram[0x0300], 0x10);
+        assert_eq!(cpu.bus.read(&0x6000, false), 0x99);
+        cpu.bus.ram[0x6000] = 0x02;
+        assert_eq!(cpu.bus.read(&0x6000, false), 0x02);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::cartridge::Cartridge;
+use crate::cpu_6502::{AddrMode, Cpu6502};
+use crate::debugger::{memory_accesses, AccessKind};
+use std::path::Path;
+
+// Code/Data Logger. Records how every byte of PRG and CHR ROM has been used
+// while the program runs, and reads and writes the FCEUX .cdl format: one
+// flag byte per PRG byte followed by one per CHR byte.
+//
+// The flags are worked out from the instruction waiting at pc, the same way
+// the watchpoints are, so logging never changes what the cpu does.
+
+// Flags of a PRG byte
+pub struct PrgFlags;
+impl PrgFlags {
+    // Executed, as an opcode or an operand
+    pub const CODE: u8 = 1 << 0;
+    // Read by an instruction
+    pub const DATA: u8 = 1 << 1;
+    // Bits 2 and 3 hold which 8K window of $8000-$FFFF the byte was seen in
+    pub const BANK: u8 = 0x0C;
+    // Target of an indirect jump
+    pub const INDIRECT_CODE: u8 = 1 << 4;
+    // Read through a pointer, (zp,X) or (zp),Y
+    pub const INDIRECT_DATA: u8 = 1 << 5;
+    // Played as DPCM samples
+    pub const PCM_DATA: u8 = 1 << 6;
+}
+
+// Flags of a CHR byte
+pub struct ChrFlags;
+impl ChrFlags {
+    // Fetched by the PPU to draw the screen
+    pub const RENDERED: u8 = 1 << 0;
+    // Read by the program through PPUDATA
+    pub const READ: u8 = 1 << 1;
+}
+
+pub struct CodeDataLogger {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    // FCEUX marks opcodes and operands both as code, this keeps which
+    // bytes started an instruction for as long as the logger is alive
+    opcodes: Vec<bool>,
+    // Offsets in PRG of what is seen at $8000 and $C000
+    banks: [usize; 2],
+}
+
+impl CodeDataLogger {
+    // PRG is seen at $8000-$FFFF, mirrored when it is smaller than 32K
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        let prg_size = prg_size.clamp(1, 0x8000);
+        Self {
+            prg: vec![0; prg_size],
+            chr: vec![0; chr_size],
+            opcodes: vec![false; prg_size],
+            banks: [0, 0x4000],
+        }
+    }
+
+    // Sized for the whole of the cartridge's PRG and CHR ROM, as FCEUX
+    // sizes its files, with PRG found where the cartridge put it
+    pub fn for_cartridge(cartridge: &Cartridge) -> Self {
+        let prg_size = cartridge.prg.len().max(1);
+        Self {
+            prg: vec![0; prg_size],
+            chr: vec![0; cartridge.chr.len()],
+            opcodes: vec![false; prg_size],
+            banks: [0, cartridge.high_bank()],
+        }
+    }
+
+    pub fn prg_size(&self) -> usize {
+        self.prg.len()
+    }
+
+    pub fn chr_size(&self) -> usize {
+        self.chr.len()
+    }
+
+    // Offset into PRG ROM of a cpu address, None outside the cartridge
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        if addr >= 0x8000 {
+            let bank = self.banks[(addr as usize - 0x8000) / 0x4000];
+            Some((bank + (addr as usize & 0x3FFF)) % self.prg.len())
+        } else {
+            None
+        }
+    }
+
+    pub fn prg_flags(&self, addr: u16) -> u8 {
+        match self.prg_offset(addr) {
+            Some(offset) => self.prg[offset],
+            None => 0,
+        }
+    }
+
+    pub fn chr_flags(&self, offset: usize) -> u8 {
+        self.chr.get(offset).copied().unwrap_or(0)
+    }
+
+    pub fn is_opcode(&self, addr: u16) -> bool {
+        self.prg_offset(addr).is_some_and(|o| self.opcodes[o])
+    }
+
+    pub fn log_prg(&mut self, addr: u16, flags: u8) {
+        if let Some(offset) = self.prg_offset(addr) {
+            let bank = ((addr & 0x6000) >> 11) as u8;
+            self.prg[offset] |= flags | bank;
+        }
+    }
+
+    pub fn log_chr_rendered(&mut self, offset: usize) {
+        if let Some(flags) = self.chr.get_mut(offset) {
+            *flags |= ChrFlags::RENDERED;
+        }
+    }
+
+    pub fn log_chr_read(&mut self, offset: usize) {
+        if let Some(flags) = self.chr.get_mut(offset) {
+            *flags |= ChrFlags::READ;
+        }
+    }
+
+    // Called with the cpu on an instruction boundary, before the
+    // instruction at pc runs
+    pub fn log_instruction(&mut self, cpu: &Cpu6502) {
+        let ins = cpu.decode(cpu.pc);
+        for i in 0..ins.bytes.len() as u16 {
+            self.log_prg(cpu.pc.wrapping_add(i), PrgFlags::CODE);
+        }
+        if let Some(offset) = self.prg_offset(cpu.pc) {
+            self.opcodes[offset] = true;
+        }
+
+        match ins.mode {
+            AddrMode::Ind => {
+                // The pointer is data, where it points is code
+                for i in 0..2 {
+                    self.log_prg(ins.operand.wrapping_add(i), PrgFlags::DATA);
+                }
+                if let Some(target) = cpu.operand_address() {
+                    self.log_prg(target, PrgFlags::INDIRECT_CODE);
+                }
+            }
+            AddrMode::Izx | AddrMode::Izy => {
+                for (addr, kind) in memory_accesses(cpu) {
+                    if kind == AccessKind::Read {
+                        self.log_prg(addr, PrgFlags::DATA | PrgFlags::INDIRECT_DATA);
+                    }
+                }
+            }
+            _ => {
+                for (addr, kind) in memory_accesses(cpu) {
+                    if kind == AccessKind::Read {
+                        self.log_prg(addr, PrgFlags::DATA);
+                    }
+                }
+            }
+        }
+    }
+
+    // Bytes marked as code, as data and not touched at all
+    pub fn coverage(&self) -> (usize, usize, usize) {
+        let code = self.prg.iter().filter(|f| *f & PrgFlags::CODE != 0).count();
+        let data = self
+            .prg
+            .iter()
+            .filter(|f| *f & PrgFlags::CODE == 0 && *f & PrgFlags::DATA != 0)
+            .count();
+        let unknown = self.prg.iter().filter(|f| **f == 0).count();
+        (code, data, unknown)
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // FILES
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut data = self.prg.clone();
+        data.extend_from_slice(&self.chr);
+        std::fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // A .cdl holds no sizes of its own, they have to match the cartridge
+    // that is loaded. Flags already logged are kept.
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if data.len() != self.prg.len() + self.chr.len() {
+            return Err(format!(
+                "{}: {} bytes, expected {} for {}K PRG and {}K CHR",
+                path.display(),
+                data.len(),
+                self.prg.len() + self.chr.len(),
+                self.prg.len() / 1024,
+                self.chr.len() / 1024
+            ));
+        }
+        let (prg, chr) = data.split_at(self.prg.len());
+        for (flags, loaded) in self.prg.iter_mut().zip(prg) {
+            *flags |= loaded;
+        }
+        for (flags, loaded) in self.chr.iter_mut().zip(chr) {
+            *flags |= loaded;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cartridge(prg_banks: u8, chr_banks: u8, mapper: u8) -> Cartridge {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, mapper << 4];
+        rom.resize(16, 0);
+        rom.resize(
+            16 + prg_banks as usize * 0x4000 + chr_banks as usize * 0x2000,
+            0,
+        );
+        Cartridge::from_ines(&rom).unwrap()
+    }
+
+    #[test]
+    fn sized_and_mapped_like_the_cartridge() {
+        // 16K of PRG seen twice, CHR RAM
+        let cdl = CodeDataLogger::for_cartridge(&cartridge(1, 0, 0));
+        assert_eq!((cdl.prg_size(), cdl.chr_size()), (0x4000, 0));
+        assert_eq!(cdl.prg_offset(0x8123), Some(0x0123));
+        assert_eq!(cdl.prg_offset(0xC123), Some(0x0123));
+        assert_eq!(cdl.prg_offset(0x6000), None);
+
+        let cdl = CodeDataLogger::for_cartridge(&cartridge(2, 1, 0));
+        assert_eq!((cdl.prg_size(), cdl.chr_size()), (0x8000, 0x2000));
+        assert_eq!(cdl.prg_offset(0xC123), Some(0x4123));
+
+        // 128K, the last bank fixed at $C000
+        let mut cdl = CodeDataLogger::for_cartridge(&cartridge(8, 0, 1));
+        assert_eq!(cdl.prg_size(), 0x20000);
+        assert_eq!(cdl.prg_offset(0x8123), Some(0x0123));
+        assert_eq!(cdl.prg_offset(0xFFFF), Some(0x1FFFF));
+        cdl.log_prg(0xE000, PrgFlags::CODE);
+        assert_eq!(cdl.prg[0x1E000], PrgFlags::CODE | 0x0C);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::save_state::{ChunkReader, ChunkWriter};
+use std::cell::Cell;
+
+// Standard NES controller as seen through $4016/$4017. Writing 1 then 0
+// to $4016 latches the buttons into a shift register, every read then
+// returns the next button in bit 0, A first. After all eight the register
+// reads 1s, like the official pad.
+
+// Button bits in the order they are shifted out
+pub struct Buttons;
+impl Buttons {
+    pub const A: u8 = 1 << 0;
+    pub const B: u8 = 1 << 1;
+    pub const SELECT: u8 = 1 << 2;
+    pub const START: u8 = 1 << 3;
+    pub const UP: u8 = 1 << 4;
+    pub const DOWN: u8 = 1 << 5;
+    pub const LEFT: u8 = 1 << 6;
+    pub const RIGHT: u8 = 1 << 7;
+}
+
+pub struct Controller {
+    // Buttons held right now, set by the frontend or a movie
+    pub buttons: u8,
+    // Reads go through &Bus, so shifting uses a Cell
+    shift: Cell<u8>,
+    strobe: bool,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Controller::new()
+    }
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self {
+            buttons: 0,
+            shift: Cell::new(0),
+            strobe: false,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.shift.set(self.buttons);
+        }
+    }
+
+    // Bits 1-7 are open bus, $40 on most consoles
+    pub fn read(&self) -> u8 {
+        if self.strobe {
+            return 0x40 | (self.buttons & 0x01);
+        }
+        let shift = self.shift.get();
+        self.shift.set((shift >> 1) | 0x80);
+        0x40 | (shift & 0x01)
+    }
+
+    // What read() would return, without shifting
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            0x40 | (self.buttons & 0x01)
+        } else {
+            0x40 | (self.shift.get() & 0x01)
+        }
+    }
+
+    pub fn save_state(&self, c: &mut ChunkWriter) {
+        c.u8(self.buttons);
+        c.u8(self.shift.get());
+        c.u8(self.strobe as u8);
+    }
+
+    pub fn load_state(&mut self, c: &mut ChunkReader) -> Result<(), String> {
+        let (buttons, shift, strobe) = (c.u8()?, c.u8()?, c.u8()?);
+        self.buttons = buttons;
+        self.shift.set(shift);
+        self.strobe = strobe != 0;
+        Ok(())
+    }
+}
+
+// Buttons from the letters of FCEUX's "RLDUTSBA", any other character in a
+// slot means pressed
+pub fn buttons_from_fm2(text: &str) -> u8 {
+    text.chars()
+        .zip([7, 6, 5, 4, 3, 2, 1, 0])
+        .filter(|(c, _)| *c != '.' && *c != ' ')
+        .fold(0, |buttons, (_, bit)| buttons | (1 << bit))
+}
+
+pub fn buttons_to_fm2(buttons: u8) -> String {
+    "RLDUTSBA"
+        .chars()
+        .zip([7, 6, 5, 4, 3, 2, 1, 0])
+        .map(|(c, bit)| if buttons & (1 << bit) != 0 { c } else { '.' })
+        .collect()
+}
@@ -1,9 +1,12 @@
 use crate::bus::Bus;
-use std::collections::HashMap;
+use crate::controller::Controller;
+use crate::disassembler::DisassembledInstruction;
+use crate::save_state::{StateReader, StateWriter};
+use std::collections::BTreeMap;
 use std::ops::Add;
 
 #[non_exhaustive]
-pub(crate) struct Flags6502;
+pub struct Flags6502;
 impl Flags6502 {
     pub const C: u8 = (1 << 0);
     // Carry Bit
@@ -41,29 +44,91 @@ impl Flags6502 {
 //     N = (1 << 7),    // Negative
 // }
 
+// The addressing modes of the 6502, named after the functions that
+// implement them
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AddrMode {
+    Imp,
+    Imm,
+    Zp0,
+    Zpx,
+    Zpy,
+    Rel,
+    Abs,
+    Abx,
+    Aby,
+    Ind,
+    Izx,
+    Izy,
+}
+
+impl AddrMode {
+    pub const ALL: [AddrMode; 12] = [
+        AddrMode::Imp,
+        AddrMode::Imm,
+        AddrMode::Zp0,
+        AddrMode::Zpx,
+        AddrMode::Zpy,
+        AddrMode::Rel,
+        AddrMode::Abs,
+        AddrMode::Abx,
+        AddrMode::Aby,
+        AddrMode::Ind,
+        AddrMode::Izx,
+        AddrMode::Izy,
+    ];
+
+    // Number of operand bytes following the opcode
+    pub fn operand_size(&self) -> u16 {
+        match self {
+            AddrMode::Imp => 0,
+            AddrMode::Abs | AddrMode::Abx | AddrMode::Aby | AddrMode::Ind => 2,
+            _ => 1,
+        }
+    }
+
+    // Short upper case name, as shown between braces in the debugger
+    pub fn tag(&self) -> &'static str {
+        match self {
+            AddrMode::Imp => "IMP",
+            AddrMode::Imm => "IMM",
+            AddrMode::Zp0 => "ZP0",
+            AddrMode::Zpx => "ZPX",
+            AddrMode::Zpy => "ZPY",
+            AddrMode::Rel => "REL",
+            AddrMode::Abs => "ABS",
+            AddrMode::Abx => "ABX",
+            AddrMode::Aby => "ABY",
+            AddrMode::Ind => "IND",
+            AddrMode::Izx => "IZX",
+            AddrMode::Izy => "IZY",
+        }
+    }
+}
+
 struct Instruction {
     name: String,
     operate: fn(&mut Cpu6502) -> u8,
-    addresmode: fn(&mut Cpu6502) -> u8,
+    addresmode: AddrMode,
     cyles: u8,
 }
 
-pub(crate) struct Cpu6502 {
+pub struct Cpu6502 {
     // accumulator
-    pub(crate) a: u8,
+    pub a: u8,
     // register X
-    pub(crate) x: u8,
+    pub x: u8,
     // register y
-    pub(crate) y: u8,
+    pub y: u8,
     // program counter
-    pub(crate) pc: u16,
+    pub pc: u16,
     // stack pointer
-    pub(crate) sp: u8,
+    pub sp: u8,
     // status register
-    pub(crate) sr: u8,
+    pub sr: u8,
 
     // Bus
-    pub(crate) bus: Bus,
+    pub bus: Bus,
 
     // // Flags
     // flags: Flags6502,
@@ -80,6 +145,8 @@ pub(crate) struct Cpu6502 {
     opcode: u8,
     // Counts how many cycles the instruction has remaining
     cycles: u8,
+    // A global accumulation of the number of clocks
+    pub clock_count: u64,
 
     lookup: [Instruction; 256],
 }
@@ -109,1537 +176,1537 @@ impl Cpu6502 {
             Instruction {
                 name: "BRK".to_string(),
                 operate: Cpu6502::brk,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 7,
             },
             Instruction {
                 name: "ORA".to_string(),
                 operate: Cpu6502::ora,
-                addresmode: Cpu6502::izx,
+                addresmode: AddrMode::Izx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 3,
             },
             Instruction {
                 name: "ORA".to_string(),
                 operate: Cpu6502::ora,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "ASL".to_string(),
                 operate: Cpu6502::asl,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "PHP".to_string(),
                 operate: Cpu6502::php,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 3,
             },
             Instruction {
                 name: "ORA".to_string(),
                 operate: Cpu6502::ora,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "ASL".to_string(),
                 operate: Cpu6502::asl,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "ORA".to_string(),
                 operate: Cpu6502::ora,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "ASL".to_string(),
                 operate: Cpu6502::asl,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "BPL".to_string(),
                 operate: Cpu6502::bpl,
-                addresmode: Cpu6502::rel,
+                addresmode: AddrMode::Rel,
                 cyles: 2,
             },
             Instruction {
                 name: "ORA".to_string(),
                 operate: Cpu6502::ora,
-                addresmode: Cpu6502::izy,
+                addresmode: AddrMode::Izy,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "ORA".to_string(),
                 operate: Cpu6502::ora,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "ASL".to_string(),
                 operate: Cpu6502::asl,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "CLC".to_string(),
                 operate: Cpu6502::clc,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "ORA".to_string(),
                 operate: Cpu6502::ora,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "ORA".to_string(),
                 operate: Cpu6502::ora,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 4,
             },
             Instruction {
                 name: "ASL".to_string(),
                 operate: Cpu6502::asl,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "JSR".to_string(),
                 operate: Cpu6502::jsr,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 6,
             },
             Instruction {
                 name: "AND".to_string(),
                 operate: Cpu6502::and,
-                addresmode: Cpu6502::izx,
+                addresmode: AddrMode::Izx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "BIT".to_string(),
                 operate: Cpu6502::bit,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "AND".to_string(),
                 operate: Cpu6502::and,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "ROL".to_string(),
                 operate: Cpu6502::rol,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "PLP".to_string(),
                 operate: Cpu6502::plp,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "AND".to_string(),
                 operate: Cpu6502::and,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "ROL".to_string(),
                 operate: Cpu6502::rol,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "BIT".to_string(),
                 operate: Cpu6502::bit,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "AND".to_string(),
                 operate: Cpu6502::and,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "ROL".to_string(),
                 operate: Cpu6502::rol,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "BMI".to_string(),
                 operate: Cpu6502::bmi,
-                addresmode: Cpu6502::rel,
+                addresmode: AddrMode::Rel,
                 cyles: 2,
             },
             Instruction {
                 name: "AND".to_string(),
                 operate: Cpu6502::and,
-                addresmode: Cpu6502::izy,
+                addresmode: AddrMode::Izy,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "AND".to_string(),
                 operate: Cpu6502::and,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "ROL".to_string(),
                 operate: Cpu6502::rol,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "SEC".to_string(),
                 operate: Cpu6502::sec,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "AND".to_string(),
                 operate: Cpu6502::and,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "AND".to_string(),
                 operate: Cpu6502::and,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 4,
             },
             Instruction {
                 name: "ROL".to_string(),
                 operate: Cpu6502::rol,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "RTI".to_string(),
                 operate: Cpu6502::rti,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "EOR".to_string(),
                 operate: Cpu6502::eor,
-                addresmode: Cpu6502::izx,
+                addresmode: AddrMode::Izx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 3,
             },
             Instruction {
                 name: "EOR".to_string(),
                 operate: Cpu6502::eor,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "LSR".to_string(),
                 operate: Cpu6502::lsr,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "PHA".to_string(),
                 operate: Cpu6502::pha,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 3,
             },
             Instruction {
                 name: "EOR".to_string(),
                 operate: Cpu6502::eor,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "LSR".to_string(),
                 operate: Cpu6502::lsr,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "JMP".to_string(),
                 operate: Cpu6502::jmp,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 3,
             },
             Instruction {
                 name: "EOR".to_string(),
                 operate: Cpu6502::eor,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "LSR".to_string(),
                 operate: Cpu6502::lsr,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "BVC".to_string(),
                 operate: Cpu6502::bvc,
-                addresmode: Cpu6502::rel,
+                addresmode: AddrMode::Rel,
                 cyles: 2,
             },
             Instruction {
                 name: "EOR".to_string(),
                 operate: Cpu6502::eor,
-                addresmode: Cpu6502::izy,
+                addresmode: AddrMode::Izy,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "EOR".to_string(),
                 operate: Cpu6502::eor,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "LSR".to_string(),
                 operate: Cpu6502::lsr,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "CLI".to_string(),
                 operate: Cpu6502::cli,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "EOR".to_string(),
                 operate: Cpu6502::eor,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "EOR".to_string(),
                 operate: Cpu6502::eor,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 4,
             },
             Instruction {
                 name: "LSR".to_string(),
                 operate: Cpu6502::lsr,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "RTS".to_string(),
                 operate: Cpu6502::rts,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "ADC".to_string(),
                 operate: Cpu6502::adc,
-                addresmode: Cpu6502::izx,
+                addresmode: AddrMode::Izx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 3,
             },
             Instruction {
                 name: "ADC".to_string(),
                 operate: Cpu6502::adc,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "ROR".to_string(),
                 operate: Cpu6502::ror,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "PLA".to_string(),
                 operate: Cpu6502::pla,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "ADC".to_string(),
                 operate: Cpu6502::adc,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "ROR".to_string(),
                 operate: Cpu6502::ror,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "JMP".to_string(),
                 operate: Cpu6502::jmp,
-                addresmode: Cpu6502::ind,
+                addresmode: AddrMode::Ind,
                 cyles: 5,
             },
             Instruction {
                 name: "ADC".to_string(),
                 operate: Cpu6502::adc,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "ROR".to_string(),
                 operate: Cpu6502::ror,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "BVS".to_string(),
                 operate: Cpu6502::bvs,
-                addresmode: Cpu6502::rel,
+                addresmode: AddrMode::Rel,
                 cyles: 2,
             },
             Instruction {
                 name: "ADC".to_string(),
                 operate: Cpu6502::adc,
-                addresmode: Cpu6502::izy,
+                addresmode: AddrMode::Izy,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "ADC".to_string(),
                 operate: Cpu6502::adc,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "ROR".to_string(),
                 operate: Cpu6502::ror,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "SEI".to_string(),
                 operate: Cpu6502::sei,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "ADC".to_string(),
                 operate: Cpu6502::adc,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "ADC".to_string(),
                 operate: Cpu6502::adc,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 4,
             },
             Instruction {
                 name: "ROR".to_string(),
                 operate: Cpu6502::ror,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "STA".to_string(),
                 operate: Cpu6502::sta,
-                addresmode: Cpu6502::izx,
+                addresmode: AddrMode::Izx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "STY".to_string(),
                 operate: Cpu6502::sty,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "STA".to_string(),
                 operate: Cpu6502::sta,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "STX".to_string(),
                 operate: Cpu6502::stx,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 3,
             },
             Instruction {
                 name: "DEY".to_string(),
                 operate: Cpu6502::dey,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "TXA".to_string(),
                 operate: Cpu6502::txa,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "STY".to_string(),
                 operate: Cpu6502::sty,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "STA".to_string(),
                 operate: Cpu6502::sta,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "STX".to_string(),
                 operate: Cpu6502::stx,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "BCC".to_string(),
                 operate: Cpu6502::bcc,
-                addresmode: Cpu6502::rel,
+                addresmode: AddrMode::Rel,
                 cyles: 2,
             },
             Instruction {
                 name: "STA".to_string(),
                 operate: Cpu6502::sta,
-                addresmode: Cpu6502::izy,
+                addresmode: AddrMode::Izy,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "STY".to_string(),
                 operate: Cpu6502::sty,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "STA".to_string(),
                 operate: Cpu6502::sta,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "STX".to_string(),
                 operate: Cpu6502::stx,
-                addresmode: Cpu6502::zpy,
+                addresmode: AddrMode::Zpy,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "TYA".to_string(),
                 operate: Cpu6502::tya,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "STA".to_string(),
                 operate: Cpu6502::sta,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 5,
             },
             Instruction {
                 name: "TXS".to_string(),
                 operate: Cpu6502::txs,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "STA".to_string(),
                 operate: Cpu6502::sta,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "LDY".to_string(),
                 operate: Cpu6502::ldy,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "LDA".to_string(),
                 operate: Cpu6502::lda,
-                addresmode: Cpu6502::izx,
+                addresmode: AddrMode::Izx,
                 cyles: 6,
             },
             Instruction {
                 name: "LDX".to_string(),
                 operate: Cpu6502::ldx,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "LDY".to_string(),
                 operate: Cpu6502::ldy,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "LDA".to_string(),
                 operate: Cpu6502::lda,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "LDX".to_string(),
                 operate: Cpu6502::ldx,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 3,
             },
             Instruction {
                 name: "TAY".to_string(),
                 operate: Cpu6502::tay,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "LDA".to_string(),
                 operate: Cpu6502::lda,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "TAX".to_string(),
                 operate: Cpu6502::tax,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "LDY".to_string(),
                 operate: Cpu6502::ldy,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "LDA".to_string(),
                 operate: Cpu6502::lda,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "LDX".to_string(),
                 operate: Cpu6502::ldx,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "BCS".to_string(),
                 operate: Cpu6502::bcs,
-                addresmode: Cpu6502::rel,
+                addresmode: AddrMode::Rel,
                 cyles: 2,
             },
             Instruction {
                 name: "LDA".to_string(),
                 operate: Cpu6502::lda,
-                addresmode: Cpu6502::izy,
+                addresmode: AddrMode::Izy,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "LDY".to_string(),
                 operate: Cpu6502::ldy,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "LDA".to_string(),
                 operate: Cpu6502::lda,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "LDX".to_string(),
                 operate: Cpu6502::ldx,
-                addresmode: Cpu6502::zpy,
+                addresmode: AddrMode::Zpy,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "CLV".to_string(),
                 operate: Cpu6502::clv,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "LDA".to_string(),
                 operate: Cpu6502::lda,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 4,
             },
             Instruction {
                 name: "TSX".to_string(),
                 operate: Cpu6502::tsx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "LDY".to_string(),
                 operate: Cpu6502::ldy,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 4,
             },
             Instruction {
                 name: "LDA".to_string(),
                 operate: Cpu6502::lda,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 4,
             },
             Instruction {
                 name: "LDX".to_string(),
                 operate: Cpu6502::ldx,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 4,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "CPY".to_string(),
                 operate: Cpu6502::cpy,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "CMP".to_string(),
                 operate: Cpu6502::cmp,
-                addresmode: Cpu6502::izx,
+                addresmode: AddrMode::Izx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "CPY".to_string(),
                 operate: Cpu6502::cpy,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "CMP".to_string(),
                 operate: Cpu6502::cmp,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "DEC".to_string(),
                 operate: Cpu6502::dec,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "INY".to_string(),
                 operate: Cpu6502::iny,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "CMP".to_string(),
                 operate: Cpu6502::cmp,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "DEX".to_string(),
                 operate: Cpu6502::dex,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "CPY".to_string(),
                 operate: Cpu6502::cpy,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "CMP".to_string(),
                 operate: Cpu6502::cmp,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "DEC".to_string(),
                 operate: Cpu6502::dec,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "BNE".to_string(),
                 operate: Cpu6502::bne,
-                addresmode: Cpu6502::rel,
+                addresmode: AddrMode::Rel,
                 cyles: 2,
             },
             Instruction {
                 name: "CMP".to_string(),
                 operate: Cpu6502::cmp,
-                addresmode: Cpu6502::izy,
+                addresmode: AddrMode::Izy,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "CMP".to_string(),
                 operate: Cpu6502::cmp,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "DEC".to_string(),
                 operate: Cpu6502::dec,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "CLD".to_string(),
                 operate: Cpu6502::cld,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "CMP".to_string(),
                 operate: Cpu6502::cmp,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 4,
             },
             Instruction {
                 name: "NOP".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "CMP".to_string(),
                 operate: Cpu6502::cmp,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 4,
             },
             Instruction {
                 name: "DEC".to_string(),
                 operate: Cpu6502::dec,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "CPX".to_string(),
                 operate: Cpu6502::cpx,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "SBC".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::izx,
+                addresmode: AddrMode::Izx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "CPX".to_string(),
                 operate: Cpu6502::cpx,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "SBC".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 3,
             },
             Instruction {
                 name: "INC".to_string(),
                 operate: Cpu6502::inc,
-                addresmode: Cpu6502::zp0,
+                addresmode: AddrMode::Zp0,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 5,
             },
             Instruction {
                 name: "INX".to_string(),
                 operate: Cpu6502::inx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "SBC".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::imm,
+                addresmode: AddrMode::Imm,
                 cyles: 2,
             },
             Instruction {
                 name: "NOP".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "CPX".to_string(),
                 operate: Cpu6502::cpx,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "SBC".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 4,
             },
             Instruction {
                 name: "INC".to_string(),
                 operate: Cpu6502::inc,
-                addresmode: Cpu6502::abs,
+                addresmode: AddrMode::Abs,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "BEQ".to_string(),
                 operate: Cpu6502::beq,
-                addresmode: Cpu6502::rel,
+                addresmode: AddrMode::Rel,
                 cyles: 2,
             },
             Instruction {
                 name: "SBC".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::izy,
+                addresmode: AddrMode::Izy,
                 cyles: 5,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 8,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "SBC".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 4,
             },
             Instruction {
                 name: "INC".to_string(),
                 operate: Cpu6502::inc,
-                addresmode: Cpu6502::zpx,
+                addresmode: AddrMode::Zpx,
                 cyles: 6,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 6,
             },
             Instruction {
                 name: "SED".to_string(),
                 operate: Cpu6502::sed,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "SBC".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::aby,
+                addresmode: AddrMode::Aby,
                 cyles: 4,
             },
             Instruction {
                 name: "NOP".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 2,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::nop,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 4,
             },
             Instruction {
                 name: "SBC".to_string(),
                 operate: Cpu6502::sbc,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 4,
             },
             Instruction {
                 name: "INC".to_string(),
                 operate: Cpu6502::inc,
-                addresmode: Cpu6502::abx,
+                addresmode: AddrMode::Abx,
                 cyles: 7,
             },
             Instruction {
                 name: "???".to_string(),
                 operate: Cpu6502::xxx,
-                addresmode: Cpu6502::imp,
+                addresmode: AddrMode::Imp,
                 cyles: 7,
             },
         ];
@@ -1659,6 +1726,7 @@ impl Cpu6502 {
             addr_rel: 0x00,
             opcode: 0x00,
             cycles: 0x00,
+            clock_count: 0,
             lookup: loukup_table,
         }
     }
@@ -1669,25 +1737,36 @@ impl Cpu6502 {
     // }
 
     // Perform one clock cycle's worth of update
-    pub(crate) fn clock(&mut self) {
+    pub fn clock(&mut self) {
         if self.cycles == 0 {
+            if let Some(mut cdl) = self.bus.cdl.take() {
+                cdl.log_instruction(self);
+                self.bus.cdl = Some(cdl);
+            }
+
             self.opcode = self.read(self.pc);
             self.pc += 1;
 
             let instru: &Instruction = &self.lookup[self.opcode as usize];
             self.cycles = instru.cyles.clone();
-            let addtion_cycle_1 = (instru.addresmode)(self);
+            let addtion_cycle_1 = self.address(instru.addresmode);
             let instru2: &Instruction = &self.lookup[self.opcode as usize];
             let addtion_cycle_2 = (instru2.operate)(self);
 
             self.opcode += addtion_cycle_1 & addtion_cycle_2
         }
 
+        // Until there is an APU the machine is silent
+        if let Some(audio) = self.bus.audio.as_mut() {
+            audio.clock(0.0);
+        }
+
+        self.clock_count += 1;
         self.cycles -= 1;
     }
 
     // Reset Interrupt - Forces CPU into known state
-    pub(crate) fn reset(&mut self) {
+    pub fn reset(&mut self) {
         self.a = 0;
         self.x = 0;
         self.y = 0;
@@ -1706,22 +1785,32 @@ impl Cpu6502 {
 
         self.cycles = 8;
     }
+    // Power On - Internal RAM and the pads start cleared, then a reset. The
+    // clock starts from zero too so frames line up from one run to the next.
+    pub fn power_on(&mut self) {
+        self.bus.ram[..0x0800].fill(0);
+        for pad in self.bus.controllers.iter_mut() {
+            *pad = Controller::new();
+        }
+        self.clock_count = 0;
+        self.reset();
+    }
     // Interrupt Request - Executes an instruction at a specific location
-    pub(crate) fn irq(&mut self) {
+    pub fn irq(&mut self) {
         if self.get_flag(Flags6502::I) == 0 {
             self.write(
                 &(0x0100 + self.sp as u16),
                 &(((self.pc >> 8) & 0x00FF) as u8),
             );
-            self.sp -= 1;
+            self.sp = self.sp.wrapping_sub(1);
             self.write(&(0x0100 + self.sp as u16), &((self.pc & 0x00FF) as u8));
-            self.sp -= 1;
+            self.sp = self.sp.wrapping_sub(1);
 
             self.set_flag(Flags6502::B, false);
             self.set_flag(Flags6502::U, true);
             self.set_flag(Flags6502::I, true);
             self.write(&(0x0100 + self.sp as u16), &self.sr.clone());
-            self.sp -= 1;
+            self.sp = self.sp.wrapping_sub(1);
 
             self.addr_abs = 0xFFFE;
             let lo: u16 = self.read(self.addr_abs + 0) as u16;
@@ -1732,20 +1821,20 @@ impl Cpu6502 {
         }
     }
     // Non-Maskable Interrupt Request - As above, but cannot be disabled
-    pub(crate) fn nmi(&mut self) {
+    pub fn nmi(&mut self) {
         self.write(
             &(0x0100 + self.sp as u16),
             &(((self.pc >> 8) & 0x00FF) as u8),
         );
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
         self.write(&(0x0100 + self.sp as u16), &((self.pc & 0x00FF) as u8));
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
 
         self.set_flag(Flags6502::B, false);
         self.set_flag(Flags6502::U, true);
         self.set_flag(Flags6502::I, true);
         self.write(&(0x0100 + self.sp as u16), &self.sr.clone());
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
 
         self.addr_abs = 0xFFFA;
         let lo: u16 = self.read(self.addr_abs + 0) as u16;
@@ -1757,7 +1846,7 @@ impl Cpu6502 {
 
     ///////////////////////////////////////////////////////////////////////////////
     // BUS CONNECTIVITY
-    pub(crate) fn read(&self, addre: u16) -> u8 {
+    pub fn read(&self, addre: u16) -> u8 {
         self.bus.read(&addre, false)
     }
 
@@ -1795,6 +1884,24 @@ impl Cpu6502 {
     // a flag saying it has potential, as does each instruction. If both instruction
     // and address function return 1, then an additional clock cycle is required.
 
+    // Runs the addressing mode function that goes with the mode
+    fn address(&mut self, mode: AddrMode) -> u8 {
+        match mode {
+            AddrMode::Imp => self.imp(),
+            AddrMode::Imm => self.imm(),
+            AddrMode::Zp0 => self.zp0(),
+            AddrMode::Zpx => self.zpx(),
+            AddrMode::Zpy => self.zpy(),
+            AddrMode::Rel => self.rel(),
+            AddrMode::Abs => self.abs(),
+            AddrMode::Abx => self.abx(),
+            AddrMode::Aby => self.aby(),
+            AddrMode::Ind => self.ind(),
+            AddrMode::Izx => self.izx(),
+            AddrMode::Izy => self.izy(),
+        }
+    }
+
     // Address Mode: Implied
     // There is no additional data required for this instruction. The instruction
     // does something very simple like like sets a status bit. However, we will
@@ -2005,7 +2112,7 @@ impl Cpu6502 {
     // is a variable global to the CPU, and is set by calling this
     // function. It also returns it for convenience.
     fn fetch(&mut self) -> u8 {
-        if (self.lookup[self.opcode as usize].addresmode) as usize != (Cpu6502::imp) as usize {
+        if self.lookup[self.opcode as usize].addresmode != AddrMode::Imp {
             self.fetched = self.read(self.addr_abs);
         }
         self.fetched
@@ -2184,7 +2291,7 @@ impl Cpu6502 {
         self.set_flag(Flags6502::C, (self.temp & 0xFF00) > 0);
         self.set_flag(Flags6502::Z, (self.temp & 0x00FF) == 0x00);
         self.set_flag(Flags6502::N, (self.temp & 0x80) != 0);
-        if self.lookup[self.opcode as usize].addresmode as usize == Cpu6502::imp as usize {
+        if self.lookup[self.opcode as usize].addresmode == AddrMode::Imp {
             self.a = (self.temp & 0x00FF) as u8;
         } else {
             let addr = &self.addr_abs.clone();
@@ -2300,21 +2407,21 @@ impl Cpu6502 {
     // Function:    Program Sourced Interrupt
 
     fn brk(&mut self) -> u8 {
-        self.pc += 1;
-
+        // The byte after BRK was skipped as its immediate operand, so pc
+        // already holds BRK+2, the address RTI comes back to
         self.set_flag(Flags6502::I, true);
         self.write(
             &(0x0100 + self.sp as u16),
             &(((self.pc >> 8) & 0x00FF) as u8),
         );
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
         self.write(&(0x0100 + self.sp as u16), &((self.pc & 0x00FF) as u8));
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
 
         self.set_flag(Flags6502::B, true);
 
         self.write(&(0x0100 + self.sp as u16), &self.sr.clone());
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
         self.set_flag(Flags6502::B, false);
 
         self.pc = self.read(0xFFFE) as u16 | ((self.read(0xFFFF) as u16) << 8);
@@ -2508,9 +2615,9 @@ impl Cpu6502 {
             &(0x0100 + self.sp as u16),
             &(((self.pc >> 8) & 0x00FF) as u8),
         );
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
         self.write(&(0x0100 + self.sp as u16), &((self.pc & 0x00FF) as u8));
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
 
         self.pc = self.addr_abs;
         return 0;
@@ -2555,7 +2662,7 @@ impl Cpu6502 {
         self.temp = (self.fetched >> 1) as u16;
         self.set_flag(Flags6502::Z, (self.temp & 0x00FF) == 0x0000);
         self.set_flag(Flags6502::N, (self.temp & 0x0080) != 0);
-        if self.lookup[self.opcode as usize].addresmode as usize == Cpu6502::imp as usize {
+        if self.lookup[self.opcode as usize].addresmode == AddrMode::Imp {
             self.a = (self.temp & 0x00FF) as u8;
         } else {
             self.write(&self.addr_abs.clone(), &((self.temp & 0x00FF) as u8));
@@ -2595,7 +2702,7 @@ impl Cpu6502 {
 
     fn pha(&mut self) -> u8 {
         self.write(&(0x0100 + self.sp as u16), &self.a.clone());
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
         return 0;
     }
 
@@ -2610,7 +2717,7 @@ impl Cpu6502 {
         );
         self.set_flag(Flags6502::B, false);
         self.set_flag(Flags6502::U, false);
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
         return 0;
     }
 
@@ -2619,7 +2726,7 @@ impl Cpu6502 {
     // Flags Out:   N, Z
 
     fn pla(&mut self) -> u8 {
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         self.a = self.read(0x0100 + self.sp as u16);
         self.set_flag(Flags6502::Z, self.a == 0x00);
         self.set_flag(Flags6502::N, (self.a & 0x80) != 0x00);
@@ -2630,7 +2737,7 @@ impl Cpu6502 {
     // Function:    Status <- stack
 
     fn plp(&mut self) -> u8 {
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         self.sr = self.read(0x0100 + self.sp as u16);
         self.set_flag(Flags6502::U, true);
         return 0;
@@ -2642,7 +2749,7 @@ impl Cpu6502 {
         self.set_flag(Flags6502::C, (self.temp & 0xFF00) != 0);
         self.set_flag(Flags6502::Z, (self.temp & 0x00FF) == 0x0000);
         self.set_flag(Flags6502::N, (self.temp & 0x0080) != 0);
-        if self.lookup[self.opcode as usize].addresmode as usize == Cpu6502::imp as usize {
+        if self.lookup[self.opcode as usize].addresmode == AddrMode::Imp {
             self.a = (self.temp & 0x00FF) as u8;
         } else {
             self.write(&self.addr_abs.clone(), &((self.temp & 0x00FF) as u8));
@@ -2656,7 +2763,7 @@ impl Cpu6502 {
         self.set_flag(Flags6502::C, (self.fetched & 0x01) != 0);
         self.set_flag(Flags6502::Z, (self.temp & 0x00FF) == 0x00);
         self.set_flag(Flags6502::N, (self.temp & 0x0080) != 0);
-        if self.lookup[self.opcode as usize].addresmode as usize == Cpu6502::imp as usize {
+        if self.lookup[self.opcode as usize].addresmode == AddrMode::Imp {
             self.a = (self.temp & 0x00FF) as u8;
         } else {
             self.write(&self.addr_abs.clone(), &((self.temp & 0x00FF) as u8));
@@ -2665,22 +2772,22 @@ impl Cpu6502 {
     }
 
     fn rti(&mut self) -> u8 {
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         self.sr = self.read(0x0100 + self.sp as u16);
         self.sr &= !Flags6502::B;
         self.sr &= !Flags6502::U;
 
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         self.pc = self.read(0x0100 + self.sp as u16) as u16;
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         self.pc |= (self.read(0x0100 + self.sp as u16) as u16) << 8;
         return 0;
     }
 
     fn rts(&mut self) -> u8 {
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         self.pc = self.read(0x0100 + self.sp as u16) as u16;
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         self.pc |= (self.read(0x0100 + self.sp as u16) as u16) << 8;
 
         self.pc += 1;
@@ -2794,7 +2901,7 @@ impl Cpu6502 {
     ///////////////////////////////////////////////////////////////////////////////
     // HELPER FUNCTIONS
 
-    fn hex(n: u32, d: u8) -> String {
+    pub fn hex(n: u32, d: u8) -> String {
         let char_arra: [char; 16] = [
             '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F',
         ];
@@ -2807,120 +2914,166 @@ impl Cpu6502 {
         return sf;
     }
 
-    pub(crate) fn complete(&self) -> bool {
+    pub fn complete(&self) -> bool {
         return self.cycles == 0;
     }
 
-    pub(crate) fn disassemble(&self, n_start: u16, n_stop: u16) -> HashMap<u16, String> {
-        let mut addr: u32 = n_start.clone() as u32;
+    // Mnemonic of the instruction an opcode decodes to, "???" for illegal ones
+    pub fn instruction_name(&self, opcode: u8) -> &str {
+        &self.lookup[opcode as usize].name
+    }
 
-        let mut value: u8;
-        let mut hi: u8;
-        let mut lo: u8;
-        let mut map_lines: HashMap<u16, String> = HashMap::new();
-        let mut v_s: String;
+    // Number of bytes the instruction occupies in memory, opcode included
+    pub fn instruction_length(&self, opcode: u8) -> u16 {
+        1 + self.lookup[opcode as usize].addresmode.operand_size()
+    }
 
-        while addr <= n_stop as u32 {
-            let line_addr = addr.clone() as u16;
-            let opcode = self.bus.read(&(addr as u16), true) as usize;
-            let mut s_inst: String = format!(
-                "${}: {} ",
-                Cpu6502::hex(addr as u32, 4),
-                self.lookup[opcode.clone()].name
-            );
-            let addremode = self.lookup[opcode.clone()].addresmode as usize;
-            addr += 1;
-
-            if addremode == Cpu6502::imp as usize {
-                s_inst = s_inst.add(" {IMP}");
-            } else if addremode == Cpu6502::imm as usize {
-                value = self.read(addr as u16);
-                addr += 1;
-                v_s = format!("#${} {{IMM}}", Cpu6502::hex(value as u32, 2));
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::zp0 as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                // hi = 0x00;
-                v_s = format!("${} {{ZP0}}", Cpu6502::hex(lo as u32, 2));
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::zpx as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                // hi = 0x00;
-                v_s = format!("${}, X {{ZPX}}", Cpu6502::hex(lo as u32, 2));
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::zpy as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                // hi = 0x00;
-                v_s = format!("${}, Y {{ZPY}}", Cpu6502::hex(lo as u32, 2));
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::izx as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                // hi = 0x00;
-                v_s = format!("(${}, X) {{IZX}}", Cpu6502::hex(lo as u32, 2));
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::izy as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                // hi = 0x00;
-                v_s = format!("(${}), Y {{IZY}}", Cpu6502::hex(lo as u32, 2));
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::abs as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                hi = self.read(addr as u16);
-                addr += 1;
-                v_s = format!("${} {{ABS}}", Cpu6502::hex((hi as u32) << 8 | lo as u32, 4));
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::abx as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                hi = self.read(addr as u16);
-                addr += 1;
-                v_s = format!(
-                    "${}, X {{ABX}}",
-                    Cpu6502::hex((hi as u32) << 8 | lo as u32, 4)
-                );
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::aby as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                hi = self.read(addr as u16);
-                addr += 1;
-                v_s = format!(
-                    "${}, Y {{ABY}}",
-                    Cpu6502::hex((hi as u32) << 8 | lo as u32, 4)
-                );
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::ind as usize {
-                lo = self.read(addr as u16);
-                addr += 1;
-                hi = self.read(addr as u16);
-                addr += 1;
-                v_s = format!(
-                    "(${}) {{IND}}",
-                    Cpu6502::hex((hi as u32) << 8 | lo as u32, 4)
-                );
-                s_inst = s_inst.add(&v_s);
-            } else if addremode == Cpu6502::rel as usize {
-                value = self.read(addr as u16);
-                addr += 1;
-                v_s = format!(
-                    "${} [${}] {{REL}}",
-                    Cpu6502::hex(value as u32, 2),
-                    Cpu6502::hex(addr + value as u32, 4)
-                );
-                s_inst = s_inst.add(&v_s);
-            } else {
-                panic!("not enter ifs")
+    pub fn addressing_mode(&self, opcode: u8) -> AddrMode {
+        self.lookup[opcode as usize].addresmode
+    }
+
+    // Finds the opcode of a mnemonic in an addressing mode
+    pub fn find_opcode(&self, name: &str, mode: AddrMode) -> Option<u8> {
+        // Like the original table, $DA and $FA are undocumented opcodes that
+        // are named NOP too, the real one is $EA
+        if name == "NOP" && mode == AddrMode::Imp {
+            return Some(0xEA);
+        }
+        self.lookup
+            .iter()
+            .position(|i| i.name == name && i.addresmode == mode)
+            .map(|opcode| opcode as u8)
+    }
+
+    // Works out the address the instruction waiting at pc will operate on,
+    // using the current registers but without touching the cpu state or
+    // reading the bus with side effects. Implied, immediate and relative
+    // modes have no memory operand, so they return None.
+    pub fn operand_address(&self) -> Option<u16> {
+        let peek = |addr: u16| self.bus.read(&addr, true) as u16;
+
+        let lo = peek(self.pc.wrapping_add(1));
+        let hi = peek(self.pc.wrapping_add(2));
+
+        match self.lookup[peek(self.pc) as usize].addresmode {
+            AddrMode::Zp0 => Some(lo),
+            AddrMode::Zpx => Some((lo + self.x as u16) & 0x00FF),
+            AddrMode::Zpy => Some((lo + self.y as u16) & 0x00FF),
+            AddrMode::Abs => Some((hi << 8) | lo),
+            AddrMode::Abx => Some(((hi << 8) | lo).wrapping_add(self.x as u16)),
+            AddrMode::Aby => Some(((hi << 8) | lo).wrapping_add(self.y as u16)),
+            AddrMode::Ind => {
+                // Same page boundary hardware bug as the real addressing mode
+                let ptr = (hi << 8) | lo;
+                let ptr_hi = (ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF);
+                Some((peek(ptr_hi) << 8) | peek(ptr))
+            }
+            AddrMode::Izx => {
+                let t = lo + self.x as u16;
+                Some((peek((t + 1) & 0x00FF) << 8) | peek(t & 0x00FF))
+            }
+            AddrMode::Izy => {
+                let base = (peek((lo + 1) & 0x00FF) << 8) | peek(lo);
+                Some(base.wrapping_add(self.y as u16))
             }
+            AddrMode::Imp | AddrMode::Imm | AddrMode::Rel => None,
+        }
+    }
 
-            map_lines.insert(line_addr, s_inst);
+    // Decodes the instruction at addr. Memory is read through the peek path
+    // so disassembling never disturbs the machine.
+    pub fn decode(&self, addr: u16) -> DisassembledInstruction {
+        let opcode = self.bus.read(&addr, true);
+        let instru = &self.lookup[opcode as usize];
+        let length = 1 + instru.addresmode.operand_size();
+
+        let bytes: Vec<u8> = (0..length)
+            .map(|i| self.bus.read(&addr.wrapping_add(i), true))
+            .collect();
+        let operand = match length {
+            2 => bytes[1] as u16,
+            3 => (bytes[2] as u16) << 8 | bytes[1] as u16,
+            _ => 0,
+        };
+
+        let target = match instru.addresmode {
+            AddrMode::Imp | AddrMode::Imm => None,
+            // The offset is signed and counts from the next instruction
+            AddrMode::Rel => Some(addr.wrapping_add(2).wrapping_add(operand as i8 as u16)),
+            _ => Some(operand),
+        };
+
+        DisassembledInstruction {
+            address: addr,
+            bytes,
+            mnemonic: instru.name.clone(),
+            mode: instru.addresmode,
+            operand,
+            target,
+            cycles: instru.cyles,
+            illegal: instru.name == "???",
+        }
+    }
+
+    // Decodes every instruction from n_start up to n_stop, stepping over
+    // operands, keyed and ordered by address
+    pub fn disassemble(&self, n_start: u16, n_stop: u16) -> BTreeMap<u16, DisassembledInstruction> {
+        let mut addr: u32 = n_start as u32;
+        let mut map_lines: BTreeMap<u16, DisassembledInstruction> = BTreeMap::new();
+
+        while addr <= n_stop as u32 {
+            let instruction = self.decode(addr as u16);
+            addr += instruction.bytes.len() as u32;
+            map_lines.insert(instruction.address, instruction);
         }
 
         map_lines
     }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // SAVE STATES
+
+    // Everything needed to carry on mid instruction, the lookup table is
+    // rebuilt by new() so it is not saved
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.chunk(b"CPU ", |c| {
+            c.u8(self.a);
+            c.u8(self.x);
+            c.u8(self.y);
+            c.u16(self.pc);
+            c.u8(self.sp);
+            c.u8(self.sr);
+            c.u8(self.fetched);
+            c.u16(self.temp);
+            c.u16(self.addr_abs);
+            c.u16(self.addr_rel);
+            c.u8(self.opcode);
+            c.u8(self.cycles);
+            c.u64(self.clock_count);
+        });
+    }
+
+    // Reads the whole chunk before touching anything, so a bad state
+    // leaves the cpu as it was
+    pub fn load_state(&mut self, state: &StateReader) -> Result<(), String> {
+        let mut c = state.chunk(b"CPU ")?;
+        let (a, x, y, pc, sp, sr) = (c.u8()?, c.u8()?, c.u8()?, c.u16()?, c.u8()?, c.u8()?);
+        let (fetched, temp, addr_abs, addr_rel) = (c.u8()?, c.u16()?, c.u16()?, c.u16()?);
+        let (opcode, cycles, clock_count) = (c.u8()?, c.u8()?, c.u64()?);
+
+        self.a = a;
+        self.x = x;
+        self.y = y;
+        self.pc = pc;
+        self.sp = sp;
+        self.sr = sr;
+        self.fetched = fetched;
+        self.temp = temp;
+        self.addr_abs = addr_abs;
+        self.addr_rel = addr_rel;
+        self.opcode = opcode;
+        self.cycles = cycles;
+        self.clock_count = clock_count;
+        Ok(())
+    }
 }
@@ -0,0 +1,623 @@
+use crate::battery::BatterySave;
+use crate::call_stack::{CallStack, StackProblem};
+use crate::cheats::CheatList;
+use crate::cpu_6502::Cpu6502;
+use crate::expression::Expression;
+use crate::memory_editor::{AddressSpace, Freeze};
+use crate::movie::MovieSession;
+use crate::profiler::Profiler;
+use crate::rewind::Rewind;
+use crate::trace_logger::TraceLogger;
+use crate::video_recorder::VideoRecorder;
+
+// NTSC timing: 341 dots x 262 scanlines per frame with three PPU dots per
+// CPU cycle, rounded up to whole CPU cycles
+pub const CPU_CYCLES_PER_FRAME: u64 = 29781;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+pub struct Breakpoint {
+    pub id: u32,
+    pub addr: u16,
+    // Only stop when this evaluates to non zero
+    pub condition: Option<Expression>,
+    pub enabled: bool,
+}
+
+pub struct Watchpoint {
+    pub id: u32,
+    // Inclusive address range being watched
+    pub start: u16,
+    pub end: u16,
+    pub kind: AccessKind,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopReason {
+    // A single instruction was stepped
+    Step,
+    Breakpoint(u32),
+    Watchpoint {
+        id: u32,
+        addr: u16,
+        kind: AccessKind,
+    },
+    // Run to cursor or step over reached its target address
+    Reached(u16),
+    // Step out executed the RTS/RTI leaving the current routine
+    Returned,
+    // Execution arrived at the start of the NMI handler
+    Nmi,
+    Frame,
+    // The shadow call stack caught a bad return or a stack wrap, only when
+    // stopping on those is turned on
+    Stack(StackProblem),
+}
+
+// What the debugger is doing while the cpu runs
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum RunMode {
+    Step,
+    Continue,
+    StepOver { addr: u16, sp: u8 },
+    StepOut { sp: u8 },
+    RunTo(u16),
+    UntilNmi,
+    UntilFrame(u64),
+}
+
+// Drives a Cpu6502 an instruction at a time and decides when to stop. The
+// frontends pick a run mode (step, continue, step over...) and then call
+// update() regularly, giving it a budget of cycles, until it reports why
+// execution stopped.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_id: u32,
+    mode: Option<RunMode>,
+    // Breakpoints are ignored for the first instruction of a run, otherwise
+    // resuming from one would stop straight away on the same address
+    started: bool,
+    // Logs every instruction update() executes when set
+    trace: Option<TraceLogger>,
+    profiler: Option<Profiler>,
+    rewind: Option<Rewind>,
+    movie: Option<MovieSession>,
+    video: Option<VideoRecorder>,
+    call_stack: CallStack,
+    stop_on_stack_problems: bool,
+    freezes: Vec<Freeze>,
+    cheats: CheatList,
+    battery: Option<BatterySave>,
+    // Cycles update() has run the cpu for. Unlike the cpu's clock this keeps
+    // counting through a power on from a movie
+    elapsed: u64,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_id: 1,
+            mode: None,
+            started: false,
+            trace: None,
+            profiler: None,
+            rewind: None,
+            movie: None,
+            video: None,
+            call_stack: CallStack::new(),
+            stop_on_stack_problems: false,
+            freezes: Vec::new(),
+            cheats: CheatList::new(),
+            battery: None,
+            elapsed: 0,
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // BREAKPOINTS AND WATCHPOINTS
+
+    pub fn add_breakpoint(&mut self, addr: u16, condition: Option<Expression>) -> u32 {
+        let id = self.take_id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr,
+            condition,
+            enabled: true,
+        });
+        id
+    }
+
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, kind: AccessKind) -> u32 {
+        let id = self.take_id();
+        self.watchpoints.push(Watchpoint {
+            id,
+            start: start.min(end),
+            end: start.max(end),
+            kind,
+            enabled: true,
+        });
+        id
+    }
+
+    // Removes a breakpoint or watchpoint, returns false if the id is unknown
+    pub fn remove(&mut self, id: u32) -> bool {
+        let count = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.watchpoints.retain(|w| w.id != id);
+        count != self.breakpoints.len() + self.watchpoints.len()
+    }
+
+    pub fn set_enabled(&mut self, id: u32, enabled: bool) -> bool {
+        if let Some(b) = self.breakpoints.iter_mut().find(|b| b.id == id) {
+            b.enabled = enabled;
+            true
+        } else if let Some(w) = self.watchpoints.iter_mut().find(|w| w.id == id) {
+            w.enabled = enabled;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Adds an unconditional breakpoint at addr, or removes every breakpoint
+    // at addr if there already is one
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if self.has_breakpoint(addr) {
+            self.breakpoints.retain(|b| b.addr != addr);
+        } else {
+            self.add_breakpoint(addr, None);
+        }
+    }
+
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.iter().any(|b| b.addr == addr)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    fn take_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // FROZEN MEMORY AND CHEATS
+
+    // Holds addr at value from now on, replacing any freeze already on it
+    pub fn freeze(&mut self, space: AddressSpace, addr: usize, value: u8) {
+        self.unfreeze(space, addr);
+        self.freezes.push(Freeze { space, addr, value });
+    }
+
+    pub fn unfreeze(&mut self, space: AddressSpace, addr: usize) -> bool {
+        let before = self.freezes.len();
+        self.freezes.retain(|f| f.space != space || f.addr != addr);
+        self.freezes.len() != before
+    }
+
+    pub fn clear_freezes(&mut self) {
+        self.freezes.clear();
+    }
+
+    pub fn freezes(&self) -> &[Freeze] {
+        &self.freezes
+    }
+
+    pub fn is_frozen(&self, space: AddressSpace, addr: usize) -> bool {
+        self.freezes
+            .iter()
+            .any(|f| f.space == space && f.addr == addr)
+    }
+
+    // Cheats are applied along with the freezes
+    pub fn cheats(&self) -> &CheatList {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut CheatList {
+        &mut self.cheats
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // CALL STACK
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    // The shadow stack cannot see where the cpu was reset from, so the
+    // frontends clear it along with the reset
+    pub fn clear_call_stack(&mut self) {
+        self.call_stack.clear();
+    }
+
+    pub fn set_stop_on_stack_problems(&mut self, stop: bool) {
+        self.stop_on_stack_problems = stop;
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // TRACE LOGGING, PROFILING, REWIND, MOVIES AND VIDEO
+
+    // Replaces the trace logger, returning the old one so its ring buffer can
+    // still be looked at
+    pub fn set_trace(&mut self, trace: Option<TraceLogger>) -> Option<TraceLogger> {
+        std::mem::replace(&mut self.trace, trace)
+    }
+
+    pub fn trace(&self) -> Option<&TraceLogger> {
+        self.trace.as_ref()
+    }
+
+    pub fn trace_mut(&mut self) -> Option<&mut TraceLogger> {
+        self.trace.as_mut()
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        std::mem::replace(&mut self.profiler, profiler)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn set_rewind(&mut self, rewind: Option<Rewind>) {
+        self.rewind = rewind;
+    }
+
+    pub fn rewind(&self) -> Option<&Rewind> {
+        self.rewind.as_ref()
+    }
+
+    // Goes back to the start of the frame before, false when the rewind
+    // buffer is off or used up
+    pub fn step_back(&mut self, cpu: &mut Cpu6502) -> Result<bool, String> {
+        let rewind = match self.rewind.as_mut() {
+            Some(rewind) => rewind,
+            None => return Ok(false),
+        };
+        self.mode = None;
+        // Where the calls were is not part of the state
+        self.call_stack.clear();
+        let stepped = rewind.step_back(cpu)?;
+        if let Some(movie) = self.movie.as_mut() {
+            movie.rewound(cpu);
+        }
+        Ok(stepped)
+    }
+
+    // Replaces the movie being recorded or played, returning the old one so
+    // a recording can be saved
+    pub fn set_movie(&mut self, movie: Option<MovieSession>) -> Option<MovieSession> {
+        std::mem::replace(&mut self.movie, movie)
+    }
+
+    pub fn movie(&self) -> Option<&MovieSession> {
+        self.movie.as_ref()
+    }
+
+    pub fn movie_mut(&mut self) -> Option<&mut MovieSession> {
+        self.movie.as_mut()
+    }
+
+    // Replaces the video recorder, returning the old one so it can be
+    // finished
+    pub fn set_video(&mut self, video: Option<VideoRecorder>) -> Option<VideoRecorder> {
+        std::mem::replace(&mut self.video, video)
+    }
+
+    pub fn video(&self) -> Option<&VideoRecorder> {
+        self.video.as_ref()
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // BATTERY SAVES
+
+    // Replaces the battery save, returning the old one so it can be
+    // flushed
+    pub fn set_battery(&mut self, battery: Option<BatterySave>) -> Option<BatterySave> {
+        std::mem::replace(&mut self.battery, battery)
+    }
+
+    pub fn battery(&self) -> Option<&BatterySave> {
+        self.battery.as_ref()
+    }
+
+    pub fn battery_mut(&mut self) -> Option<&mut BatterySave> {
+        self.battery.as_mut()
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // RUN CONTROL
+
+    pub fn is_running(&self) -> bool {
+        self.mode.is_some()
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.start(RunMode::Continue);
+    }
+
+    // Runs the next instruction, a JSR is treated as a single instruction
+    // and the whole subroutine runs until it returns
+    pub fn step_over(&mut self, cpu: &Cpu6502) {
+        let opcode = cpu.bus.read(&cpu.pc, true);
+        if cpu.instruction_name(opcode) == "JSR" {
+            self.start(RunMode::StepOver {
+                addr: cpu.pc.wrapping_add(3),
+                sp: cpu.sp,
+            });
+        } else {
+            self.start(RunMode::Step);
+        }
+    }
+
+    // Runs until the current routine executes the RTS (or RTI) that returns
+    // to its caller
+    pub fn step_out(&mut self, cpu: &Cpu6502) {
+        self.start(RunMode::StepOut { sp: cpu.sp });
+    }
+
+    pub fn run_to(&mut self, addr: u16) {
+        self.start(RunMode::RunTo(addr));
+    }
+
+    pub fn run_until_nmi(&mut self) {
+        self.start(RunMode::UntilNmi);
+    }
+
+    pub fn run_frame(&mut self, cpu: &Cpu6502) {
+        let frame = cpu.clock_count / CPU_CYCLES_PER_FRAME;
+        self.start(RunMode::UntilFrame((frame + 1) * CPU_CYCLES_PER_FRAME));
+    }
+
+    // Executes exactly one instruction right away
+    pub fn step(&mut self, cpu: &mut Cpu6502) -> StopReason {
+        self.start(RunMode::Step);
+        self.update(cpu, u64::MAX).unwrap_or(StopReason::Step)
+    }
+
+    fn start(&mut self, mode: RunMode) {
+        self.mode = Some(mode);
+        self.started = false;
+    }
+
+    // Lets the cpu run in the current mode for at most max_cycles. Returns
+    // why it stopped, or None if it is still running (or was never started).
+    pub fn update(&mut self, cpu: &mut Cpu6502, max_cycles: u64) -> Option<StopReason> {
+        let mode = self.mode?;
+
+        // Finish whatever is left of a reset or interrupt sequence so we are
+        // sitting on an instruction boundary
+        let mut clock = cpu.clock_count;
+        while !cpu.complete() {
+            cpu.clock();
+        }
+        self.count(cpu, &mut clock);
+
+        let budget_end = self.elapsed.saturating_add(max_cycles);
+        while self.elapsed < budget_end {
+            if let Some(reason) = self.check_before(cpu, mode) {
+                self.mode = None;
+                return Some(reason);
+            }
+            self.started = true;
+
+            // Input goes in before anything looks at the instruction, and a
+            // reset from the movie is finished so we are back on a boundary
+            if let Some(movie) = self.movie.as_mut() {
+                movie.log(cpu);
+                while !cpu.complete() {
+                    cpu.clock();
+                }
+            }
+            // Frozen values go back before anything sees what was written
+            for freeze in &self.freezes {
+                freeze.apply(cpu);
+            }
+            self.cheats.apply(cpu);
+            if let Some(video) = self.video.as_mut() {
+                video.log(cpu);
+            }
+            if let Some(battery) = self.battery.as_mut() {
+                battery.log(cpu);
+            }
+            if let Some(trace) = self.trace.as_mut() {
+                trace.log(cpu);
+            }
+            if let Some(rewind) = self.rewind.as_mut() {
+                rewind.log(cpu);
+            }
+
+            self.call_stack.before(cpu);
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.log(cpu, &self.call_stack);
+            }
+
+            let opcode = cpu.bus.read(&cpu.pc, true);
+            let watch_hit = self.check_watchpoints(cpu);
+
+            cpu.clock();
+            while !cpu.complete() {
+                cpu.clock();
+            }
+            self.count(cpu, &mut clock);
+
+            let stack_problem = self
+                .call_stack
+                .after(cpu)
+                .filter(|_| self.stop_on_stack_problems)
+                .map(StopReason::Stack);
+            let reason = watch_hit
+                .or(stack_problem)
+                .or_else(|| Debugger::check_after(cpu, mode, opcode));
+            if reason.is_some() {
+                self.mode = None;
+                return reason;
+            }
+        }
+
+        None
+    }
+
+    // Adds the cycles run since the clock read last. A power on puts the
+    // clock back to 0, so then everything since it counts
+    fn count(&mut self, cpu: &Cpu6502, clock: &mut u64) {
+        self.elapsed += match cpu.clock_count.checked_sub(*clock) {
+            Some(cycles) => cycles,
+            None => cpu.clock_count,
+        };
+        *clock = cpu.clock_count;
+    }
+
+    // Total cycles update() has run the cpu for, power ons and all
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    // Conditions that stop the cpu before the instruction at pc executes
+    fn check_before(&self, cpu: &Cpu6502, mode: RunMode) -> Option<StopReason> {
+        if self.started {
+            let hit = self.breakpoints.iter().find(|b| {
+                b.enabled && b.addr == cpu.pc && b.condition.as_ref().is_none_or(|c| c.is_true(cpu))
+            });
+            if let Some(b) = hit {
+                return Some(StopReason::Breakpoint(b.id));
+            }
+        }
+
+        match mode {
+            RunMode::RunTo(addr) if cpu.pc == addr && self.started => {
+                Some(StopReason::Reached(addr))
+            }
+            RunMode::StepOver { addr, sp } if cpu.pc == addr && cpu.sp >= sp => {
+                Some(StopReason::Reached(addr))
+            }
+            RunMode::UntilNmi if cpu.pc == read_vector(cpu, 0xFFFA) && self.started => {
+                Some(StopReason::Nmi)
+            }
+            RunMode::UntilFrame(target) if cpu.clock_count >= target => Some(StopReason::Frame),
+            _ => None,
+        }
+    }
+
+    // Conditions that stop the cpu once an instruction has executed
+    fn check_after(cpu: &Cpu6502, mode: RunMode, opcode: u8) -> Option<StopReason> {
+        match mode {
+            RunMode::Step => Some(StopReason::Step),
+            RunMode::StepOut { sp } => {
+                let name = cpu.instruction_name(opcode);
+                if (name == "RTS" || name == "RTI") && cpu.sp > sp {
+                    Some(StopReason::Returned)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn check_watchpoints(&self, cpu: &Cpu6502) -> Option<StopReason> {
+        if self.watchpoints.iter().all(|w| !w.enabled) {
+            return None;
+        }
+
+        let accesses = memory_accesses(cpu);
+        for w in self.watchpoints.iter().filter(|w| w.enabled) {
+            if let Some((addr, kind)) = accesses
+                .iter()
+                .find(|(addr, kind)| *kind == w.kind && *addr >= w.start && *addr <= w.end)
+            {
+                return Some(StopReason::Watchpoint {
+                    id: w.id,
+                    addr: *addr,
+                    kind: *kind,
+                });
+            }
+        }
+        None
+    }
+}
+
+pub(crate) fn read_vector(cpu: &Cpu6502, addr: u16) -> u16 {
+    let lo = cpu.bus.read(&addr, true) as u16;
+    let hi = cpu.bus.read(&(addr + 1), true) as u16;
+    (hi << 8) | lo
+}
+
+// Every memory access the instruction waiting at pc is going to make, worked
+// out from its addressing mode and the current registers without running it
+pub fn memory_accesses(cpu: &Cpu6502) -> Vec<(u16, AccessKind)> {
+    let opcode = cpu.bus.read(&cpu.pc, true);
+    let mut accesses: Vec<(u16, AccessKind)> = vec![(cpu.pc, AccessKind::Execute)];
+
+    let stack = |offset: u8| 0x0100 + cpu.sp.wrapping_add(offset) as u16;
+    let name = cpu.instruction_name(opcode);
+    match name {
+        "STA" | "STX" | "STY" => {
+            if let Some(addr) = cpu.operand_address() {
+                accesses.push((addr, AccessKind::Write));
+            }
+        }
+        "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" => {
+            if let Some(addr) = cpu.operand_address() {
+                accesses.push((addr, AccessKind::Read));
+                accesses.push((addr, AccessKind::Write));
+            }
+        }
+        "LDA" | "LDX" | "LDY" | "ADC" | "SBC" | "AND" | "ORA" | "EOR" | "CMP" | "CPX" | "CPY"
+        | "BIT" => {
+            if let Some(addr) = cpu.operand_address() {
+                accesses.push((addr, AccessKind::Read));
+            }
+        }
+        "PHA" | "PHP" => accesses.push((stack(0), AccessKind::Write)),
+        "PLA" | "PLP" => accesses.push((stack(1), AccessKind::Read)),
+        "JSR" => {
+            accesses.push((stack(0), AccessKind::Write));
+            accesses.push((stack(0xFF), AccessKind::Write));
+        }
+        "BRK" => {
+            for i in 0..3 {
+                accesses.push((stack(0u8.wrapping_sub(i)), AccessKind::Write));
+            }
+        }
+        "RTS" => {
+            accesses.push((stack(1), AccessKind::Read));
+            accesses.push((stack(2), AccessKind::Read));
+        }
+        "RTI" => {
+            for i in 1..4 {
+                accesses.push((stack(i), AccessKind::Read));
+            }
+        }
+        _ => (),
+    }
+
+    accesses
+}
@@ -0,0 +1,716 @@
+use crate::code_data_logger::{CodeDataLogger, PrgFlags};
+use crate::cpu_6502::{AddrMode, Cpu6502};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+// One decoded instruction, as produced by Cpu6502::decode()
+#[derive(Clone, PartialEq, Debug)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    // Opcode followed by the operand bytes
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub mode: AddrMode,
+    // Raw operand, zero for implied instructions
+    pub operand: u16,
+    // Address the instruction refers to: the destination of a branch, or
+    // the base address for the memory modes. None for implied and immediate.
+    pub target: Option<u16>,
+    // Base cycle count, without page crossing or branch penalties
+    pub cycles: u8,
+    // The opcode is not a documented 6502 instruction
+    pub illegal: bool,
+}
+
+impl DisassembledInstruction {
+    // Accumulator versions of the shifts are implied mode in the lookup
+    // table but assemblers expect an explicit A
+    pub fn is_accumulator(&self) -> bool {
+        self.mode == AddrMode::Imp
+            && matches!(self.mnemonic.as_str(), "ASL" | "LSR" | "ROL" | "ROR")
+    }
+
+    // Absolute operand small enough to fit in the zero page. Assemblers pick
+    // the zero page form for these unless told otherwise, which would not
+    // reassemble to the same bytes.
+    pub fn is_absolute_in_zero_page(&self) -> bool {
+        matches!(self.mode, AddrMode::Abs | AddrMode::Abx | AddrMode::Aby) && self.operand < 0x100
+    }
+
+    // The operand as a plain hex number, the target for branches
+    pub fn value_text(&self) -> String {
+        match self.mode {
+            AddrMode::Imp => String::new(),
+            AddrMode::Rel => format!("${}", Cpu6502::hex(self.target.unwrap_or(0) as u32, 4)),
+            _ => format!(
+                "${}",
+                Cpu6502::hex(self.operand as u32, self.bytes.len() as u8 * 2 - 2)
+            ),
+        }
+    }
+
+    // Operand as the debugger shows it, with value in place of the number
+    pub fn operand_with(&self, value: &str) -> String {
+        match self.mode {
+            AddrMode::Imp => String::new(),
+            AddrMode::Imm => format!("#{}", value),
+            AddrMode::Zp0 | AddrMode::Abs => value.to_string(),
+            AddrMode::Zpx | AddrMode::Abx => format!("{}, X", value),
+            AddrMode::Zpy | AddrMode::Aby => format!("{}, Y", value),
+            AddrMode::Ind => format!("({})", value),
+            AddrMode::Izx => format!("({}, X)", value),
+            AddrMode::Izy => format!("({}), Y", value),
+            AddrMode::Rel => format!("${} [{}]", Cpu6502::hex(self.operand as u32, 2), value),
+        }
+    }
+
+    // Debugger style line around an operand
+    pub fn line_with(&self, operand: &str) -> String {
+        format!(
+            "${}: {} {} {{{}}}",
+            Cpu6502::hex(self.address as u32, 4),
+            self.mnemonic,
+            operand,
+            self.mode.tag()
+        )
+    }
+}
+
+// Debugger style line, e.g. "$8000: LDX #$0A {IMM}"
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.line_with(&self.operand_with(&self.value_text()))
+        )
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ASSEMBLER SYNTAXES
+
+// How a particular assembler wants instructions written. Output from any of
+// these assembles back to the same bytes.
+pub trait Syntax {
+    fn name(&self) -> &'static str;
+
+    // Directive that emits raw bytes, used for illegal opcodes and data
+    fn byte_directive(&self) -> &'static str;
+
+    fn word_directive(&self) -> &'static str;
+
+    fn org_directive(&self) -> &'static str {
+        ".org"
+    }
+
+    // Size of the banks the assembler splits code into, if it does. Every
+    // bank starts with bank_directive and an origin of its own.
+    fn bank_size(&self) -> Option<u32> {
+        None
+    }
+
+    fn bank_directive(&self, _bank: u32) -> String {
+        String::new()
+    }
+
+    // Operand text for a legal instruction, value being the number or label
+    // to put in it. None if this assembler cannot express the encoding.
+    fn operand(&self, ins: &DisassembledInstruction, value: &str) -> Option<String>;
+
+    fn format(&self, ins: &DisassembledInstruction) -> String {
+        self.format_with(ins, &ins.value_text())
+    }
+
+    fn format_with(&self, ins: &DisassembledInstruction, value: &str) -> String {
+        if ins.illegal {
+            return self.bytes(&ins.bytes);
+        }
+        match self.operand(ins, value) {
+            Some(operand) if operand.is_empty() => ins.mnemonic.clone(),
+            Some(operand) => format!("{} {}", ins.mnemonic, operand),
+            None => self.bytes(&ins.bytes),
+        }
+    }
+
+    fn bytes(&self, bytes: &[u8]) -> String {
+        let list: Vec<String> = bytes
+            .iter()
+            .map(|b| format!("${}", Cpu6502::hex(*b as u32, 2)))
+            .collect();
+        format!("{} {}", self.byte_directive(), list.join(", "))
+    }
+}
+
+// Operand in the usual MOS notation shared by ca65 and asm6
+fn mos_operand(ins: &DisassembledInstruction, value: &str) -> String {
+    match ins.mode {
+        AddrMode::Imp if ins.is_accumulator() => "A".to_string(),
+        AddrMode::Imp => String::new(),
+        AddrMode::Imm => format!("#{}", value),
+        AddrMode::Zp0 | AddrMode::Abs | AddrMode::Rel => value.to_string(),
+        AddrMode::Zpx | AddrMode::Abx => format!("{},X", value),
+        AddrMode::Zpy | AddrMode::Aby => format!("{},Y", value),
+        AddrMode::Ind => format!("({})", value),
+        AddrMode::Izx => format!("({},X)", value),
+        AddrMode::Izy => format!("({}),Y", value),
+    }
+}
+
+pub struct Ca65;
+
+impl Syntax for Ca65 {
+    fn name(&self) -> &'static str {
+        "ca65"
+    }
+
+    fn byte_directive(&self) -> &'static str {
+        ".byte"
+    }
+
+    fn word_directive(&self) -> &'static str {
+        ".word"
+    }
+
+    fn operand(&self, ins: &DisassembledInstruction, value: &str) -> Option<String> {
+        // a: forces the absolute encoding
+        if ins.is_absolute_in_zero_page() {
+            return Some(mos_operand(ins, &format!("a:{}", value)));
+        }
+        Some(mos_operand(ins, value))
+    }
+}
+
+pub struct Asm6;
+
+impl Syntax for Asm6 {
+    fn name(&self) -> &'static str {
+        "asm6"
+    }
+
+    fn byte_directive(&self) -> &'static str {
+        ".db"
+    }
+
+    fn word_directive(&self) -> &'static str {
+        ".dw"
+    }
+
+    fn operand(&self, ins: &DisassembledInstruction, value: &str) -> Option<String> {
+        // asm6 has no way to force an absolute address, fall back to bytes
+        if ins.is_absolute_in_zero_page() {
+            return None;
+        }
+        Some(mos_operand(ins, value))
+    }
+}
+
+pub struct Nesasm;
+
+impl Syntax for Nesasm {
+    fn name(&self) -> &'static str {
+        "nesasm"
+    }
+
+    fn byte_directive(&self) -> &'static str {
+        ".db"
+    }
+
+    fn word_directive(&self) -> &'static str {
+        ".dw"
+    }
+
+    fn bank_size(&self) -> Option<u32> {
+        Some(0x2000)
+    }
+
+    fn bank_directive(&self, bank: u32) -> String {
+        format!(".bank {}", bank)
+    }
+
+    // NESASM only uses the zero page when the operand is prefixed with <,
+    // and writes indirection with square brackets
+    fn operand(&self, ins: &DisassembledInstruction, value: &str) -> Option<String> {
+        let operand = match ins.mode {
+            AddrMode::Zp0 => format!("<{}", value),
+            AddrMode::Zpx => format!("<{},X", value),
+            AddrMode::Zpy => format!("<{},Y", value),
+            AddrMode::Ind => format!("[{}]", value),
+            AddrMode::Izx => format!("[{},X]", value),
+            AddrMode::Izy => format!("[{}],Y", value),
+            _ => mos_operand(ins, value),
+        };
+        Some(operand)
+    }
+}
+
+// Looks a syntax up by the name it reports
+pub fn syntax_by_name(name: &str) -> Option<Box<dyn Syntax>> {
+    match name.to_ascii_lowercase().as_str() {
+        "ca65" => Some(Box::new(Ca65)),
+        "asm6" => Some(Box::new(Asm6)),
+        "nesasm" => Some(Box::new(Nesasm)),
+        _ => None,
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// RECURSIVE DESCENT
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteKind {
+    // Never reached by the trace, listed as data
+    Unknown,
+    // First byte of an instruction
+    Code,
+    // Operand byte of an instruction
+    Operand,
+    Data,
+}
+
+// How the entries of a jump table are stored
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JumpTableKind {
+    // Plain little endian addresses, used with JMP (ind)
+    Words,
+    // Addresses minus one, pushed on the stack and jumped to with RTS
+    RtsWords,
+}
+
+struct JumpTable {
+    addr: u16,
+    entries: u16,
+    kind: JumpTableKind,
+}
+
+// Follows the flow of the program from the interrupt vectors, and from any
+// extra entry points or jump tables it is told about, instead of sweeping
+// memory in a line. Bytes that are never reached stay data, so the decoder
+// cannot get out of step after a table in the middle of the code.
+pub struct Tracer {
+    start: u16,
+    end: u16,
+    entries: Vec<u16>,
+    tables: Vec<JumpTable>,
+    // Names to use instead of generated labels
+    names: BTreeMap<u16, String>,
+    // Code and data known from a code/data log. Unlike entries, the code
+    // addresses do not get labels of their own.
+    code: Vec<u16>,
+    data: Vec<u16>,
+}
+
+impl Tracer {
+    // Traces code between start and end inclusive, normally the PRG ROM
+    // at $8000-$FFFF
+    pub fn new(start: u16, end: u16) -> Self {
+        Self {
+            start: start.min(end),
+            end: start.max(end),
+            entries: Vec::new(),
+            tables: Vec::new(),
+            names: BTreeMap::new(),
+            code: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    // Labels known from debug symbols, they end up in the listing as well
+    pub fn add_label(&mut self, addr: u16, name: &str) {
+        self.names.insert(addr, name.to_string());
+    }
+
+    // Code that is only reached in ways the trace cannot follow
+    pub fn add_entry(&mut self, addr: u16) {
+        self.entries.push(addr);
+    }
+
+    // Takes entry points and data from a code/data log. Every instruction
+    // the log saw start becomes an entry, or for a log loaded from a file
+    // (which cannot tell opcodes from operands) the start of each run of
+    // code. Bytes only ever read are kept as data.
+    pub fn add_code_data_log(&mut self, cdl: &CodeDataLogger) {
+        for addr in self.start.max(0x8000)..=self.end {
+            let flags = cdl.prg_flags(addr);
+            let previous = cdl.prg_flags(addr.wrapping_sub(1));
+            if flags & PrgFlags::INDIRECT_CODE != 0 {
+                self.entries.push(addr);
+            } else if flags & PrgFlags::CODE != 0
+                && (cdl.is_opcode(addr) || previous & PrgFlags::CODE == 0)
+            {
+                self.code.push(addr);
+            } else if flags & PrgFlags::DATA != 0 {
+                self.data.push(addr);
+            }
+        }
+    }
+
+    pub fn add_jump_table(&mut self, addr: u16, entries: u16, kind: JumpTableKind) {
+        self.tables.push(JumpTable {
+            addr,
+            entries,
+            kind,
+        });
+    }
+
+    pub fn trace(&self, cpu: &Cpu6502) -> CodeMap {
+        let mut map = CodeMap {
+            start: self.start,
+            end: self.end,
+            kinds: vec![ByteKind::Unknown; (self.end - self.start) as usize + 1],
+            instructions: BTreeMap::new(),
+            words: BTreeMap::new(),
+            labels: self.names.clone(),
+        };
+        let mut pending: Vec<u16> = Vec::new();
+
+        for (vector, name) in [(0xFFFA, "NMI"), (0xFFFC, "RESET"), (0xFFFE, "IRQ")] {
+            if map.add_word(vector, 0) {
+                let target = read_word(cpu, vector);
+                map.labels.entry(target).or_insert_with(|| name.to_string());
+                pending.push(target);
+            }
+        }
+
+        for addr in &self.entries {
+            map.label(*addr, "L");
+            pending.push(*addr);
+        }
+
+        for table in &self.tables {
+            let adjust = match table.kind {
+                JumpTableKind::Words => 0,
+                JumpTableKind::RtsWords => 1,
+            };
+            for i in 0..table.entries {
+                let at = table.addr.wrapping_add(i * 2);
+                if !map.add_word(at, adjust) {
+                    break;
+                }
+                let target = read_word(cpu, at).wrapping_add(adjust);
+                map.label(target, "L");
+                pending.push(target);
+            }
+        }
+
+        pending.extend(self.code.iter().rev());
+        for addr in &self.data {
+            if map.kind(*addr) == ByteKind::Unknown && map.contains(*addr) {
+                map.kinds[(addr - self.start) as usize] = ByteKind::Data;
+            }
+        }
+
+        while let Some(addr) = pending.pop() {
+            map.follow(cpu, addr, &mut pending);
+        }
+
+        map
+    }
+}
+
+fn read_word(cpu: &Cpu6502, addr: u16) -> u16 {
+    let lo = cpu.bus.read(&addr, true) as u16;
+    let hi = cpu.bus.read(&addr.wrapping_add(1), true) as u16;
+    (hi << 8) | lo
+}
+
+// Result of a trace: what every byte in the region is, plus the labels the
+// trace generated for branch, jump and call targets
+pub struct CodeMap {
+    start: u16,
+    end: u16,
+    kinds: Vec<ByteKind>,
+    instructions: BTreeMap<u16, DisassembledInstruction>,
+    // Addresses stored as data, with what to add to get the real target
+    words: BTreeMap<u16, u16>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl CodeMap {
+    pub fn contains(&self, addr: u16) -> bool {
+        addr >= self.start && addr <= self.end
+    }
+
+    pub fn kind(&self, addr: u16) -> ByteKind {
+        if self.contains(addr) {
+            self.kinds[(addr - self.start) as usize]
+        } else {
+            ByteKind::Unknown
+        }
+    }
+
+    pub fn instructions(&self) -> &BTreeMap<u16, DisassembledInstruction> {
+        &self.instructions
+    }
+
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+
+    fn label(&mut self, addr: u16, prefix: &str) {
+        self.labels
+            .entry(addr)
+            .or_insert_with(|| format!("{}_{}", prefix, Cpu6502::hex(addr as u32, 4)));
+    }
+
+    // Marks a word of data, false if it does not fit in the region or
+    // overlaps something already traced
+    fn add_word(&mut self, addr: u16, adjust: u16) -> bool {
+        let hi = addr.wrapping_add(1);
+        if !self.contains(addr) || !self.contains(hi) || self.words.contains_key(&addr) {
+            return false;
+        }
+        if self.kind(addr) != ByteKind::Unknown || self.kind(hi) != ByteKind::Unknown {
+            return false;
+        }
+        self.kinds[(addr - self.start) as usize] = ByteKind::Data;
+        self.kinds[(hi - self.start) as usize] = ByteKind::Data;
+        self.words.insert(addr, adjust);
+        true
+    }
+
+    // Decodes instructions from addr until the flow of control leaves, adding
+    // the targets of branches, jumps and calls to pending
+    fn follow(&mut self, cpu: &Cpu6502, mut addr: u16, pending: &mut Vec<u16>) {
+        loop {
+            if !self.contains(addr) || self.kind(addr) != ByteKind::Unknown {
+                return;
+            }
+
+            let ins = cpu.decode(addr);
+            let len = ins.bytes.len() as u16;
+            // Illegal opcodes and instructions running into bytes already
+            // used for something else mean this path was really data
+            let clash = (1..len).any(|i| {
+                let at = addr.wrapping_add(i);
+                !self.contains(at) || self.kind(at) != ByteKind::Unknown
+            });
+            if ins.illegal || clash {
+                return;
+            }
+
+            self.kinds[(addr - self.start) as usize] = ByteKind::Code;
+            for i in 1..len {
+                self.kinds[(addr.wrapping_add(i) - self.start) as usize] = ByteKind::Operand;
+            }
+
+            let mut stop = false;
+            match (ins.mnemonic.as_str(), ins.mode) {
+                ("JSR", _) => {
+                    let target = ins.operand;
+                    self.label(target, "S");
+                    pending.push(target);
+                }
+                ("JMP", AddrMode::Abs) => {
+                    let target = ins.operand;
+                    self.label(target, "L");
+                    pending.push(target);
+                    stop = true;
+                }
+                ("JMP", _) | ("RTS", _) | ("RTI", _) | ("BRK", _) => stop = true,
+                (_, AddrMode::Rel) => {
+                    if let Some(target) = ins.target {
+                        self.label(target, "L");
+                        pending.push(target);
+                    }
+                }
+                _ => (),
+            }
+
+            self.instructions.insert(addr, ins);
+            if stop {
+                return;
+            }
+            addr = addr.wrapping_add(len);
+        }
+    }
+
+    // A label has to be an equate when there is no line for it in the
+    // listing, because it is outside the region or inside another line
+    fn needs_equate(&self, addr: u16) -> bool {
+        !self.contains(addr)
+            || self.kind(addr) == ByteKind::Operand
+            || self.words.contains_key(&addr.wrapping_sub(1))
+    }
+
+    fn starts_line(&self, addr: u16) -> bool {
+        self.labels.contains_key(&addr)
+            || self.instructions.contains_key(&addr)
+            || self.words.contains_key(&addr)
+    }
+
+    // Source for the whole region that assembles back to the same bytes.
+    // Branch and call targets get labels, everything the trace did not
+    // reach is written out as bytes.
+    pub fn listing(&self, cpu: &Cpu6502, syntax: &dyn Syntax) -> String {
+        let mut out = String::new();
+        let peek = |addr: u16| cpu.bus.read(&addr, true);
+
+        let _ = writeln!(
+            out,
+            "; {} listing of ${}-${}",
+            syntax.name(),
+            Cpu6502::hex(self.start as u32, 4),
+            Cpu6502::hex(self.end as u32, 4)
+        );
+        for (addr, name) in self
+            .labels
+            .iter()
+            .filter(|(addr, _)| self.needs_equate(**addr))
+        {
+            let _ = writeln!(out, "{} = ${}", name, Cpu6502::hex(*addr as u32, 4));
+        }
+        // Banks are counted from the one the region starts in. An
+        // instruction running over the end of a bank cannot be split, so
+        // the next bank then starts at the line after it.
+        let bank_size = syntax.bank_size();
+        let bank_of = |addr: u32| bank_size.map_or(0, |size| addr / size);
+        let first_bank = bank_of(self.start as u32);
+        let origin = |out: &mut String, addr: u32| {
+            let _ = writeln!(out);
+            if bank_size.is_some() {
+                let bank = bank_of(addr) - first_bank;
+                let _ = writeln!(out, "    {}", syntax.bank_directive(bank));
+            }
+            let _ = writeln!(
+                out,
+                "    {} ${}",
+                syntax.org_directive(),
+                Cpu6502::hex(addr, 4)
+            );
+        };
+        origin(&mut out, self.start as u32);
+
+        let mut addr = self.start as u32;
+        let mut bank = first_bank;
+        while addr <= self.end as u32 {
+            let at = addr as u16;
+            if bank_of(addr) != bank {
+                bank = bank_of(addr);
+                origin(&mut out, addr);
+            }
+            if let Some(name) = self.labels.get(&at) {
+                let _ = writeln!(out, "{}:", name);
+            }
+
+            if let Some(ins) = self.instructions.get(&at) {
+                let text = match ins.target.and_then(|t| self.labels.get(&t)) {
+                    Some(label) => syntax.format_with(ins, label),
+                    None => syntax.format(ins),
+                };
+                let _ = writeln!(out, "    {}", text);
+                addr += ins.bytes.len() as u32;
+            } else if let Some(adjust) = self.words.get(&at) {
+                let value = read_word(cpu, at);
+                let text = match self.labels.get(&value.wrapping_add(*adjust)) {
+                    Some(label) if *adjust == 0 => label.clone(),
+                    Some(label) => format!("{}-{}", label, adjust),
+                    None => format!("${}", Cpu6502::hex(value as u32, 4)),
+                };
+                let _ = writeln!(out, "    {} {}", syntax.word_directive(), text);
+                addr += 2;
+            } else {
+                // Runs of up to 16 bytes, broken wherever another line starts
+                let mut bytes: Vec<u8> = vec![peek(at)];
+                addr += 1;
+                while addr <= self.end as u32
+                    && bytes.len() < 16
+                    && !self.starts_line(addr as u16)
+                    && bank_of(addr) == bank
+                {
+                    bytes.push(peek(addr as u16));
+                    addr += 1;
+                }
+                let _ = writeln!(out, "    {}", syntax.bytes(&bytes));
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    // Code reached through a call, a branch and a jump table, an absolute
+    // access to the zero page, a jump into the middle of an instruction
+    // and bytes nothing reaches
+    const PROGRAM: [(u16, &[u8]); 5] = [
+        (
+            0x8000,
+            &[
+                0xA2, 0x00, // LDX #$00
+                0x20, 0x10, 0x80, // JSR $8010
+                0xAD, 0x10, 0x00, // LDA a:$0010
+                0xD0, 0xF6, // BNE $8000
+                0x6C, 0x20, 0x80, // JMP ($8020)
+                0x02, 0xFF, 0x00, // not code
+            ],
+        ),
+        (
+            0x8010,
+            &[
+                0x85, 0x10, // STA $10
+                0xB5, 0x11, // LDA $11,X
+                0x60, // RTS
+                0x4C, 0x02, 0x80, // never reached
+            ],
+        ),
+        (0x8020, &[0x30, 0x80]),
+        (
+            0x8030,
+            &[
+                0xE8, // INX
+                0x4C, 0x03, 0x80, // JMP into the operand of the JSR
+            ],
+        ),
+        (0x803E, &[0xEA, 0xEA]),
+    ];
+
+    fn traced(start: u16, end: u16) -> (Cpu6502, CodeMap) {
+        let mut cpu = Cpu6502::new();
+        for (addr, bytes) in PROGRAM {
+            for (i, b) in bytes.iter().enumerate() {
+                cpu.bus.write(&(addr + i as u16), b);
+            }
+        }
+        let mut tracer = Tracer::new(start, end);
+        tracer.add_entry(0x8000);
+        tracer.add_jump_table(0x8020, 1, JumpTableKind::Words);
+        let map = tracer.trace(&cpu);
+        (cpu, map)
+    }
+
+    #[test]
+    fn listings_reassemble_to_the_same_bytes() {
+        let (cpu, map) = traced(0x8000, 0x803F);
+        assert_eq!(map.kind(0x800D), ByteKind::Unknown);
+        assert_eq!(map.kind(0x8030), ByteKind::Code);
+
+        let original: Vec<u8> = (0x8000..=0x803F).map(|a| cpu.bus.read(&a, true)).collect();
+        for syntax in ["ca65", "asm6"] {
+            let listing = map.listing(&cpu, syntax_by_name(syntax).unwrap().as_ref());
+            let assembly = Assembler::new()
+                .assemble(&listing)
+                .unwrap_or_else(|e| panic!("{}: {}\n{}", syntax, e, listing));
+            assert_eq!(assembly.segments.len(), 1, "{}", listing);
+            assert_eq!(assembly.segments[0].origin, 0x8000);
+            assert_eq!(assembly.segments[0].data, original, "{}", listing);
+        }
+    }
+
+    #[test]
+    fn nesasm_listings_are_split_into_banks() {
+        let (cpu, map) = traced(0x9FF0, 0xA00F);
+        let listing = map.listing(&cpu, &Nesasm);
+        let origins: Vec<&str> = listing
+            .lines()
+            .map(str::trim)
+            .filter(|l| l.starts_with(".bank") || l.starts_with(".org"))
+            .collect();
+        assert_eq!(origins, [".bank 0", ".org $9FF0", ".bank 1", ".org $A000"]);
+    }
+}
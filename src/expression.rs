@@ -0,0 +1,358 @@
+use crate::cpu_6502::Cpu6502;
+use crate::symbols::SymbolTable;
+
+// Small expression language used by conditional breakpoints, e.g.
+//
+//     A == $10 && [$0200] != 0
+//     X >= 3 || (P & %00000001) == 1
+//
+// Numbers are decimal, $hex, 0xhex or %binary. Registers are A, X, Y, P,
+// SP and PC, any other name is looked up in the symbol table the
+// expression was parsed with and replaced by its address. [expr] reads the
+// byte at that address through the bus peek path, so evaluating a
+// condition never has side effects on the machine.
+// Everything evaluates to an integer, comparisons and logic produce 1 or 0.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    P,
+    SP,
+    PC,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum UnaryOp {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Node {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        Expression::parse_with(source, None)
+    }
+
+    pub fn parse_with_symbols(source: &str, symbols: &SymbolTable) -> Result<Expression, String> {
+        Expression::parse_with(source, Some(symbols))
+    }
+
+    fn parse_with(source: &str, symbols: Option<&SymbolTable>) -> Result<Expression, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            symbols,
+        };
+        let root = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(format!(
+                "unexpected {:?} in '{}'",
+                parser.tokens[parser.pos], source
+            ));
+        }
+        Ok(Expression {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    // The text the expression was parsed from, handy for listing breakpoints
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn evaluate(&self, cpu: &Cpu6502) -> i64 {
+        evaluate(&self.root, cpu)
+    }
+
+    pub fn is_true(&self, cpu: &Cpu6502) -> bool {
+        self.evaluate(cpu) != 0
+    }
+}
+
+fn evaluate(node: &Node, cpu: &Cpu6502) -> i64 {
+    match node {
+        Node::Number(n) => *n,
+        Node::Register(r) => match r {
+            Register::A => cpu.a as i64,
+            Register::X => cpu.x as i64,
+            Register::Y => cpu.y as i64,
+            Register::P => cpu.sr as i64,
+            Register::SP => cpu.sp as i64,
+            Register::PC => cpu.pc as i64,
+        },
+        Node::Memory(addr) => {
+            let addr = (evaluate(addr, cpu) & 0xFFFF) as u16;
+            cpu.bus.read(&addr, true) as i64
+        }
+        Node::Unary(op, n) => {
+            let v = evaluate(n, cpu);
+            match op {
+                UnaryOp::Not => (v == 0) as i64,
+                UnaryOp::Negate => v.wrapping_neg(),
+                UnaryOp::Complement => !v,
+            }
+        }
+        Node::Binary(op, l, r) => {
+            let l = evaluate(l, cpu);
+            // Short circuit the logical operators
+            match op {
+                BinaryOp::Or if l != 0 => return 1,
+                BinaryOp::And if l == 0 => return 0,
+                _ => (),
+            }
+            let r = evaluate(r, cpu);
+            match op {
+                BinaryOp::Or | BinaryOp::And => (r != 0) as i64,
+                BinaryOp::Equal => (l == r) as i64,
+                BinaryOp::NotEqual => (l != r) as i64,
+                BinaryOp::Less => (l < r) as i64,
+                BinaryOp::LessEqual => (l <= r) as i64,
+                BinaryOp::Greater => (l > r) as i64,
+                BinaryOp::GreaterEqual => (l >= r) as i64,
+                BinaryOp::BitOr => l | r,
+                BinaryOp::BitXor => l ^ r,
+                BinaryOp::BitAnd => l & r,
+                BinaryOp::Add => l.wrapping_add(r),
+                BinaryOp::Sub => l.wrapping_sub(r),
+            }
+        }
+    }
+}
+
+// Longest operators first so "<=" is not read as "<" followed by "="
+const OPERATORS: [&str; 19] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "~", "(", ")", "[",
+    "]",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let (radix, skip) = if c == '$' {
+            (16, 1)
+        } else if c == '%' {
+            (2, 1)
+        } else if c == '0' && i + 1 < chars.len() && (chars[i + 1] == 'x' || chars[i + 1] == 'X') {
+            (16, 2)
+        } else if c.is_ascii_digit() {
+            (10, 0)
+        } else {
+            (0, 0)
+        };
+
+        if radix != 0 {
+            let start = i + skip;
+            let mut end = start;
+            while end < chars.len() && chars[end].is_digit(radix) {
+                end += 1;
+            }
+            let digits: String = chars[start..end].iter().collect();
+            let n = i64::from_str_radix(&digits, radix)
+                .map_err(|_| format!("bad number at column {}", i + 1))?;
+            tokens.push(Token::Number(n));
+            i = end;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '@' || c == '.' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '_'
+                    || chars[i] == '@'
+                    || chars[i] == '.')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => return Err(format!("unexpected '{}' at column {}", c, i + 1)),
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl Parser<'_> {
+    fn accept(&mut self, op: &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        next: fn(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        let mut lhs = next(self)?;
+        'outer: loop {
+            for (text, op) in ops {
+                if self.accept(text) {
+                    let rhs = next(self)?;
+                    lhs = Node::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Node, String> {
+        self.binary(&[("||", BinaryOp::Or)], Self::parse_and)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, String> {
+        self.binary(&[("&&", BinaryOp::And)], Self::parse_compare)
+    }
+
+    fn parse_compare(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[
+                ("==", BinaryOp::Equal),
+                ("!=", BinaryOp::NotEqual),
+                ("<=", BinaryOp::LessEqual),
+                (">=", BinaryOp::GreaterEqual),
+                ("<", BinaryOp::Less),
+                (">", BinaryOp::Greater),
+            ],
+            Self::parse_bit_or,
+        )
+    }
+
+    fn parse_bit_or(&mut self) -> Result<Node, String> {
+        self.binary(&[("|", BinaryOp::BitOr)], Self::parse_bit_xor)
+    }
+
+    fn parse_bit_xor(&mut self) -> Result<Node, String> {
+        self.binary(&[("^", BinaryOp::BitXor)], Self::parse_bit_and)
+    }
+
+    fn parse_bit_and(&mut self) -> Result<Node, String> {
+        self.binary(&[("&", BinaryOp::BitAnd)], Self::parse_sum)
+    }
+
+    fn parse_sum(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            Self::parse_unary,
+        )
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, String> {
+        let op = if self.accept("!") {
+            UnaryOp::Not
+        } else if self.accept("-") {
+            UnaryOp::Negate
+        } else if self.accept("~") {
+            UnaryOp::Complement
+        } else {
+            return self.parse_primary();
+        };
+        Ok(Node::Unary(op, Box::new(self.parse_unary()?)))
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, String> {
+        if self.accept("(") {
+            let node = self.parse_or()?;
+            return if self.accept(")") {
+                Ok(node)
+            } else {
+                Err("missing ')'".to_string())
+            };
+        }
+        if self.accept("[") {
+            let node = self.parse_or()?;
+            return if self.accept("]") {
+                Ok(Node::Memory(Box::new(node)))
+            } else {
+                Err("missing ']'".to_string())
+            };
+        }
+
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Node::Number(n)),
+            Some(Token::Ident(name)) => match name.to_ascii_uppercase().as_str() {
+                "A" => Ok(Node::Register(Register::A)),
+                "X" => Ok(Node::Register(Register::X)),
+                "Y" => Ok(Node::Register(Register::Y)),
+                "P" => Ok(Node::Register(Register::P)),
+                "SP" => Ok(Node::Register(Register::SP)),
+                "PC" => Ok(Node::Register(Register::PC)),
+                _ => match self.symbols.and_then(|s| s.address_of(&name)) {
+                    Some(addr) => Ok(Node::Number(addr as i64)),
+                    None => Err(format!("unknown name '{}'", name)),
+                },
+            },
+            Some(t) => Err(format!("unexpected {:?}", t)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
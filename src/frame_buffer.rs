@@ -0,0 +1,134 @@
+use crate::image::Image;
+use crate::palette::Palette;
+use crate::zip::crc32;
+
+// The picture the PPU puts out, 256x240 colour numbers (see palette.rs for
+// what those hold). It is kept as colour numbers rather than RGB so the
+// palette can be chosen when the picture is looked at, and so a hash of it
+// does not change with the palette.
+//
+// Nothing draws into it yet. Until the core has a PPU every screenshot is
+// blank (colour 0 all over) and every frame has the same hash.
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+// Rows and columns to cut from the edges. TVs hid about 8 lines at the
+// top and bottom, and games often leave garbage there.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    pub const NONE: Overscan = Overscan {
+        top: 0,
+        bottom: 0,
+        left: 0,
+        right: 0,
+    };
+    pub const NTSC: Overscan = Overscan {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct FrameBuffer {
+    pixels: Vec<u16>,
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        FrameBuffer::new()
+    }
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * WIDTH + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, pixel: u16) {
+        self.pixels[y * WIDTH + x] = pixel;
+    }
+
+    pub fn clear(&mut self, pixel: u16) {
+        self.pixels.fill(pixel);
+    }
+
+    // CRC-32 of the colour numbers, for checking a frame against a known
+    // good one in tests
+    pub fn hash(&self) -> u32 {
+        let bytes: Vec<u8> = self.pixels.iter().flat_map(|p| p.to_le_bytes()).collect();
+        crc32(&bytes)
+    }
+
+    pub fn screenshot(&self, palette: &Palette, overscan: Overscan) -> Image {
+        let width = WIDTH.saturating_sub(overscan.left + overscan.right);
+        let height = HEIGHT.saturating_sub(overscan.top + overscan.bottom);
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = self.pixel(x + overscan.left, y + overscan.top);
+                image.set_pixel(x, y, palette.rgb(pixel));
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Diagonal stripes through every colour, with emphasis on the lines
+    // from 200 down
+    fn frame() -> FrameBuffer {
+        let mut frame = FrameBuffer::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let emphasis = if y >= 200 { 0x40 } else { 0 };
+                frame.set_pixel(x, y, ((x + 2 * y) % 64) as u16 | emphasis);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn hash_is_of_the_colour_numbers() {
+        assert_eq!(FrameBuffer::new().hash(), crc32(&[0; WIDTH * HEIGHT * 2]));
+        assert_eq!(frame().hash(), 0x4D79E19D);
+    }
+
+    #[test]
+    fn screenshots_crop_the_overscan() {
+        let palette = Palette::new();
+        let full = frame().screenshot(&palette, Overscan::NONE);
+        assert_eq!((full.width, full.height), (WIDTH, HEIGHT));
+        assert_eq!(full.pixel(3, 1), palette.rgb(5));
+
+        let cropped = frame().screenshot(&palette, Overscan::NTSC);
+        assert_eq!((cropped.width, cropped.height), (WIDTH, HEIGHT - 16));
+        assert_eq!(cropped.pixel(0, 0), full.pixel(0, 8));
+        assert_eq!(cropped.pixel(255, 223), full.pixel(255, 231));
+        let ppm = cropped.to_ppm();
+        assert_eq!(ppm.len(), 172047);
+        assert_eq!(crc32(&ppm), 0x560557C8);
+    }
+}
@@ -0,0 +1,527 @@
+use crate::cpu_6502::Cpu6502;
+use crate::debugger::{AccessKind, Debugger, StopReason, CPU_CYCLES_PER_FRAME};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// GDB remote serial protocol server for the 6502 core. It only listens on
+// the loopback interface, serves one client at a time and maps the gdb
+// requests onto the Debugger, so breakpoints and watchpoints behave exactly
+// like they do in the monitor and the olc frontend.
+//
+// Registers are numbered A, X, Y, SP, PC, P. All are 8 bits wide apart
+// from PC, which is 16 bits and sent little endian. The layout is also
+// described to gdb through qXfer target.xml.
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nes.mos6502">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// SIGTRAP and SIGINT, as gdb expects them in stop replies
+const SIGTRAP: u8 = 5;
+const SIGINT: u8 = 2;
+
+pub struct GdbStub {
+    stream: TcpStream,
+    // Bytes received but not consumed yet
+    pending: VecDeque<u8>,
+    // Set once the client asks for QStartNoAckMode
+    no_ack: bool,
+    // Debugger ids behind every Z packet, so z can remove them again
+    points: HashMap<(u8, u16), Vec<u32>>,
+}
+
+impl GdbStub {
+    // Listens on 127.0.0.1:port. Port 0 picks a free one, the listener can
+    // tell which with local_addr().
+    pub fn bind(port: u16) -> io::Result<TcpListener> {
+        TcpListener::bind(("127.0.0.1", port))
+    }
+
+    // Waits for a client to connect
+    pub fn accept(listener: &TcpListener) -> io::Result<GdbStub> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            stream,
+            pending: VecDeque::new(),
+            no_ack: false,
+            points: HashMap::new(),
+        })
+    }
+
+    // Answers requests until the client detaches, kills the session or
+    // drops the connection. Breakpoints the client set are removed again.
+    pub fn serve(&mut self, cpu: &mut Cpu6502, debugger: &mut Debugger) -> io::Result<()> {
+        let result = self.serve_packets(cpu, debugger);
+        for ids in self.points.values() {
+            for id in ids {
+                debugger.remove(*id);
+            }
+        }
+        self.points.clear();
+        debugger.pause();
+
+        match result {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(()),
+            r => r,
+        }
+    }
+
+    fn serve_packets(&mut self, cpu: &mut Cpu6502, debugger: &mut Debugger) -> io::Result<()> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                // A ^C while the cpu is already stopped
+                None => {
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+            };
+
+            // The OK still goes out in ack mode, everything after it doesnt
+            if packet == b"QStartNoAckMode" {
+                self.send("OK")?;
+                self.no_ack = true;
+                continue;
+            }
+
+            let (cmd, args) = match packet.first() {
+                Some(c) => (*c, &packet[1..]),
+                None => (b' ', &packet[..]),
+            };
+            let text = String::from_utf8_lossy(args).to_string();
+
+            let reply = match cmd {
+                b'?' => format!("S{:02x}", SIGTRAP),
+                b'g' => {
+                    let pc = cpu.pc.to_le_bytes();
+                    to_hex(&[cpu.a, cpu.x, cpu.y, cpu.sp, pc[0], pc[1], cpu.sr])
+                }
+                b'G' => match from_hex(&text) {
+                    Some(regs) if regs.len() >= 7 => {
+                        cpu.a = regs[0];
+                        cpu.x = regs[1];
+                        cpu.y = regs[2];
+                        cpu.sp = regs[3];
+                        cpu.pc = u16::from_le_bytes([regs[4], regs[5]]);
+                        cpu.sr = regs[6];
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                },
+                b'p' => match u8::from_str_radix(&text, 16) {
+                    Ok(4) => to_hex(&cpu.pc.to_le_bytes()),
+                    Ok(n) if n < 6 => to_hex(&[*register(cpu, n)]),
+                    _ => "E01".to_string(),
+                },
+                b'P' => match text
+                    .split_once('=')
+                    .map(|(n, v)| (u8::from_str_radix(n, 16), from_hex(v)))
+                {
+                    Some((Ok(4), Some(v))) if v.len() == 2 => {
+                        cpu.pc = u16::from_le_bytes([v[0], v[1]]);
+                        "OK".to_string()
+                    }
+                    Some((Ok(n), Some(v))) if n < 6 && n != 4 && v.len() == 1 => {
+                        *register(cpu, n) = v[0];
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                },
+                b'm' => match parse_range(&text) {
+                    Some((addr, len)) => {
+                        let data: Vec<u8> = (0..len)
+                            .map(|i| cpu.bus.read(&addr.wrapping_add(i), true))
+                            .collect();
+                        to_hex(&data)
+                    }
+                    None => "E01".to_string(),
+                },
+                b'M' | b'X' => {
+                    let split = args.iter().position(|c| *c == b':');
+                    let range =
+                        split.and_then(|i| parse_range(&String::from_utf8_lossy(&args[..i])));
+                    let data = match (split, cmd) {
+                        (Some(i), b'M') => from_hex(&String::from_utf8_lossy(&args[i + 1..])),
+                        (Some(i), _) => Some(args[i + 1..].to_vec()),
+                        _ => None,
+                    };
+                    match (range, data) {
+                        (Some((addr, len)), Some(data)) if data.len() == len as usize => {
+                            for (i, b) in data.iter().enumerate() {
+                                cpu.bus.poke(addr.wrapping_add(i as u16), *b);
+                            }
+                            "OK".to_string()
+                        }
+                        _ => "E01".to_string(),
+                    }
+                }
+                b'c' | b's' => {
+                    if let Ok(addr) = u16::from_str_radix(&text, 16) {
+                        cpu.pc = addr;
+                    }
+                    if cmd == b's' {
+                        let reason = debugger.step(cpu);
+                        self.stop_reply(reason)
+                    } else {
+                        debugger.resume();
+                        self.run(cpu, debugger)?
+                    }
+                }
+                b'Z' | b'z' => self.breakpoint(cmd == b'Z', &text, debugger),
+                b'k' => return Ok(()),
+                b'D' => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                b'H' => "OK".to_string(),
+                b'q' => self.query(&text),
+                _ => String::new(),
+            };
+
+            self.send(&reply)?;
+        }
+    }
+
+    // Runs until the debugger stops or the client sends a ^C
+    fn run(&mut self, cpu: &mut Cpu6502, debugger: &mut Debugger) -> io::Result<String> {
+        loop {
+            if let Some(reason) = debugger.update(cpu, CPU_CYCLES_PER_FRAME) {
+                return Ok(self.stop_reply(reason));
+            }
+            if self.poll_interrupt()? {
+                debugger.pause();
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    fn breakpoint(&mut self, insert: bool, text: &str, debugger: &mut Debugger) -> String {
+        let mut fields = text.split(',');
+        let kind = fields.next().and_then(|f| f.parse::<u8>().ok());
+        let addr = fields.next().and_then(|f| u16::from_str_radix(f, 16).ok());
+        let len = fields
+            .next()
+            .and_then(|f| u16::from_str_radix(f, 16).ok())
+            .unwrap_or(1);
+        let (Some(kind), Some(addr)) = (kind, addr) else {
+            return "E01".to_string();
+        };
+
+        if !insert {
+            return match self.points.remove(&(kind, addr)) {
+                Some(ids) => {
+                    for id in ids {
+                        debugger.remove(id);
+                    }
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            };
+        }
+
+        let end = addr.wrapping_add(len.max(1) - 1);
+        let ids = match kind {
+            0 | 1 => vec![debugger.add_breakpoint(addr, None)],
+            2 => vec![debugger.add_watchpoint(addr, end, AccessKind::Write)],
+            3 => vec![debugger.add_watchpoint(addr, end, AccessKind::Read)],
+            4 => vec![
+                debugger.add_watchpoint(addr, end, AccessKind::Read),
+                debugger.add_watchpoint(addr, end, AccessKind::Write),
+            ],
+            _ => return String::new(),
+        };
+        self.points.entry((kind, addr)).or_default().extend(ids);
+        "OK".to_string()
+    }
+
+    // Watchpoints are reported as the kind of Z packet that set them, Z2
+    // watch, Z3 rwatch and Z4 awatch. Ones set some other way go by the
+    // access, and gdb has nothing for an execute watchpoint but a trap.
+    fn stop_reply(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+            StopReason::Watchpoint { id, addr, kind } => {
+                let z_kind = self
+                    .points
+                    .iter()
+                    .find(|(_, ids)| ids.contains(&id))
+                    .map(|((z_kind, _), _)| *z_kind);
+                let name = match (z_kind, kind) {
+                    (Some(2), _) => "watch",
+                    (Some(3), _) => "rwatch",
+                    (Some(4), _) => "awatch",
+                    (_, AccessKind::Write) => "watch",
+                    (_, AccessKind::Read) => "rwatch",
+                    (_, AccessKind::Execute) => return format!("S{:02x}", SIGTRAP),
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, name, addr)
+            }
+            _ => format!("S{:02x}", SIGTRAP),
+        }
+    }
+
+    fn query(&self, text: &str) -> String {
+        match text {
+            t if t.starts_with("Supported") => {
+                "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+".to_string()
+            }
+            t if t.starts_with("Xfer:features:read:target.xml:") => {
+                let range = &t["Xfer:features:read:target.xml:".len()..];
+                let (offset, len) = parse_range(range).unwrap_or((0, 0));
+                let data = TARGET_XML.as_bytes();
+                let start = (offset as usize).min(data.len());
+                let end = (start + len as usize).min(data.len());
+                let prefix = if end == data.len() { "l" } else { "m" };
+                format!("{}{}", prefix, String::from_utf8_lossy(&data[start..end]))
+            }
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // PACKET LAYER
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        while self.pending.is_empty() {
+            let mut buf = [0u8; 1024];
+            let n = self.stream.read(&mut buf)?;
+            if n == 0 {
+                return Err(io::Error::from(ErrorKind::UnexpectedEof));
+            }
+            self.pending.extend(&buf[..n]);
+        }
+        Ok(self.pending.pop_front().expect(""))
+    }
+
+    // Reads the next $packet#cs, acknowledging it unless acks are off.
+    // Returns None if the client sent a ^C instead.
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match self.read_byte()? {
+                b'$' => (),
+                0x03 => return Ok(None),
+                _ => continue,
+            }
+
+            let mut data: Vec<u8> = Vec::new();
+            let mut sum: u8 = 0;
+            loop {
+                let c = self.read_byte()?;
+                if c == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(c);
+                if c == b'}' {
+                    let escaped = self.read_byte()?;
+                    sum = sum.wrapping_add(escaped);
+                    data.push(escaped ^ 0x20);
+                } else {
+                    data.push(c);
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = u8::from_str_radix(&String::from_utf8_lossy(&checksum), 16).ok();
+
+            if self.no_ack {
+                return Ok(Some(data));
+            }
+            if expected == Some(sum) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(data));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet: Vec<u8> = vec![b'$'];
+        let mut sum: u8 = 0;
+        for c in data.bytes() {
+            if matches!(c, b'$' | b'#' | b'}' | b'*') {
+                packet.push(b'}');
+                packet.push(c ^ 0x20);
+                sum = sum.wrapping_add(b'}').wrapping_add(c ^ 0x20);
+            } else {
+                packet.push(c);
+                sum = sum.wrapping_add(c);
+            }
+        }
+        packet.extend(format!("#{:02x}", sum).bytes());
+
+        loop {
+            self.stream.write_all(&packet)?;
+            if self.no_ack {
+                return Ok(());
+            }
+            // Resend on '-', anything else that isnt an ack is dropped
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    0x03 => self.pending.push_back(0x03),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    // Checks, without blocking, whether the client sent a ^C
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 1024];
+        let result = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+            Ok(n) => self.pending.extend(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+        }
+
+        match self.pending.iter().position(|c| *c == 0x03) {
+            Some(i) => {
+                self.pending.remove(i);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+fn register(cpu: &mut Cpu6502, n: u8) -> &mut u8 {
+    match n {
+        0 => &mut cpu.a,
+        1 => &mut cpu.x,
+        2 => &mut cpu.y,
+        3 => &mut cpu.sp,
+        _ => &mut cpu.sr,
+    }
+}
+
+// "addr,length" in hex
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (addr, len) = text.split_once(',')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let len = u32::from_str_radix(len, 16).ok()?;
+    Some(((addr & 0xFFFF) as u16, len.min(0xFFFF) as u16))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // A gdb stand in talking to the stub over loopback
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        // Sends $packet#cs and returns the reply, checking both checksums
+        fn request(&mut self, packet: &str) -> String {
+            let sum = packet.bytes().fold(0u8, |s, c| s.wrapping_add(c));
+            write!(self.stream, "${}#{:02x}", packet, sum).unwrap();
+            assert_eq!(self.byte(), b'+', "no ack for {}", packet);
+
+            assert_eq!(self.byte(), b'$');
+            let mut reply = Vec::new();
+            loop {
+                match self.byte() {
+                    b'#' => break,
+                    c => reply.push(c),
+                }
+            }
+            let checksum = [self.byte(), self.byte()];
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16);
+            let sum = reply.iter().fold(0u8, |s, c| s.wrapping_add(*c));
+            assert_eq!(checksum, Ok(sum), "bad checksum on reply to {}", packet);
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(reply).unwrap()
+        }
+
+        fn byte(&mut self) -> u8 {
+            let mut buf = [0u8; 1];
+            self.stream.read_exact(&mut buf).unwrap();
+            buf[0]
+        }
+    }
+
+    #[test]
+    fn serves_a_loopback_client() {
+        let listener = GdbStub::bind(0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            // $8000 LDA #$42, STA $0200, JMP $8000
+            let mut cpu = Cpu6502::new();
+            let program = [0xA9, 0x42, 0x8D, 0x00, 0x02, 0x4C, 0x00, 0x80];
+            for (i, b) in program.iter().enumerate() {
+                cpu.bus.write(&(0x8000 + i as u16), b);
+            }
+            cpu.reset();
+            cpu.pc = 0x8000;
+            let mut debugger = Debugger::new();
+            let mut stub = GdbStub::accept(&listener).unwrap();
+            stub.serve(&mut cpu, &mut debugger).unwrap();
+            cpu.bus.read(&0x0301, true)
+        });
+        let mut gdb = Client {
+            stream: TcpStream::connect(("127.0.0.1", port)).unwrap(),
+        };
+
+        // A X Y SP, PC little endian, P
+        let regs = gdb.request("g");
+        assert_eq!(&regs[8..12], "0080");
+
+        assert_eq!(gdb.request("M0300,2:beef"), "OK");
+        assert_eq!(gdb.request("m0300,2"), "beef");
+
+        assert_eq!(gdb.request("Z0,8005,1"), "OK");
+        assert_eq!(gdb.request("c"), "T05swbreak:;");
+        assert_eq!(&gdb.request("g")[8..12], "0580");
+        assert_eq!(gdb.request("z0,8005,1"), "OK");
+
+        assert_eq!(gdb.request("s"), "S05");
+        assert_eq!(&gdb.request("g")[8..12], "0080");
+
+        assert_eq!(gdb.request("Z2,0200,1"), "OK");
+        assert_eq!(gdb.request("c"), "T05watch:0200;");
+        assert_eq!(gdb.request("m0200,1"), "42");
+        assert_eq!(gdb.request("z2,0200,1"), "OK");
+
+        assert_eq!(gdb.request("Z4,0200,1"), "OK");
+        assert_eq!(gdb.request("c"), "T05awatch:0200;");
+
+        assert_eq!(gdb.request("D"), "OK");
+        assert_eq!(server.join().unwrap(), 0xEF);
+    }
+}
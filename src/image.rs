@@ -0,0 +1,119 @@
+use crate::zip::{crc32, zlib_store};
+use std::path::Path;
+
+// An RGB picture, for screenshots. Written as PNG, or as binary PPM for
+// tools that would rather not decode anything. Both are lossless; the PNG
+// is stored rather than compressed, which keeps the writer tiny.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // Three bytes a pixel, rows top to bottom
+    pub rgb: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            rgb: vec![0; width * height * 3],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.width + x) * 3;
+        [self.rgb[i], self.rgb[i + 1], self.rgb[i + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        let i = (y * self.width + x) * 3;
+        self.rgb[i..i + 3].copy_from_slice(&color);
+    }
+
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        out.extend_from_slice(&self.rgb);
+        out
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bits a channel, truecolour, deflate, standard filters, no
+        // interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        // Every row starts with its filter type, 0 for none
+        let mut rows = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.rgb.chunks(self.width * 3) {
+            rows.push(0);
+            rows.extend_from_slice(row);
+        }
+
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut out, b"IHDR", &header);
+        png_chunk(&mut out, b"IDAT", &zlib_store(&rows));
+        png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    // PNG unless the file name ends in .ppm
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let is_ppm = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("ppm"));
+        let data = if is_ppm { self.to_ppm() } else { self.to_png() };
+        std::fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+fn png_chunk(out: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(tag);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Image {
+        let mut image = Image::new(2, 2);
+        image.set_pixel(0, 0, [255, 0, 0]);
+        image.set_pixel(1, 0, [0, 255, 0]);
+        image.set_pixel(0, 1, [0, 0, 255]);
+        image.set_pixel(1, 1, [255, 255, 255]);
+        image
+    }
+
+    #[test]
+    fn ppm_is_a_header_and_the_pixels() {
+        let mut expected = b"P6\n2 2\n255\n".to_vec();
+        expected.extend_from_slice(&[255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+        assert_eq!(image().to_ppm(), expected);
+    }
+
+    #[test]
+    fn png_matches_a_known_file() {
+        let expected = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, // signature
+            0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, // IHDR
+            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0xFD,
+            0xD4, 0x9A, 0x73, //
+            0x00, 0x00, 0x00, 0x19, 0x49, 0x44, 0x41, 0x54, // IDAT
+            0x78, 0x01, 0x01, 0x0E, 0x00, 0xF1, 0xFF, // one stored block
+            0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF, 0x00, //
+            0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, //
+            0x1F, 0xEE, 0x05, 0xFB, 0xDE, 0xDD, 0xEC, 0x2B, //
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, // IEND
+            0xAE, 0x42, 0x60, 0x82,
+        ];
+        assert_eq!(image().to_png(), expected);
+    }
+}
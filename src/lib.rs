@@ -0,0 +1,30 @@
+pub mod assembler;
+pub mod audio;
+pub mod battery;
+pub mod bus;
+pub mod call_stack;
+pub mod cartridge;
+pub mod cheat_search;
+pub mod cheats;
+pub mod code_data_logger;
+pub mod controller;
+pub mod cpu_6502;
+pub mod debugger;
+pub mod disassembler;
+pub mod expression;
+pub mod frame_buffer;
+pub mod gdb_stub;
+pub mod image;
+pub mod memory_editor;
+pub mod movie;
+pub mod ntsc;
+pub mod palette;
+pub mod ppu_viewer;
+pub mod profiler;
+pub mod rewind;
+pub mod save_state;
+pub mod symbols;
+pub mod trace_logger;
+pub mod video_filter;
+pub mod video_recorder;
+pub mod zip;
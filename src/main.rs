@@ -1,5 +1,3 @@
-mod bus;
-mod cpu_6502;
 mod olc_nes_video1_6502;
 
 extern crate olc_pixel_game_engine;
@@ -0,0 +1,312 @@
+use crate::cpu_6502::Cpu6502;
+
+// The state behind a hex editor panel: which memory it shows, where it is
+// scrolled to, the byte under the cursor and a half typed byte. It also
+// remembers what the memory held at the start of the frame so the panel
+// can pick out what changed, and finds byte patterns.
+//
+// Editing goes straight to the memory, without the side effects a write
+// from the cpu would have. Freezing an address is done by the debugger,
+// which puts the value back before every instruction (see Freeze).
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AddressSpace {
+    // What the cpu sees, $0000-$FFFF
+    Cpu,
+    // $0000-$3FFF of the PPU's bus
+    Ppu,
+    Oam,
+    PrgRom,
+    Chr,
+    // $6000-$7FFF
+    SaveRam,
+}
+
+impl AddressSpace {
+    pub const ALL: [AddressSpace; 6] = [
+        AddressSpace::Cpu,
+        AddressSpace::Ppu,
+        AddressSpace::Oam,
+        AddressSpace::PrgRom,
+        AddressSpace::Chr,
+        AddressSpace::SaveRam,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AddressSpace::Cpu => "cpu",
+            AddressSpace::Ppu => "ppu",
+            AddressSpace::Oam => "oam",
+            AddressSpace::PrgRom => "prg",
+            AddressSpace::Chr => "chr",
+            AddressSpace::SaveRam => "sram",
+        }
+    }
+
+    pub fn by_name(name: &str) -> Option<AddressSpace> {
+        let name = name.to_ascii_lowercase();
+        AddressSpace::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    // The next space in ALL, wrapping round
+    pub fn next(self) -> AddressSpace {
+        let i = AddressSpace::ALL
+            .iter()
+            .position(|s| *s == self)
+            .unwrap_or(0);
+        AddressSpace::ALL[(i + 1) % AddressSpace::ALL.len()]
+    }
+
+    pub fn size(self, cpu: &Cpu6502) -> usize {
+        let cartridge = cpu.bus.cartridge.as_ref();
+        match self {
+            AddressSpace::Cpu => 0x10000,
+            AddressSpace::Ppu => 0x4000,
+            AddressSpace::Oam => 0x100,
+            AddressSpace::PrgRom => cartridge.map_or(0, |c| c.prg.len()),
+            AddressSpace::Chr => cartridge.map_or(0, |c| c.chr.len()),
+            AddressSpace::SaveRam => 0x2000,
+        }
+    }
+
+    // Reads without side effects. There is no PPU yet, so of its bus only
+    // the pattern tables ($0000-$1FFF, the cartridge's CHR) hold anything,
+    // and OAM is all zero.
+    pub fn read(self, cpu: &Cpu6502, addr: usize) -> u8 {
+        let cartridge = cpu.bus.cartridge.as_ref();
+        match self {
+            AddressSpace::Cpu => cpu.bus.read(&(addr as u16), true),
+            AddressSpace::Ppu if addr < 0x2000 => cartridge
+                .and_then(|c| c.chr.get(addr).copied())
+                .unwrap_or(0),
+            AddressSpace::Ppu | AddressSpace::Oam => 0,
+            AddressSpace::PrgRom => cartridge
+                .and_then(|c| c.prg.get(addr).copied())
+                .unwrap_or(0),
+            AddressSpace::Chr => cartridge
+                .and_then(|c| c.chr.get(addr).copied())
+                .unwrap_or(0),
+            AddressSpace::SaveRam => cpu.bus.ram[0x6000 + (addr & 0x1FFF)],
+        }
+    }
+
+    // A write to PRG also changes the copies of it the cpu runs from
+    pub fn write(self, cpu: &mut Cpu6502, addr: usize, value: u8) -> Result<(), String> {
+        if addr >= self.size(cpu) {
+            return Err(format!("${:X} is past the end of {}", addr, self.name()));
+        }
+        let cartridge = cpu.bus.cartridge.as_mut();
+        match self {
+            AddressSpace::Cpu => cpu.bus.ram[addr] = value,
+            AddressSpace::Ppu if addr < 0x2000 => match cartridge {
+                Some(c) if addr < c.chr.len() => c.chr[addr] = value,
+                _ => return Err("there is no CHR to write to".to_string()),
+            },
+            AddressSpace::Ppu | AddressSpace::Oam => {
+                return Err(format!("{} cannot be written without a PPU", self.name()))
+            }
+            AddressSpace::PrgRom => {
+                let c = cartridge.ok_or("no cartridge")?;
+                c.prg[addr] = value;
+                for mapped in c.cpu_addresses(addr) {
+                    cpu.bus.ram[mapped as usize] = value;
+                }
+            }
+            AddressSpace::Chr => cartridge.ok_or("no cartridge")?.chr[addr] = value,
+            AddressSpace::SaveRam => cpu.bus.ram[0x6000 + addr] = value,
+        }
+        Ok(())
+    }
+}
+
+// An address held at a value, whatever the program writes there
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Freeze {
+    pub space: AddressSpace,
+    pub addr: usize,
+    pub value: u8,
+}
+
+impl Freeze {
+    pub fn apply(&self, cpu: &mut Cpu6502) {
+        if self.space.read(cpu, self.addr) != self.value {
+            let _ = self.space.write(cpu, self.addr, self.value);
+        }
+    }
+}
+
+// A byte pattern as typed, "A9 ?? 8D" with ?? matching anything. Spaces
+// are optional between bytes.
+pub fn parse_pattern(text: &str) -> Result<Vec<Option<u8>>, String> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("'{}' is not a list of hex bytes", text));
+    }
+    let mut pattern = Vec::new();
+    for pair in digits.chunks(2) {
+        let byte: String = pair.iter().collect();
+        if byte == "??" {
+            pattern.push(None);
+        } else if pair.iter().all(|c| c.is_ascii_hexdigit()) {
+            pattern.push(Some(u8::from_str_radix(&byte, 16).unwrap()));
+        } else {
+            return Err(format!("'{}' is not a hex byte", byte));
+        }
+    }
+    Ok(pattern)
+}
+
+pub struct MemoryEditor {
+    space: AddressSpace,
+    // First address shown
+    top: usize,
+    cursor: usize,
+    rows: usize,
+    // The first digit of a byte being typed
+    typed: Option<u8>,
+    // The space as it was at the start of the frame
+    previous: Vec<u8>,
+}
+
+pub const COLUMNS: usize = 16;
+
+impl MemoryEditor {
+    pub fn new(rows: usize) -> Self {
+        Self {
+            space: AddressSpace::Cpu,
+            top: 0,
+            cursor: 0,
+            rows: rows.max(1),
+            typed: None,
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn space(&self) -> AddressSpace {
+        self.space
+    }
+
+    pub fn set_space(&mut self, space: AddressSpace) {
+        self.space = space;
+        self.top = 0;
+        self.cursor = 0;
+        self.typed = None;
+        self.previous.clear();
+    }
+
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    // The first digit typed of the byte under the cursor, if any
+    pub fn typed(&self) -> Option<u8> {
+        self.typed
+    }
+
+    // Moves the cursor to addr, scrolling it into view
+    pub fn goto(&mut self, cpu: &Cpu6502, addr: usize) {
+        let size = self.space.size(cpu);
+        if size == 0 {
+            return;
+        }
+        self.cursor = addr.min(size - 1);
+        self.typed = None;
+        let page = self.rows * COLUMNS;
+        if self.cursor < self.top {
+            self.top = self.cursor - self.cursor % COLUMNS;
+        } else if self.cursor >= self.top + page {
+            self.top = self.cursor - self.cursor % COLUMNS + COLUMNS - page;
+        }
+    }
+
+    // Moves the cursor by a number of bytes, 16 being a row down
+    pub fn move_cursor(&mut self, cpu: &Cpu6502, delta: isize) {
+        let addr = (self.cursor as isize + delta).max(0) as usize;
+        self.goto(cpu, addr);
+    }
+
+    // Takes a hex digit. The second digit of a byte writes it and moves on
+    // to the next.
+    pub fn type_digit(&mut self, cpu: &mut Cpu6502, digit: u8) -> Result<(), String> {
+        let digit = digit & 0x0F;
+        match self.typed.take() {
+            None => {
+                self.typed = Some(digit);
+                Ok(())
+            }
+            Some(high) => {
+                self.space.write(cpu, self.cursor, (high << 4) | digit)?;
+                self.move_cursor(cpu, 1);
+                Ok(())
+            }
+        }
+    }
+
+    // Remembers the space as it is now, for changed()
+    pub fn begin_frame(&mut self, cpu: &Cpu6502) {
+        let size = self.space.size(cpu);
+        self.previous = (0..size).map(|a| self.space.read(cpu, a)).collect();
+    }
+
+    // Whether addr holds something other than at the last begin_frame
+    pub fn changed(&self, cpu: &Cpu6502, addr: usize) -> bool {
+        self.previous
+            .get(addr)
+            .is_some_and(|old| *old != self.space.read(cpu, addr))
+    }
+
+    // The next place after the cursor the pattern is found, wrapping round
+    // to the start. The cursor is moved there.
+    pub fn find_next(&mut self, cpu: &Cpu6502, pattern: &[Option<u8>]) -> Option<usize> {
+        let found = search(cpu, self.space, pattern, self.cursor + 1)?;
+        self.goto(cpu, found);
+        Some(found)
+    }
+}
+
+// Where pattern first matches in space at or after from, wrapping round to
+// the start
+pub fn search(
+    cpu: &Cpu6502,
+    space: AddressSpace,
+    pattern: &[Option<u8>],
+    from: usize,
+) -> Option<usize> {
+    let size = space.size(cpu);
+    if pattern.is_empty() || pattern.len() > size {
+        return None;
+    }
+    let last = size - pattern.len();
+    let matches = |start: usize| {
+        pattern
+            .iter()
+            .enumerate()
+            .all(|(i, p)| p.is_none_or(|value| space.read(cpu, start + i) == value))
+    };
+    let from = from.min(last + 1);
+    (from..=last).chain(0..from).find(|start| matches(*start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_patterns() {
+        assert_eq!(
+            parse_pattern("A9 ?? 8d00").unwrap(),
+            [Some(0xA9), None, Some(0x8D), Some(0x00)]
+        );
+        for bad in ["", "A", "A9 ?", "G0", "+F", "?A", "aéb", "éé", "A9 日本"] {
+            assert!(parse_pattern(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,737 @@
+use crate::cartridge::Cartridge;
+use crate::controller::{buttons_from_fm2, buttons_to_fm2, Buttons};
+use crate::cpu_6502::Cpu6502;
+use crate::debugger::CPU_CYCLES_PER_FRAME;
+use crate::save_state;
+use crate::zip::{read_zip, write_zip};
+use std::fmt::Write;
+use std::path::Path;
+
+// Input movies: the buttons held on every frame, from power on or from a
+// save state. Played back from the same start they reproduce a run exactly,
+// since input is the only thing the machine does not decide for itself.
+//
+// Two formats are read and written:
+//
+//     .fm2    FCEUX, a text header and one "|commands|RLDUTSBA|RLDUTSBA||"
+//             line per frame
+//     .bk2    BizHawk, a zip holding Header.txt and "Input Log.txt" with
+//             the buttons in the order its LogKey line gives
+//
+// A movie that starts from a save state carries one of ours, other
+// emulators cannot use it (nor we theirs).
+
+// Per frame commands, as FCEUX numbers them
+pub struct Commands;
+impl Commands {
+    pub const SOFT_RESET: u8 = 1 << 0;
+    pub const POWER: u8 = 1 << 1;
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub pads: [u8; 2],
+}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Movie {
+    pub rom_name: String,
+    // MD5 of the PRG and CHR ROM, which FCEUX checks the game against
+    pub rom_checksum: Option<[u8; 16]>,
+    pub author: String,
+    pub rerecords: u32,
+    // Where the movie starts, power on when None
+    pub savestate: Option<Vec<u8>>,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        match extension(path).as_str() {
+            "fm2" => Movie::from_fm2(&String::from_utf8_lossy(&data)),
+            "bk2" => Movie::from_bk2(&data),
+            _ => Err(format!("{}: unknown movie type", path.display())),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = match extension(path).as_str() {
+            "fm2" => self.to_fm2().into_bytes(),
+            "bk2" => self.to_bk2(),
+            _ => return Err(format!("{}: unknown movie type", path.display())),
+        };
+        std::fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // FM2
+
+    pub fn from_fm2(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if let Some(input) = line.strip_prefix('|') {
+                let fields: Vec<&str> = input.split('|').collect();
+                let commands = fields
+                    .first()
+                    .and_then(|c| c.trim().parse().ok())
+                    .ok_or(format!("line {}: bad commands", i + 1))?;
+                let pad = |n: usize| fields.get(n).map_or(0, |p| buttons_from_fm2(p));
+                movie.frames.push(MovieFrame {
+                    commands,
+                    pads: [pad(1), pad(2)],
+                });
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "version" if value != "3" => {
+                    return Err(format!("fm2 version {} not supported", value))
+                }
+                "romFilename" => movie.rom_name = value.to_string(),
+                "romChecksum" => {
+                    let encoded = value.strip_prefix("base64:").unwrap_or(value);
+                    movie.rom_checksum = base64_decode(encoded)?.try_into().ok();
+                }
+                "rerecordCount" => movie.rerecords = value.parse().unwrap_or(0),
+                "comment" => {
+                    if let Some(author) = value.strip_prefix("author ") {
+                        movie.author = author.to_string();
+                    }
+                }
+                "savestate" => {
+                    let encoded = value.strip_prefix("base64:").unwrap_or(value);
+                    movie.savestate = Some(base64_decode(encoded)?);
+                }
+                _ => (),
+            }
+        }
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "version 3");
+        let _ = writeln!(out, "emuVersion 22020");
+        let _ = writeln!(out, "rerecordCount {}", self.rerecords);
+        let _ = writeln!(out, "palFlag 0");
+        let _ = writeln!(out, "romFilename {}", self.rom_name);
+        if let Some(checksum) = &self.rom_checksum {
+            let _ = writeln!(out, "romChecksum base64:{}", base64_encode(checksum));
+        }
+        let _ = writeln!(out, "guid 00000000-0000-0000-0000-000000000000");
+        let _ = writeln!(out, "fourscore 0");
+        let _ = writeln!(out, "microphone 0");
+        let _ = writeln!(out, "port0 1");
+        let _ = writeln!(out, "port1 1");
+        let _ = writeln!(out, "port2 0");
+        let _ = writeln!(out, "FDS 0");
+        let _ = writeln!(out, "NewPPU 0");
+        if !self.author.is_empty() {
+            let _ = writeln!(out, "comment author {}", self.author);
+        }
+        if let Some(state) = &self.savestate {
+            let _ = writeln!(out, "savestate base64:{}", base64_encode(state));
+        }
+        for frame in &self.frames {
+            let _ = writeln!(
+                out,
+                "|{}|{}|{}||",
+                frame.commands,
+                buttons_to_fm2(frame.pads[0]),
+                buttons_to_fm2(frame.pads[1])
+            );
+        }
+        out
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // BK2
+
+    pub fn from_bk2(data: &[u8]) -> Result<Movie, String> {
+        let files = read_zip(data)?;
+        let file = |name: &str| {
+            files
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, contents)| contents)
+        };
+
+        let mut movie = Movie::default();
+        let header = file("Header.txt").ok_or("no Header.txt")?;
+        let mut from_state = false;
+        for line in String::from_utf8_lossy(header).lines() {
+            let (key, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match key {
+                "Author" => movie.author = value.to_string(),
+                "GameName" => movie.rom_name = value.to_string(),
+                "rerecordCount" => movie.rerecords = value.parse().unwrap_or(0),
+                "StartsFromSavestate" => from_state = value.eq_ignore_ascii_case("true"),
+                "Platform" if value != "NES" => {
+                    return Err(format!("{} movies are not supported", value))
+                }
+                _ => (),
+            }
+        }
+        if from_state {
+            movie.savestate = Some(file("Core.bin").ok_or("no Core.bin")?.clone());
+        }
+
+        let log = file("Input Log.txt").ok_or("no Input Log.txt")?;
+        let log = String::from_utf8_lossy(log);
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        for line in log.lines() {
+            if let Some(key) = line.strip_prefix("LogKey:") {
+                groups = key
+                    .split('#')
+                    .filter(|g| !g.is_empty())
+                    .map(|g| {
+                        g.split('|')
+                            .filter(|n| !n.is_empty())
+                            .map(|n| n.to_string())
+                            .collect()
+                    })
+                    .collect();
+            } else if let Some(input) = line.strip_prefix('|') {
+                let mut frame = MovieFrame::default();
+                for (group, text) in groups.iter().zip(input.split('|')) {
+                    for (name, c) in group.iter().zip(text.chars()) {
+                        if c != '.' && c != ' ' {
+                            bk2_press(&mut frame, name);
+                        }
+                    }
+                }
+                movie.frames.push(frame);
+            }
+        }
+        Ok(movie)
+    }
+
+    pub fn to_bk2(&self) -> Vec<u8> {
+        let mut header = String::new();
+        let _ = writeln!(header, "MovieVersion BizHawk v2.0.0");
+        let _ = writeln!(header, "Author {}", self.author);
+        let _ = writeln!(header, "Platform NES");
+        let _ = writeln!(header, "GameName {}", self.rom_name);
+        let _ = writeln!(header, "Core NesHawk");
+        let _ = writeln!(header, "rerecordCount {}", self.rerecords);
+        if self.savestate.is_some() {
+            let _ = writeln!(header, "StartsFromSavestate True");
+        }
+
+        let mut log = String::new();
+        let _ = writeln!(log, "[Input]");
+        let mut key = String::from("LogKey:#Reset|Power|");
+        for pad in 1..=2 {
+            key.push('#');
+            for (name, _, _) in BK2_BUTTONS {
+                let _ = write!(key, "P{} {}|", pad, name);
+            }
+        }
+        let _ = writeln!(log, "{}", key);
+        for frame in &self.frames {
+            let reset = if frame.commands & Commands::SOFT_RESET != 0 {
+                'r'
+            } else {
+                '.'
+            };
+            let power = if frame.commands & Commands::POWER != 0 {
+                'P'
+            } else {
+                '.'
+            };
+            let mut line = format!("|{}{}|", reset, power);
+            for pad in frame.pads {
+                for (_, mnemonic, bit) in BK2_BUTTONS {
+                    line.push(if pad & bit != 0 { mnemonic } else { '.' });
+                }
+                line.push('|');
+            }
+            let _ = writeln!(log, "{}", line);
+        }
+        let _ = writeln!(log, "[/Input]");
+
+        let mut files: Vec<(&str, &[u8])> = vec![
+            ("Header.txt", header.as_bytes()),
+            ("Input Log.txt", log.as_bytes()),
+        ];
+        if let Some(state) = &self.savestate {
+            files.push(("Core.bin", state));
+        }
+        write_zip(&files)
+    }
+}
+
+// NES pad buttons in BizHawk's order, with the letter shown when held
+const BK2_BUTTONS: [(&str, char, u8); 8] = [
+    ("Up", 'U', Buttons::UP),
+    ("Down", 'D', Buttons::DOWN),
+    ("Left", 'L', Buttons::LEFT),
+    ("Right", 'R', Buttons::RIGHT),
+    ("Start", 'S', Buttons::START),
+    ("Select", 's', Buttons::SELECT),
+    ("B", 'B', Buttons::B),
+    ("A", 'A', Buttons::A),
+];
+
+fn bk2_press(frame: &mut MovieFrame, name: &str) {
+    match name {
+        "Reset" => frame.commands |= Commands::SOFT_RESET,
+        "Power" => frame.commands |= Commands::POWER,
+        _ => {
+            let (pad, button) = name.split_once(' ').unwrap_or(("", ""));
+            let pad = match pad {
+                "P1" => 0,
+                "P2" => 1,
+                _ => return,
+            };
+            if let Some((_, _, bit)) = BK2_BUTTONS.iter().find(|(n, _, _)| *n == button) {
+                frame.pads[pad] |= bit;
+            }
+        }
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// RECORDING AND PLAYBACK
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    // Playback ran out of frames, the pads are left alone from here
+    Finished,
+}
+
+// A movie being recorded or played. The debugger calls log() before every
+// instruction, at the first instruction of each frame the pads are either
+// written to the movie or set from it.
+pub struct MovieSession {
+    pub movie: Movie,
+    mode: MovieMode,
+    // Frames recorded or played so far
+    frame: usize,
+    // Machine frame that movie frame start_index was on. A power command
+    // starts the machine's count again from 0.
+    start_frame: u64,
+    start_index: usize,
+    last_frame: Option<u64>,
+    // Commands to record with the next frame
+    pending: u8,
+}
+
+impl MovieSession {
+    // Starts recording from power on, or from the machine as it is now
+    pub fn record(cpu: &mut Cpu6502, from_state: bool) -> Self {
+        let mut movie = Movie {
+            rom_checksum: cpu.bus.cartridge.as_ref().map(rom_checksum),
+            ..Movie::default()
+        };
+        if from_state {
+            movie.savestate = Some(save_state::save(cpu));
+        } else {
+            cpu.power_on();
+        }
+        MovieSession::new(movie, MovieMode::Recording, cpu)
+    }
+
+    // Puts the machine where the movie starts and plays it from there
+    pub fn play(movie: Movie, cpu: &mut Cpu6502) -> Result<Self, String> {
+        match &movie.savestate {
+            Some(state) => save_state::load(cpu, state)?,
+            None => cpu.power_on(),
+        }
+        Ok(MovieSession::new(movie, MovieMode::Playing, cpu))
+    }
+
+    fn new(movie: Movie, mode: MovieMode, cpu: &Cpu6502) -> Self {
+        Self {
+            movie,
+            mode,
+            frame: 0,
+            start_frame: cpu.clock_count / CPU_CYCLES_PER_FRAME,
+            start_index: 0,
+            last_frame: None,
+            pending: 0,
+        }
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    // Recorded with the next frame, and carried out then as well
+    pub fn queue_command(&mut self, commands: u8) {
+        self.pending |= commands;
+    }
+
+    pub fn log(&mut self, cpu: &mut Cpu6502) {
+        let machine_frame = cpu.clock_count / CPU_CYCLES_PER_FRAME;
+        if self.last_frame == Some(machine_frame) {
+            return;
+        }
+        self.last_frame = Some(machine_frame);
+
+        let frame = match self.mode {
+            MovieMode::Recording => {
+                let frame = MovieFrame {
+                    commands: self.pending,
+                    pads: [
+                        cpu.bus.controllers[0].buttons,
+                        cpu.bus.controllers[1].buttons,
+                    ],
+                };
+                self.pending = 0;
+                self.movie.frames.push(frame);
+                frame
+            }
+            MovieMode::Playing => match self.movie.frames.get(self.frame) {
+                Some(frame) => *frame,
+                None => {
+                    self.mode = MovieMode::Finished;
+                    return;
+                }
+            },
+            MovieMode::Finished => return,
+        };
+        self.frame += 1;
+
+        if frame.commands & Commands::POWER != 0 {
+            // The clock is back at 0, still in the frame just logged
+            cpu.power_on();
+            self.last_frame = Some(0);
+            self.start_frame = 0;
+            self.start_index = self.frame - 1;
+        } else if frame.commands & Commands::SOFT_RESET != 0 {
+            cpu.reset();
+        }
+        for (pad, buttons) in cpu.bus.controllers.iter_mut().zip(frame.pads) {
+            pad.buttons = buttons;
+        }
+    }
+
+    // The machine was taken back in time (rewind or a state load). While
+    // recording, everything after that point is thrown away and recorded
+    // again, which counts as a rerecord.
+    pub fn rewound(&mut self, cpu: &Cpu6502) {
+        let machine_frame = cpu.clock_count / CPU_CYCLES_PER_FRAME;
+        let frame = self.start_index + machine_frame.saturating_sub(self.start_frame) as usize;
+        if self.mode == MovieMode::Recording && frame < self.movie.frames.len() {
+            self.movie.frames.truncate(frame);
+            self.movie.rerecords += 1;
+        }
+        self.frame = frame;
+        self.last_frame = None;
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MD5
+
+// What FCEUX calls the game's checksum, the MD5 of its PRG then CHR ROM
+pub fn rom_checksum(cartridge: &Cartridge) -> [u8; 16] {
+    let mut rom = cartridge.prg.clone();
+    rom.extend_from_slice(&cartridge.chr);
+    md5(&rom)
+}
+
+// RFC 1321
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [[u32; 4]; 4] = [
+        [7, 12, 17, 22],
+        [5, 9, 14, 20],
+        [4, 11, 16, 23],
+        [6, 10, 15, 21],
+    ];
+    let constants: Vec<u32> = (1..=64)
+        .map(|i: u32| (f64::from(i).sin().abs() * 4_294_967_296.0) as u32)
+        .collect();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+    for block in message.chunks(64) {
+        let words: Vec<u32> = block
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let sum = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(sum.rotate_left(SHIFTS[i / 16][i % 4]));
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 16];
+    for (i, s) in state.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&s.to_le_bytes());
+    }
+    digest
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// BASE64
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in text
+        .bytes()
+        .filter(|c| *c != b'=' && !c.is_ascii_whitespace())
+    {
+        let value = BASE64
+            .iter()
+            .position(|b| *b == c)
+            .ok_or(format!("bad base64 character '{}'", c as char))?;
+        n = (n << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Debugger;
+
+    fn movie() -> Movie {
+        let frames = (0..40u32)
+            .map(|i| MovieFrame {
+                commands: match i {
+                    3 => Commands::SOFT_RESET,
+                    9 => Commands::POWER,
+                    _ => 0,
+                },
+                pads: [(i * 37) as u8, (i * 11 + 5) as u8],
+            })
+            .collect();
+        Movie {
+            rom_name: "test".to_string(),
+            rom_checksum: Some(md5(b"test")),
+            author: "someone".to_string(),
+            rerecords: 12,
+            savestate: Some(vec![0, 1, 2, 250, 251, 252, 253]),
+            frames,
+        }
+    }
+
+    // Adds what it reads from the first pad to $10 forever
+    fn cpu() -> Cpu6502 {
+        let mut cpu = Cpu6502::new();
+        let program = [
+            0xA9, 0x01, // LDA #$01
+            0x8D, 0x16, 0x40, // STA $4016
+            0xA9, 0x00, // LDA #$00
+            0x8D, 0x16, 0x40, // STA $4016
+            0xAD, 0x16, 0x40, // LDA $4016
+            0x65, 0x10, // ADC $10
+            0x85, 0x10, // STA $10
+            0x4C, 0x00, 0x80, // JMP $8000
+        ];
+        for (i, b) in program.iter().enumerate() {
+            cpu.bus.write(&(0x8000 + i as u16), b);
+        }
+        cpu.bus.write(&0xFFFC, &0x00);
+        cpu.bus.write(&0xFFFD, &0x80);
+        cpu
+    }
+
+    // Runs one movie frame, stopping where the next one would be logged
+    fn run_frame(debugger: &mut Debugger, cpu: &mut Cpu6502) {
+        let frame = debugger.movie().unwrap().frame();
+        while debugger.movie().unwrap().frame() == frame {
+            debugger.update(cpu, 1);
+        }
+        let machine_frame = cpu.clock_count / CPU_CYCLES_PER_FRAME;
+        while cpu.clock_count / CPU_CYCLES_PER_FRAME == machine_frame {
+            debugger.update(cpu, 1);
+        }
+    }
+
+    #[test]
+    fn md5_matches_rfc_1321() {
+        let hex = |data: &[u8]| {
+            md5(data)
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        };
+        assert_eq!(hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            ),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+
+    #[test]
+    fn fm2_round_trips() {
+        let movie = movie();
+        let text = movie.to_fm2();
+        assert_eq!(Movie::from_fm2(&text).unwrap(), movie);
+
+        // Without a checksum the line is left out rather than made up
+        let movie = Movie {
+            rom_checksum: None,
+            ..movie
+        };
+        let text = movie.to_fm2();
+        assert!(!text.contains("romChecksum"));
+        assert_eq!(Movie::from_fm2(&text).unwrap(), movie);
+    }
+
+    #[test]
+    fn bk2_round_trips() {
+        // BizHawk has no place for FCEUX's checksum
+        let movie = Movie {
+            rom_checksum: None,
+            ..movie()
+        };
+        assert_eq!(Movie::from_bk2(&movie.to_bk2()).unwrap(), movie);
+    }
+
+    #[test]
+    fn reads_a_deflated_bizhawk_movie() {
+        let movie = Movie::from_bk2(include_bytes!("../testdata/deflated.bk2")).unwrap();
+        assert_eq!(movie.author, "someone");
+        assert_eq!(movie.rom_name, "test");
+        assert_eq!(movie.rerecords, 3);
+        assert_eq!(movie.frames.len(), 120);
+        // |..|..LR.sB.|
+        assert_eq!(
+            movie.frames[0].pads[0],
+            Buttons::LEFT | Buttons::RIGHT | Buttons::SELECT | Buttons::B
+        );
+        // |r.|U...S...|
+        assert_eq!(
+            movie.frames[50],
+            MovieFrame {
+                commands: Commands::SOFT_RESET,
+                pads: [Buttons::UP | Buttons::START, 0],
+            }
+        );
+    }
+
+    #[test]
+    fn playing_a_recording_does_the_same_again() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.set_movie(Some(MovieSession::record(&mut cpu, false)));
+        debugger.resume();
+        for frame in 1..=12 {
+            cpu.bus.controllers[0].buttons = (frame * 37) as u8;
+            if frame == 6 {
+                debugger.movie_mut().unwrap().queue_command(Commands::POWER);
+            }
+            run_frame(&mut debugger, &mut cpu);
+        }
+        // Power on started the machine's frames again, on the frame it came in
+        assert_eq!(cpu.clock_count / CPU_CYCLES_PER_FRAME, 12 - 6 + 1);
+        let recorded = debugger.set_movie(None).unwrap().movie;
+        assert_eq!(recorded.frames.len(), 12);
+        assert_eq!(recorded.frames[5].commands, Commands::POWER);
+        let expected = (save_state::save(&cpu), cpu.bus.ram);
+
+        let mut cpu = self::cpu();
+        let mut debugger = Debugger::new();
+        debugger.set_movie(Some(MovieSession::play(recorded, &mut cpu).unwrap()));
+        debugger.resume();
+        for _ in 1..=12 {
+            run_frame(&mut debugger, &mut cpu);
+        }
+        assert_eq!((save_state::save(&cpu), cpu.bus.ram), expected);
+        assert_ne!(cpu.bus.ram[0x10], 0);
+    }
+
+    #[test]
+    fn a_power_on_does_not_stretch_the_budget() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.set_movie(Some(MovieSession::record(&mut cpu, false)));
+        debugger.resume();
+        for frame in 1..=8 {
+            if frame == 4 {
+                debugger.movie_mut().unwrap().queue_command(Commands::POWER);
+            }
+            run_frame(&mut debugger, &mut cpu);
+        }
+        let recorded = debugger.set_movie(None).unwrap().movie;
+
+        // The budget is cycles run, not where the clock has to get to, so it
+        // ends in the same place with the clock put back part way
+        let mut cpu = self::cpu();
+        let mut debugger = Debugger::new();
+        debugger.set_movie(Some(MovieSession::play(recorded, &mut cpu).unwrap()));
+        debugger.resume();
+        debugger.update(&mut cpu, 1);
+        let start = debugger.elapsed();
+        let budget = 10 * CPU_CYCLES_PER_FRAME;
+        assert_eq!(debugger.update(&mut cpu, budget), None);
+        let end = start + budget;
+        assert!((end..end + 8).contains(&debugger.elapsed()));
+        assert!(cpu.clock_count < budget - 2 * CPU_CYCLES_PER_FRAME);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::frame_buffer::{FrameBuffer, Overscan, HEIGHT, WIDTH};
+use crate::image::Image;
+use crate::palette::Palette;
+use std::f32::consts::PI;
+
+// The PPU does not put out RGB. It puts out a composite NTSC signal, a
+// square wave between two voltages at one of twelve phases of the colour
+// subcarrier, eight samples of the 12 phase clock per pixel. The TV turns
+// that back into colours, and how it does so is what the NES's colours
+// really are. Both the palette generator and the filter here model that:
+// build the signal, then decode it into YIQ and RGB the way an ideal TV
+// would, with the usual knobs.
+//
+// The palette generator decodes each colour on its own, a flat field of
+// it. The filter decodes the picture as one signal per scanline, so
+// neighbouring pixels bleed into each other and the subcarrier crawls
+// from frame to frame, as on a real TV. Voltages from nesdev's "NTSC
+// video" page.
+
+// Signal voltages for the four luma levels, low and high halves
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+// Emphasis pulls the signal down by this much during its colour's phases
+const ATTENUATION: f32 = 0.746;
+// Lines up the decoder's phase with the colour burst
+const PHASE_OFFSET: f32 = 4.0;
+
+// TV controls. Hue is in degrees, the rest are 1.0 (or 0.0 for brightness)
+// when left alone. Gamma is that of the display, 2.2 means the same as the
+// CRTs the signal was meant for.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NtscParams {
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    pub gamma: f32,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        NtscParams::new()
+    }
+}
+
+impl NtscParams {
+    pub fn new() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 2.2,
+        }
+    }
+
+    // Luma and the I and Q chroma components to RGB, with the controls
+    // applied
+    fn to_rgb(self, y: f32, i: f32, q: f32) -> [u8; 3] {
+        let (sin, cos) = self.hue.to_radians().sin_cos();
+        let i2 = (i * cos - q * sin) * self.saturation;
+        let q2 = (i * sin + q * cos) * self.saturation;
+        let y = y * self.contrast + self.brightness;
+
+        let rgb = [
+            y + 0.946882 * i2 + 0.623557 * q2,
+            y - 0.274788 * i2 - 0.635691 * q2,
+            y - 1.108545 * i2 + 1.709007 * q2,
+        ];
+        rgb.map(|c| {
+            let c = c.clamp(0.0, 1.0).powf(2.2 / self.gamma);
+            (c * 255.0).round() as u8
+        })
+    }
+}
+
+// The signal for a colour number (emphasis bits included) at one of the
+// twelve phases, 0 for black and 1 for white
+fn signal(pixel: u16, phase: usize) -> f32 {
+    let color = (pixel & 0x0F) as usize;
+    let emphasis = pixel >> 6;
+    // $xE and $xF are black whatever the luma bits say
+    let level = if color > 13 {
+        1
+    } else {
+        ((pixel >> 4) & 3) as usize
+    };
+
+    let in_phase = |c: usize| (c + phase) % 12 < 6;
+    let low = LEVELS[level + 4 * (color == 0) as usize];
+    let high = LEVELS[level + 4 * (color < 13) as usize];
+    let mut voltage = if in_phase(color) { high } else { low };
+
+    let emphasized = (emphasis & 1 != 0 && in_phase(0))
+        || (emphasis & 2 != 0 && in_phase(4))
+        || (emphasis & 4 != 0 && in_phase(8));
+    if emphasized {
+        voltage *= ATTENUATION;
+    }
+    (voltage - BLACK) / (WHITE - BLACK)
+}
+
+// What the demodulator multiplies a sample at a phase by to get I and Q.
+// The 2 makes up for the square wave spending half its time at each level.
+fn carrier(phase: f32) -> (f32, f32) {
+    let (sin, cos) = (PI * (phase + PHASE_OFFSET) / 6.0).sin_cos();
+    (2.0 * cos, 2.0 * sin)
+}
+
+// All 512 colours, every colour number with every emphasis combination
+pub fn generate_palette(params: &NtscParams) -> Palette {
+    let mut pal = Vec::with_capacity(512 * 3);
+    for pixel in 0..512u16 {
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for phase in 0..12 {
+            let s = signal(pixel, phase);
+            let (ci, cq) = carrier(phase as f32);
+            y += s;
+            i += s * ci;
+            q += s * cq;
+        }
+        pal.extend_from_slice(&params.to_rgb(y / 12.0, i / 12.0, q / 12.0));
+    }
+    Palette::from_pal(&pal).expect("512 colours is a valid palette")
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// COMPOSITE FILTER
+
+// Output pixels per NES pixel, each decoded around its own point of the
+// signal
+pub const FILTER_SCALE: usize = 2;
+const SAMPLES_PER_PIXEL: usize = 8;
+
+pub struct NtscFilter {
+    params: NtscParams,
+    // Carrier at each of the twelve phases, worked out once
+    carrier: [(f32, f32); 12],
+}
+
+impl NtscFilter {
+    pub fn new(params: NtscParams) -> Self {
+        let mut table = [(0.0, 0.0); 12];
+        for (phase, c) in table.iter_mut().enumerate() {
+            *c = carrier(phase as f32);
+        }
+        Self {
+            params,
+            carrier: table,
+        }
+    }
+
+    // Decodes a frame at FILTER_SCALE times the width. The frame number
+    // picks where the subcarrier starts: it moves on by a third of a cycle
+    // each frame and each line (341 dots of 8 samples is 4 phases over a
+    // whole number of cycles), which is the dot crawl.
+    pub fn apply(&self, screen: &FrameBuffer, overscan: Overscan, frame: u64) -> Image {
+        let left = overscan.left * FILTER_SCALE;
+        let width = (WIDTH * FILTER_SCALE).saturating_sub(left + overscan.right * FILTER_SCALE);
+        let height = HEIGHT.saturating_sub(overscan.top + overscan.bottom);
+        let mut image = Image::new(width, height);
+
+        let samples = WIDTH * SAMPLES_PER_PIXEL;
+        let step = SAMPLES_PER_PIXEL / FILTER_SCALE;
+        let mut line = vec![0.0f32; samples];
+        for out_y in 0..height {
+            let y = out_y + overscan.top;
+            let start = (frame as usize % 3) * 4 + y * 4;
+            for (n, sample) in line.iter_mut().enumerate() {
+                let pixel = screen.pixel(n / SAMPLES_PER_PIXEL, y);
+                *sample = signal(pixel, (start + n) % 12);
+            }
+
+            for out_x in 0..width {
+                // Twelve samples, a whole subcarrier cycle, centred on the
+                // output pixel. Past the edges the signal is black.
+                let centre = (out_x + left) * step + step / 2;
+                let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+                let first = centre.saturating_sub(6);
+                let window = &line[first..(centre + 6).min(samples)];
+                for (n, s) in (first..).zip(window) {
+                    let (ci, cq) = self.carrier[(start + n) % 12];
+                    luma += s;
+                    i += s * ci;
+                    q += s * cq;
+                }
+                let rgb = self.params.to_rgb(luma / 12.0, i / 12.0, q / 12.0);
+                image.set_pixel(out_x, out_y, rgb);
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blacks_and_whites_come_out_flat() {
+        let palette = generate_palette(&NtscParams::new());
+        for black in [0x0F, 0x1D, 0x1F] {
+            assert_eq!(palette.rgb(black), [0, 0, 0], "{:02X}", black);
+        }
+        assert_eq!(palette.rgb(0x20), [255, 255, 255]);
+        assert_eq!(palette.rgb(0x30), [255, 255, 255]);
+    }
+
+    #[test]
+    fn each_emphasis_bit_darkens_the_other_two_primaries() {
+        let palette = generate_palette(&NtscParams::new());
+        for grey in [0x10, 0x20, 0x30] {
+            let plain = palette.rgb(grey);
+            for (channel, emphasis) in [1u16, 2, 4].into_iter().enumerate() {
+                let rgb = palette.rgb(grey | emphasis << 6);
+                for other in (0..3).filter(|c| *c != channel) {
+                    assert!(rgb[other] + 20 < plain[other], "{:02X} {:?}", grey, rgb);
+                    assert!(rgb[channel] > rgb[other], "{:02X} {:?}", grey, rgb);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hue_and_saturation_change_the_colours() {
+        let plain = generate_palette(&NtscParams::new());
+        let turned = generate_palette(&NtscParams {
+            hue: 30.0,
+            ..NtscParams::new()
+        });
+        let grey = generate_palette(&NtscParams {
+            saturation: 0.0,
+            ..NtscParams::new()
+        });
+        // Hue turns the colours but not the greys
+        assert_ne!(turned.rgb(0x16), plain.rgb(0x16));
+        assert_ne!(turned.rgb(0x2A), plain.rgb(0x2A));
+        assert_eq!(turned.rgb(0x10), plain.rgb(0x10));
+        // With no saturation everything is a grey
+        for pixel in 0..64 {
+            let [r, g, b] = grey.rgb(pixel);
+            assert!(r == g && g == b, "{:02X} {:?}", pixel, [r, g, b]);
+        }
+        assert_ne!(grey.rgb(0x16), plain.rgb(0x16));
+    }
+
+    #[test]
+    fn filter_decodes_flat_fields_like_the_palette() {
+        let filter = NtscFilter::new(NtscParams::new());
+        let mut screen = FrameBuffer::new();
+        screen.clear(0x30);
+        let image = filter.apply(&screen, Overscan::NONE, 0);
+        assert_eq!((image.width, image.height), (WIDTH * FILTER_SCALE, HEIGHT));
+        assert_eq!(image.pixel(100, 100), [255, 255, 255]);
+        screen.clear(0x0F);
+        let image = filter.apply(&screen, Overscan::NTSC, 1);
+        assert!(image.rgb.iter().all(|c| *c == 0));
+
+        // A colour away from the edges decodes close to its palette entry
+        screen.clear(0x16);
+        let image = filter.apply(&screen, Overscan::NONE, 2);
+        let expected = generate_palette(&NtscParams::new()).rgb(0x16);
+        let got = image.pixel(200, 100);
+        for c in 0..3 {
+            assert!(
+                got[c].abs_diff(expected[c]) <= 2,
+                "{:?} {:?}",
+                got,
+                expected
+            );
+        }
+    }
+}
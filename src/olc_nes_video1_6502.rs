@@ -1,21 +1,105 @@
 extern crate olc_pixel_game_engine;
 
-use crate::cpu_6502::{Cpu6502, Flags6502};
 use crate::olc_pixel_game_engine as olc;
+use nes::assembler::Assembler;
+use nes::audio;
+use nes::battery::BatterySave;
+use nes::cartridge::Cartridge;
+use nes::cheat_search::{CheatSearch, Filter, Operand};
+use nes::cpu_6502::{Cpu6502, Flags6502};
+use nes::debugger::{Debugger, StopReason, CPU_CYCLES_PER_FRAME};
+use nes::disassembler::DisassembledInstruction;
+use nes::frame_buffer::Overscan;
+use nes::image::Image;
+use nes::memory_editor::{self, MemoryEditor};
+use nes::palette::Palette;
+use nes::ppu_viewer::{self, PpuMemory};
+use nes::rewind::{self, Rewind};
+use nes::symbols::SymbolTable;
+use nes::video_filter::FilterChain;
+use nes::video_recorder::VideoRecorder;
 use olc_pixel_game_engine::{draw_string, Error, Pixel};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::ops::Add;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::path::{Path, PathBuf};
+
+// Multiplies 10 by 3 with a loop of additions, leaving the result in $0002
+const DEMO_PROGRAM: &str = "
+    *=$8000
+    LDX #10
+    STX $0000
+    LDX #3
+    STX $0001
+    LDY $0000
+    LDA #0
+    CLC
+loop
+    ADC $0001
+    DEY
+    BNE loop
+    STA $0002
+    NOP
+    NOP
+    NOP
+
+    *=$FFFC
+    .word $8000
+";
+
+// Upscaling for screenshots, L steps through these
+const FILTERS: [&str; 10] = [
+    "none", "nearest2", "nearest3", "scale2x", "scale3x", "hq2x", "hq3x", "blend2x", "blend3x",
+    "xbr2x",
+];
 
 pub(crate) struct DemoOlc6502 {
     nes: Cpu6502,
-    map_asm: HashMap<u16, String>,
+    // Game to run in place of the demo program, from the command line
+    rom: Option<PathBuf>,
+    map_asm: BTreeMap<u16, DisassembledInstruction>,
+    symbols: SymbolTable,
+    debugger: Debugger,
+    // Address of the code line selected with UP/DOWN, used by run to cursor
+    // and to toggle breakpoints
+    cursor: u16,
+    // Why the debugger last stopped
+    status: String,
+    // Show the PPU panels in place of the RAM ones
+    ppu_view: bool,
+    // Palette the pattern tables are drawn in, 0-7
+    pattern_palette: usize,
+    // The hex editor in place of the fixed RAM pages, and whether keys go
+    // to it
+    editor: MemoryEditor,
+    editing: bool,
+    // The cheat search panel in place of the memory editor, and the search
+    // it shows, started the first time the panel is
+    searching: bool,
+    search: Option<CheatSearch>,
+    // Which of FILTERS screenshots go through
+    filter: usize,
+    filters: FilterChain,
 }
 
 impl DemoOlc6502 {
     pub fn new() -> Self {
         Self {
             nes: Cpu6502::new(),
-            map_asm: HashMap::new(),
+            rom: None,
+            map_asm: BTreeMap::new(),
+            symbols: SymbolTable::new(),
+            debugger: Debugger::new(),
+            cursor: 0x0000,
+            status: String::new(),
+            ppu_view: false,
+            pattern_palette: 0,
+            editor: MemoryEditor::new(32),
+            editing: false,
+            searching: false,
+            search: None,
+            filter: 0,
+            filters: FilterChain::new(),
         }
     }
 
@@ -33,21 +117,166 @@ impl DemoOlc6502 {
         return sf;
     }
 
-    fn draw_ram(&self, x: i32, y: i32, mut n_addr: u16, n_rows: i32, n_columns: i32) {
-        let n_raw_x = x;
-        let mut n_raw_y = y;
-        let color: Pixel = Pixel::rgb(0xFF, 0xFF, 0xFF);
-        let mut s_offset: String;
-        for _ in 0..n_rows {
-            let s = DemoOlc6502::hex(n_addr.clone() as u32, 4);
-            s_offset = format!("${s}:");
-            for _ in 0..n_columns {
-                s_offset = s_offset.add(" ");
-                s_offset = s_offset.add(&DemoOlc6502::hex(self.nes.read(n_addr.clone()) as u32, 2));
-                n_addr += 1;
+    // The memory editor's rows. Bytes that changed since the cpu last ran
+    // are yellow and frozen ones red, the cursor green while editing.
+    fn draw_memory(&self, x: i32, y: i32) {
+        let space = self.editor.space();
+        let size = space.size(&self.nes);
+        let s_title = format!(
+            "{} ${}",
+            space.name().to_uppercase(),
+            DemoOlc6502::hex(self.editor.cursor() as u32, 5)
+        );
+        olc::draw_string(x, y, &s_title, olc::WHITE).expect("");
+        for row in 0..self.editor.rows() {
+            let addr = self.editor.top() + row * memory_editor::COLUMNS;
+            if addr >= size {
+                break;
+            }
+            let n_row_y = y + 10 + row as i32 * 10;
+            let s_offset = format!("${}:", DemoOlc6502::hex(addr as u32, 5));
+            olc::draw_string(x, n_row_y, &s_offset, olc::WHITE).expect("");
+            for col in 0..memory_editor::COLUMNS.min(size - addr) {
+                let a = addr + col;
+                let mut s_byte = DemoOlc6502::hex(space.read(&self.nes, a) as u32, 2);
+                let color = if self.editing && a == self.editor.cursor() {
+                    if let Some(digit) = self.editor.typed() {
+                        s_byte = format!("{}_", DemoOlc6502::hex(digit as u32, 1));
+                    }
+                    olc::GREEN
+                } else if self.debugger.is_frozen(space, a) {
+                    olc::RED
+                } else if self.editor.changed(&self.nes, a) {
+                    olc::YELLOW
+                } else {
+                    olc::WHITE
+                };
+                olc::draw_string(x + 64 + col as i32 * 24, n_row_y, &s_byte, color).expect("");
+            }
+        }
+    }
+
+    // Keys for the memory editor while it has them
+    fn edit_memory(&mut self) {
+        let page = (self.editor.rows() * memory_editor::COLUMNS) as isize;
+        let moves = [
+            (olc::Key::LEFT, -1),
+            (olc::Key::RIGHT, 1),
+            (olc::Key::UP, -(memory_editor::COLUMNS as isize)),
+            (olc::Key::DOWN, memory_editor::COLUMNS as isize),
+            (olc::Key::PGUP, -page),
+            (olc::Key::PGDN, page),
+        ];
+        for (key, delta) in moves {
+            if olc::get_key(key).pressed {
+                self.editor.move_cursor(&self.nes, delta);
+            }
+        }
+
+        let digits = [
+            olc::Key::K0,
+            olc::Key::K1,
+            olc::Key::K2,
+            olc::Key::K3,
+            olc::Key::K4,
+            olc::Key::K5,
+            olc::Key::K6,
+            olc::Key::K7,
+            olc::Key::K8,
+            olc::Key::K9,
+            olc::Key::A,
+            olc::Key::B,
+            olc::Key::C,
+            olc::Key::D,
+            olc::Key::E,
+            olc::Key::F,
+        ];
+        for (digit, key) in digits.into_iter().enumerate() {
+            if olc::get_key(key).pressed {
+                if let Err(e) = self.editor.type_digit(&mut self.nes, digit as u8) {
+                    self.status = e;
+                }
+            }
+        }
+
+        if olc::get_key(olc::Key::M).pressed {
+            let space = self.editor.space().next();
+            self.editor.set_space(space);
+        }
+
+        let (space, cursor) = (self.editor.space(), self.editor.cursor());
+        if olc::get_key(olc::Key::INS).pressed && !self.debugger.unfreeze(space, cursor) {
+            let value = space.read(&self.nes, cursor);
+            self.debugger.freeze(space, cursor, value);
+        }
+
+        // Finds the next place holding the byte under the cursor
+        if olc::get_key(olc::Key::END).pressed {
+            let pattern = [Some(space.read(&self.nes, cursor))];
+            if self.editor.find_next(&self.nes, &pattern).is_none() {
+                self.status = "Not found".to_string();
+            }
+        }
+    }
+
+    // What is left of the cheat search, three columns of candidates with
+    // what they held at the last filter and what they hold now
+    fn draw_search(&self, x: i32, y: i32) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let s_title = format!("CHEAT SEARCH: {} candidates", search.len());
+        olc::draw_string(x, y, &s_title, olc::WHITE).expect("");
+        let candidates = search.candidates(&self.nes);
+        for (n, (addr, previous, now)) in candidates.iter().take(96).enumerate() {
+            let s = format!(
+                "${}: ${} ${}",
+                DemoOlc6502::hex(*addr as u32, 4),
+                DemoOlc6502::hex(*previous as u32, 2),
+                DemoOlc6502::hex(*now as u32, 2)
+            );
+            let color = if previous != now {
+                olc::YELLOW
+            } else {
+                olc::WHITE
+            };
+            let (col, row) = (n as i32 / 32, n as i32 % 32);
+            olc::draw_string(x + col * 144, y + 10 + row * 10, &s, color).expect("");
+        }
+        if candidates.len() > 96 {
+            let s_more = format!("... {} more", candidates.len() - 96);
+            olc::draw_string(x, y + 330, &s_more, olc::WHITE).expect("");
+        }
+    }
+
+    // Keys for the cheat search while its panel is shown: F1 starts again,
+    // F2-F7 filter and F8 holds what is left at what it holds now
+    fn cheat_search(&mut self) {
+        let filters = [
+            (olc::Key::F2, Filter::Unchanged),
+            (olc::Key::F3, Filter::Changed),
+            (olc::Key::F4, Filter::Greater(Operand::Previous)),
+            (olc::Key::F5, Filter::Less(Operand::Previous)),
+            (olc::Key::F6, Filter::ChangedBy(1)),
+            (olc::Key::F7, Filter::ChangedBy(-1)),
+        ];
+        let nes = &self.nes;
+        let search = self.search.get_or_insert_with(|| CheatSearch::new(nes));
+        if olc::get_key(olc::Key::F1).pressed {
+            search.reset(nes);
+        }
+        for (key, filter) in filters {
+            if olc::get_key(key).pressed {
+                self.status = format!("{} candidates", search.filter(nes, filter));
+            }
+        }
+
+        if olc::get_key(olc::Key::F8).pressed {
+            let cheats = search.to_cheats(nes, None, "search");
+            self.status = format!("Added {} cheats", cheats.len());
+            for cheat in cheats {
+                self.debugger.cheats_mut().add(cheat);
             }
-            olc::draw_string(n_raw_x, n_raw_y, &s_offset, color).expect("fail to draw");
-            n_raw_y += 10
         }
     }
 
@@ -95,122 +324,334 @@ impl DemoOlc6502 {
     }
 
     fn draw_code(&mut self, x: i32, y: i32, n_lines: i32) {
-        let pc = self.nes.pc.clone();
+        let pc = self.nes.pc;
         let mut n_line_y = (n_lines >> 1) * 10 + y;
-        match self.map_asm.get(&pc) {
-            None => (),
-            Some(s) => {
-                draw_string(x, n_line_y, s, olc::CYAN).expect("");
+        if let Some(ins) = self.map_asm.get(&pc) {
+            draw_string(
+                x,
+                n_line_y,
+                &self.symbols.format_instruction(ins),
+                self.line_color(pc, olc::CYAN),
+            )
+            .expect("");
+        }
+
+        // The map is ordered by address, so the lines either side of pc are
+        // just a range walk away
+        for (addr, ins) in self.map_asm.range((Excluded(pc), Unbounded)) {
+            if n_line_y >= (n_lines * 10) + y {
+                break;
             }
+            n_line_y += 10;
+            draw_string(
+                x,
+                n_line_y,
+                &self.symbols.format_instruction(ins),
+                self.line_color(*addr, olc::WHITE),
+            )
+            .expect("");
         }
 
-        let mut offset_pc: u16 = 0;
-        while n_line_y < (n_lines * 10) + y {
-            offset_pc += 1;
-            match self.map_asm.get(&(pc + offset_pc)) {
-                None => (),
-                Some(s) => {
-                    n_line_y += 10;
-                    draw_string(x, n_line_y, s, olc::WHITE).expect("");
+        n_line_y = (n_lines >> 1) * 10 + y;
+        for (addr, ins) in self.map_asm.range(..pc).rev() {
+            if n_line_y <= y {
+                break;
+            }
+            n_line_y -= 10;
+            draw_string(
+                x,
+                n_line_y,
+                &self.symbols.format_instruction(ins),
+                self.line_color(*addr, olc::WHITE),
+            )
+            .expect("");
+        }
+    }
+
+    // Draws every step-th pixel of image, so 2 draws it at half size
+    fn draw_image(&self, x: i32, y: i32, image: &Image, step: usize) {
+        for iy in (0..image.height).step_by(step) {
+            for ix in (0..image.width).step_by(step) {
+                let [r, g, b] = image.pixel(ix, iy);
+                olc::draw(
+                    x + (ix / step) as i32,
+                    y + (iy / step) as i32,
+                    Pixel::rgb(r, g, b),
+                );
+            }
+        }
+    }
+
+    // Nametables at half size, both pattern tables, palette RAM and the
+    // sprites, with the first few of them listed
+    fn draw_ppu(&self, x: i32, y: i32) {
+        let memory = PpuMemory::from_bus(&self.nes.bus);
+        let colors = Palette::new();
+        self.draw_image(x, y, &ppu_viewer::nametables(&memory, &colors), 2);
+        for table in 0..2 {
+            let image = ppu_viewer::pattern_table(&memory, table, self.pattern_palette, &colors);
+            self.draw_image(x + 260, y + table as i32 * 132, &image, 1);
+        }
+        self.draw_image(
+            x + 260,
+            y + 264,
+            &ppu_viewer::palette_ram(&memory, &colors),
+            1,
+        );
+        olc::draw_string(
+            x + 260,
+            y + 284,
+            &format!("Palette {}", self.pattern_palette),
+            olc::WHITE,
+        )
+        .expect("");
+
+        self.draw_image(x, y + 246, &ppu_viewer::oam_view(&memory, &colors), 1);
+        for (n, sprite) in memory.sprites().iter().take(9).enumerate() {
+            let s = format!(
+                "{}: X{} Y{} T{} A{}",
+                DemoOlc6502::hex(n as u32, 2),
+                DemoOlc6502::hex(sprite.x as u32, 2),
+                DemoOlc6502::hex(sprite.y as u32, 2),
+                DemoOlc6502::hex(sprite.tile as u32, 2),
+                DemoOlc6502::hex(sprite.attributes as u32, 2)
+            );
+            olc::draw_string(x + 134, y + 246 + n as i32 * 10, &s, olc::WHITE).expect("");
+        }
+    }
+
+    // Breakpoints are drawn red and the cursor line yellow, the rest keep
+    // the colour they would normally have
+    fn line_color(&self, addr: u16, color: Pixel) -> Pixel {
+        if self.debugger.has_breakpoint(addr) {
+            olc::RED
+        } else if addr == self.cursor {
+            olc::YELLOW
+        } else {
+            color
+        }
+    }
+
+    // Address of the disassembled line after (or before) addr
+    fn next_line(&self, addr: u16, forward: bool) -> u16 {
+        let next = if forward {
+            self.map_asm.range((Excluded(addr), Unbounded)).next()
+        } else {
+            self.map_asm.range(..addr).next_back()
+        };
+        next.map_or(addr, |(acc, _)| *acc)
+    }
+
+    // Starts or stops recording capture.y4m and capture.wav
+    fn toggle_recording(&mut self) {
+        self.status = match self.debugger.set_video(None) {
+            Some(video) => match video.finish(&mut self.nes) {
+                Ok(frames) => format!("Recorded {} frames", frames),
+                Err(e) => e.to_string(),
+            },
+            None => {
+                let video = VideoRecorder::create(
+                    Path::new("capture.y4m"),
+                    Palette::new(),
+                    Overscan::NONE,
+                    Some((Path::new("capture.wav"), audio::DEFAULT_RATE)),
+                );
+                match video {
+                    Ok(video) => {
+                        self.debugger.set_video(Some(video));
+                        "Recording capture.y4m".to_string()
+                    }
+                    Err(e) => e.to_string(),
                 }
             }
+        };
+    }
+
+    // Saves the picture as screenshot.png, through the filters chosen
+    fn screenshot(&mut self) {
+        let screen = &self.nes.bus.screen;
+        let picture = screen.screenshot(&Palette::new(), Overscan::NONE);
+        let image = self.filters.apply(&picture);
+        self.status = match image.save(Path::new("screenshot.png")) {
+            Ok(()) => format!("Saved screenshot.png ({})", self.filters.describe()),
+            Err(e) => e,
+        };
+    }
+
+    fn next_filter(&mut self) {
+        self.filter = (self.filter + 1) % FILTERS.len();
+        self.filters = FilterChain::parse(FILTERS[self.filter]).expect("");
+        self.status = format!("Filter {}", self.filters.describe());
+    }
+
+    // Plugs the game in and powers on, with its battery save when it has one
+    fn load_rom(&mut self, path: &Path) -> Result<(), String> {
+        let cartridge = Cartridge::load_file(path)?;
+        let mut battery = BatterySave::for_cartridge(path, &cartridge);
+        cartridge.insert(&mut self.nes.bus);
+        self.nes.power_on();
+        if let Some(battery) = battery.as_mut() {
+            if battery.load(&mut self.nes.bus)? {
+                self.status = format!("Loaded {}", battery.path().display());
+            }
         }
+        self.debugger.set_battery(battery);
+        Ok(())
+    }
 
-        offset_pc = 0;
-        n_line_y = (n_lines >> 1) * 10 + y;
-        while n_line_y > y {
-            offset_pc += 1;
-            let acc = pc.overflowing_sub(offset_pc).0;
-            if let Some(s) = self.map_asm.get(&(acc)) {
-                n_line_y -= 10;
-                draw_string(x, n_line_y, s, olc::WHITE).expect("");
-            }
-        }
-
-        // // 448 72 26
-        // let it_a = self.map_asm.get(&self.nes.pc).expect("");
-        // let mut n_line_y = (n_lines >> 1) * 10 + y;
-        // if it_a != self.map_asm.end() {
-        //     olc::draw_string(x, n_lines, it_a.second, olc::CYAN).expect("");
-        //     while n_line_y < (n_lines * 10) + 10 {
-        //         n_line_y += 10;
-        //         it_a += 1;
-        //         if it_a != self.map_asm.end() {
-        //             olc::draw_string(x, n_line_y, it_a.second);
-        //         }
-        //
-        //     }
-        // }
-        //
-        // it_a = self.map_asm.find(nes.pc);
-        // n_line_y = (n_lines >> 1) * 10 + y;
-        // if (it_a != map_asm.end()){
-        //     while n_line_y > y {
-        //         n_line_y -= 10;
-        //         it_a -= 1;
-        //         if it_a != self.map_asm.end() {
-        //             draw_string(x, n_line_y, it_a.second, olc::WHITE).expect("");
-        //         }
-        //
-        //     }
-        // }
+    fn describe(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Step => String::new(),
+            StopReason::Breakpoint(id) => format!("Breakpoint #{} hit", id),
+            StopReason::Watchpoint { id, addr, kind } => format!(
+                "Watchpoint #{}: {:?} ${}",
+                id,
+                kind,
+                DemoOlc6502::hex(addr as u32, 4)
+            ),
+            StopReason::Reached(addr) => format!("Reached ${}", DemoOlc6502::hex(addr as u32, 4)),
+            StopReason::Returned => "Returned from routine".to_string(),
+            StopReason::Nmi => "Entered NMI handler".to_string(),
+            StopReason::Frame => format!("Frame {}", self.nes.clock_count / CPU_CYCLES_PER_FRAME),
+            StopReason::Stack(problem) => problem.describe(&self.symbols),
+        }
     }
 }
 
 impl olc::Application for DemoOlc6502 {
     fn on_user_create(&mut self) -> Result<(), Error> {
-        // Load Program (assembled at https://www.masswerk.at/6502/assembler.html)
-        /*
-            *=$8000
-            LDX #10
-            STX $0000
-            LDX #3
-            STX $0001
-            LDY $0000
-            LDA #0
-            CLC
-            loop
-            ADC $0001
-            DEY
-            BNE loop
-            STA $0002
-            NOP
-            NOP
-            NOP
-        */
-
-        // Convert hex string into bytes for RAM
-        let ss: String =
-            "A2 0A 8E 00 00 A2 03 8E 01 00 AC 00 00 A9 00 18 6D 01 00 88 D0 FA 8D 02 00 EA EA EA"
-                .to_string();
-        let mut n_offset: u16 = 0x8000;
-        let vc = ss.split(" ");
-        for c in vc {
-            self.nes.bus.ram[n_offset as usize] = u8::from_str_radix(c, 16).expect("");
-            n_offset += 1;
-        }
-        self.nes.bus.ram[0xFFFC] = 0x00;
-        self.nes.bus.ram[0xFFFD] = 0x80;
+        // Load Program
+        match self.rom.clone() {
+            Some(path) => {
+                if let Err(e) = self.load_rom(&path) {
+                    self.status = e;
+                }
+            }
+            None => {
+                let program = Assembler::new().assemble(DEMO_PROGRAM).expect("");
+                program.load(&mut self.nes.bus);
+                self.symbols.add_assembly(&program);
+                self.nes.reset();
+            }
+        }
 
         self.map_asm = self.nes.disassemble(0x0000, 0xFFFF);
-
-        self.nes.reset();
+        self.cursor = self.nes.pc;
+        self.debugger.set_rewind(Some(Rewind::new(
+            rewind::DEFAULT_FRAMES,
+            rewind::DEFAULT_KEYFRAME_INTERVAL,
+            rewind::DEFAULT_BUDGET,
+        )));
         return Result::Ok(());
     }
 
     fn on_user_update(&mut self, _f_elapsed_time: f32) -> Result<(), Error> {
         olc::clear(olc::DARK_BLUE);
 
+        if olc::get_key(olc::Key::TAB).pressed {
+            self.editing = !self.editing;
+        }
+
+        if self.editing {
+            self.edit_memory();
+        }
+
         if olc::get_key(olc::Key::SPACE).pressed {
-            let mut run: bool = true;
-            while run || !(self.nes.complete()) {
-                self.nes.clock();
-                run = false;
+            self.editor.begin_frame(&self.nes);
+            let reason = self.debugger.step(&mut self.nes);
+            self.status = self.describe(reason);
+        }
+
+        if olc::get_key(olc::Key::O).pressed {
+            self.debugger.step_over(&self.nes);
+        }
+
+        if olc::get_key(olc::Key::U).pressed {
+            self.debugger.step_out(&self.nes);
+        }
+
+        if olc::get_key(olc::Key::C).pressed && !self.editing {
+            if self.debugger.is_running() {
+                self.debugger.pause();
+                self.status = "Paused".to_string();
+            } else {
+                self.debugger.resume();
+            }
+        }
+
+        if olc::get_key(olc::Key::G).pressed {
+            self.debugger.run_to(self.cursor);
+        }
+
+        if olc::get_key(olc::Key::F).pressed && !self.editing {
+            self.debugger.run_frame(&self.nes);
+        }
+
+        if olc::get_key(olc::Key::V).pressed {
+            self.debugger.run_until_nmi();
+        }
+
+        if olc::get_key(olc::Key::B).pressed && !self.editing {
+            self.debugger.toggle_breakpoint(self.cursor);
+        }
+
+        // Held down, goes back a frame per update
+        if olc::get_key(olc::Key::BACK).held {
+            self.status = match self.debugger.step_back(&mut self.nes) {
+                Ok(true) => "Rewinding".to_string(),
+                Ok(false) => "Nothing left to rewind".to_string(),
+                Err(e) => e,
+            };
+        }
+
+        if olc::get_key(olc::Key::F9).pressed {
+            self.toggle_recording();
+        }
+
+        if olc::get_key(olc::Key::F12).pressed {
+            self.screenshot();
+        }
+
+        if olc::get_key(olc::Key::L).pressed && !self.editing {
+            self.next_filter();
+        }
+
+        if olc::get_key(olc::Key::P).pressed {
+            self.ppu_view = !self.ppu_view;
+        }
+
+        if olc::get_key(olc::Key::S).pressed && !self.editing {
+            self.searching = !self.searching;
+        }
+
+        if self.searching {
+            self.cheat_search();
+        }
+
+        if olc::get_key(olc::Key::K).pressed {
+            self.pattern_palette = (self.pattern_palette + 1) % 8;
+        }
+
+        if olc::get_key(olc::Key::UP).pressed && !self.editing {
+            self.cursor = self.next_line(self.cursor, false);
+        }
+
+        if olc::get_key(olc::Key::DOWN).pressed && !self.editing {
+            self.cursor = self.next_line(self.cursor, true);
+        }
+
+        // Give the debugger one emulated frame worth of cycles per update
+        if self.debugger.is_running() {
+            self.editor.begin_frame(&self.nes);
+            self.status = "Running".to_string();
+            if let Some(reason) = self.debugger.update(&mut self.nes, CPU_CYCLES_PER_FRAME) {
+                self.status = self.describe(reason);
             }
         }
 
         if olc::get_key(olc::Key::R).pressed {
             self.nes.reset();
+            self.debugger.clear_call_stack();
         }
 
         if olc::get_key(olc::Key::I).pressed {
@@ -220,11 +661,24 @@ impl olc::Application for DemoOlc6502 {
         if olc::get_key(olc::Key::N).pressed {
             self.nes.nmi();
         }
-        self.draw_ram(2, 2, 0x0000, 16, 16);
-        self.draw_ram(2, 182, 0x8000, 16, 16);
+        if self.ppu_view {
+            self.draw_ppu(2, 2);
+        } else if self.searching {
+            self.draw_search(2, 2);
+        } else {
+            self.draw_memory(2, 2);
+        }
         self.draw_cpu(448, 2);
         self.draw_code(448, 72, 26);
 
+        olc::draw_string(448, 340, &self.status, olc::YELLOW).expect("");
+
+        let calls = self.debugger.call_stack().summary(&self.symbols);
+        olc::draw_string(2, 345, &format!("Calls: {}", calls), olc::WHITE).expect("");
+        if let Some(problem) = self.debugger.call_stack().problems().last() {
+            olc::draw_string(2, 355, &problem.describe(&self.symbols), olc::RED).expect("");
+        }
+
         olc::draw_string(
             10,
             370,
@@ -232,15 +686,65 @@ impl olc::Application for DemoOlc6502 {
             olc::WHITE,
         )
         .expect("");
+        olc::draw_string(
+            10,
+            380,
+            "O = Step Over  U = Step Out  C = Run/Pause  F = Frame  V = To NMI  P = RAM/PPU  K = Palette",
+            olc::WHITE,
+        )
+        .expect("");
+        olc::draw_string(
+            10,
+            390,
+            "UP/DOWN = Cursor  B = Breakpoint  G = Run To Cursor  BACKSPACE = Rewind  F9 = Record",
+            olc::WHITE,
+        )
+        .expect("");
+        olc::draw_string(
+            10,
+            400,
+            "F12 = Screenshot (blank until there is a PPU)  L = Screenshot Filter",
+            olc::WHITE,
+        )
+        .expect("");
+        olc::draw_string(
+            10,
+            410,
+            "TAB = Edit Memory: Arrows/PGUP/PGDN, 0-F = Type, M = Space, INS = Freeze, END = Find",
+            olc::WHITE,
+        )
+        .expect("");
+        olc::draw_string(
+            10,
+            420,
+            "S = Cheat Search: F1 New  F2/F3 Same/Changed  F4/F5 Up/Down  F6/F7 +1/-1  F8 Hold",
+            olc::WHITE,
+        )
+        .expect("");
         return Result::Ok(());
     }
 
     fn on_user_destroy(&mut self) -> Result<(), Error> {
+        if self.debugger.video().is_some() {
+            self.toggle_recording();
+        }
+        if let Some(battery) = self.debugger.battery_mut() {
+            if let Some(e) = battery.error() {
+                println!("error: {}", e);
+            }
+            match battery.flush(&self.nes.bus) {
+                Ok(true) => println!("saved {}", battery.path().display()),
+                Ok(false) => {}
+                Err(e) => println!("error: {}", e),
+            }
+        }
         Result::Ok(())
     }
 }
 
 pub fn main() -> Result<(), Error> {
     let mut demo: DemoOlc6502 = DemoOlc6502::new();
+    // nes [game.nes] runs the game, or the demo program without one
+    demo.rom = std::env::args().nth(1).map(PathBuf::from);
     olc::start("olc6502 Demonstration", &mut (demo), 680, 480, 2, 2)
 }
@@ -0,0 +1,159 @@
+use std::path::Path;
+
+// Turns the PPU's colour numbers into RGB. A pixel is the 6 bit colour from
+// palette RAM, with the three emphasis bits of $2001 above it in bits 6-8.
+// Palettes with 64 entries ignore emphasis, ones with 512 have an entry
+// for every combination.
+//
+// .pal files are the same thing on disk, 64 or 512 RGB triples, as FCEUX,
+// Mesen and Nestopia read and write them.
+
+// The 2C02 colours of the olc NES tutorial this emulator grew out of
+const DEFAULT_2C02: [[u8; 3]; 64] = [
+    [84, 84, 84],
+    [0, 30, 116],
+    [8, 16, 144],
+    [48, 0, 136],
+    [68, 0, 100],
+    [92, 0, 48],
+    [84, 4, 0],
+    [60, 24, 0],
+    [32, 42, 0],
+    [8, 58, 0],
+    [0, 64, 0],
+    [0, 60, 0],
+    [0, 50, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [152, 150, 152],
+    [8, 76, 196],
+    [48, 50, 236],
+    [92, 30, 228],
+    [136, 20, 176],
+    [160, 20, 100],
+    [152, 34, 32],
+    [120, 60, 0],
+    [84, 90, 0],
+    [40, 114, 0],
+    [8, 124, 0],
+    [0, 118, 40],
+    [0, 102, 120],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [76, 154, 236],
+    [120, 124, 236],
+    [176, 98, 236],
+    [228, 84, 236],
+    [236, 88, 180],
+    [236, 106, 100],
+    [212, 136, 32],
+    [160, 170, 0],
+    [116, 196, 0],
+    [76, 208, 32],
+    [56, 204, 108],
+    [56, 180, 204],
+    [60, 60, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [168, 204, 236],
+    [188, 188, 236],
+    [212, 178, 236],
+    [236, 174, 236],
+    [236, 174, 212],
+    [236, 180, 176],
+    [228, 196, 144],
+    [204, 210, 120],
+    [180, 222, 120],
+    [168, 226, 144],
+    [152, 226, 180],
+    [160, 214, 228],
+    [160, 162, 160],
+    [0, 0, 0],
+    [0, 0, 0],
+];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new()
+    }
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        Self {
+            colors: DEFAULT_2C02.to_vec(),
+        }
+    }
+
+    pub fn from_pal(data: &[u8]) -> Result<Self, String> {
+        if data.len() != 64 * 3 && data.len() != 512 * 3 {
+            return Err(format!(
+                "a palette is 192 or 1536 bytes, not {}",
+                data.len()
+            ));
+        }
+        Ok(Self {
+            colors: data.chunks(3).map(|c| [c[0], c[1], c[2]]).collect(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Palette::from_pal(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn to_pal(&self) -> Vec<u8> {
+        self.colors.iter().flatten().copied().collect()
+    }
+
+    pub fn has_emphasis(&self) -> bool {
+        self.colors.len() == 512
+    }
+
+    pub fn rgb(&self, pixel: u16) -> [u8; 3] {
+        if self.has_emphasis() {
+            self.colors[pixel as usize & 0x1FF]
+        } else {
+            self.colors[pixel as usize & 0x3F]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pal_files_round_trip() {
+        let small: Vec<u8> = (0..64 * 3).map(|i| i as u8).collect();
+        let palette = Palette::from_pal(&small).unwrap();
+        assert!(!palette.has_emphasis());
+        assert_eq!(palette.to_pal(), small);
+        // Emphasis bits are ignored without the colours for them
+        assert_eq!(palette.rgb(0x1C1), [3, 4, 5]);
+
+        let large: Vec<u8> = (0..512 * 3).map(|i| (i * 7) as u8).collect();
+        let palette = Palette::from_pal(&large).unwrap();
+        assert!(palette.has_emphasis());
+        assert_eq!(palette.to_pal(), large);
+        let i = 0x1C1 * 3;
+        assert_eq!(palette.rgb(0x1C1), [large[i], large[i + 1], large[i + 2]]);
+
+        let path = std::env::temp_dir().join(format!("nes-{}.pal", std::process::id()));
+        std::fs::write(&path, palette.to_pal()).unwrap();
+        assert_eq!(Palette::load(&path).unwrap(), palette);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(Palette::new().to_pal().len(), 64 * 3);
+        assert!(Palette::from_pal(&large[..100]).is_err());
+    }
+}
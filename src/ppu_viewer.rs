@@ -0,0 +1,412 @@
+use crate::bus::Bus;
+use crate::image::Image;
+use crate::palette::Palette;
+
+// Pictures of what is in the PPU's memory, for the debugger panels and for
+// tools that want them as files. Each view is a function from a PpuMemory
+// to an Image:
+//
+//     nametables     all four screens as 512x480, scroll rectangle drawn on
+//     pattern_table  one of the two 4K tile sets as 128x128 in a palette
+//     palette_ram    the 32 palette entries as 8x8 swatches, 128x16
+//     oam_view       the 64 sprites as 8x16 cells, 16 to a row, 128x64
+//
+// There is no PPU in the emulator yet, so PpuMemory::from_bus only has the
+// pattern tables (the cartridge's CHR ROM) to show. The rest stays blank
+// until something keeps the nametables, palette and OAM up to date.
+
+// PPUCTRL bits the views care about
+pub struct PpuCtrl;
+impl PpuCtrl {
+    pub const SPRITE_TABLE: u8 = 1 << 3;
+    pub const BACKGROUND_TABLE: u8 = 1 << 4;
+    pub const TALL_SPRITES: u8 = 1 << 5;
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct PpuMemory {
+    // $0000-$1FFF, both pattern tables
+    pub chr: Vec<u8>,
+    // $2000-$2FFF, the four nametables after mirroring
+    pub vram: Vec<u8>,
+    // $3F00-$3F1F
+    pub palette: [u8; 32],
+    // Four bytes a sprite: Y, tile, attributes, X
+    pub oam: [u8; 256],
+    pub ctrl: u8,
+    // Top left of the screen in the 512x480 nametable space
+    pub scroll_x: u16,
+    pub scroll_y: u16,
+}
+
+impl Default for PpuMemory {
+    fn default() -> Self {
+        PpuMemory::new()
+    }
+}
+
+impl PpuMemory {
+    pub fn new() -> Self {
+        Self {
+            chr: vec![0; 0x2000],
+            vram: vec![0; 0x1000],
+            palette: [0; 32],
+            oam: [0; 256],
+            ctrl: 0,
+            scroll_x: 0,
+            scroll_y: 0,
+        }
+    }
+
+    // What can be seen of the PPU from the bus, which for now is the first
+    // 8K of CHR ROM when a cartridge is plugged in
+    pub fn from_bus(bus: &Bus) -> Self {
+        let mut memory = PpuMemory::new();
+        if let Some(cartridge) = &bus.cartridge {
+            let len = cartridge.chr.len().min(0x2000);
+            memory.chr[..len].copy_from_slice(&cartridge.chr[..len]);
+        }
+        memory
+    }
+
+    // Colour number of a palette entry. $3F10, $3F14, $3F18 and $3F1C are
+    // mirrors of the background entries below them, and colour 0 of every
+    // palette shows the shared backdrop.
+    pub fn color(&self, palette: usize, value: u8) -> u16 {
+        let entry = if value == 0 {
+            0
+        } else {
+            (palette & 7) * 4 + value as usize
+        };
+        self.palette[entry & 0x1F] as u16 & 0x3F
+    }
+
+    // The 2 bit values of a tile's 64 pixels, row by row
+    pub fn tile(&self, table: usize, tile: usize) -> [u8; 64] {
+        let base = (table & 1) * 0x1000 + (tile & 0xFF) * 16;
+        let mut pixels = [0; 64];
+        for row in 0..8 {
+            let low = self.chr[base + row];
+            let high = self.chr[base + row + 8];
+            for col in 0..8 {
+                let bit = 7 - col;
+                pixels[row * 8 + col] = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+            }
+        }
+        pixels
+    }
+
+    pub fn sprites(&self) -> Vec<Sprite> {
+        self.oam
+            .chunks(4)
+            .map(|s| Sprite {
+                y: s[0],
+                tile: s[1],
+                attributes: s[2],
+                x: s[3],
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sprite {
+    pub y: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    pub x: u8,
+}
+
+impl Sprite {
+    pub const FLIP_H: u8 = 1 << 6;
+    pub const FLIP_V: u8 = 1 << 7;
+    pub const BEHIND: u8 = 1 << 5;
+
+    // Sprites use palettes 4-7
+    pub fn palette(&self) -> usize {
+        4 + (self.attributes & 3) as usize
+    }
+}
+
+// Draws a tile at x, y. Pixels of value 0 are drawn too when opaque is set,
+// in the backdrop colour, otherwise they are left alone.
+fn draw_tile(
+    image: &mut Image,
+    memory: &PpuMemory,
+    colors: &Palette,
+    (table, tile, palette): (usize, usize, usize),
+    (x, y): (usize, usize),
+    flip: (bool, bool),
+    opaque: bool,
+) {
+    let pixels = memory.tile(table, tile);
+    for row in 0..8 {
+        for col in 0..8 {
+            let src_row = if flip.1 { 7 - row } else { row };
+            let src_col = if flip.0 { 7 - col } else { col };
+            let value = pixels[src_row * 8 + src_col];
+            if value == 0 && !opaque {
+                continue;
+            }
+            let (px, py) = (x + col, y + row);
+            if px < image.width && py < image.height {
+                image.set_pixel(px, py, colors.rgb(memory.color(palette, value)));
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// VIEWS
+
+// The 256 tiles of a pattern table in 16 rows, in one of the eight
+// palettes
+pub fn pattern_table(memory: &PpuMemory, table: usize, palette: usize, colors: &Palette) -> Image {
+    let mut image = Image::new(128, 128);
+    for tile in 0..256 {
+        let (x, y) = ((tile % 16) * 8, (tile / 16) * 8);
+        draw_tile(
+            &mut image,
+            memory,
+            colors,
+            (table, tile, palette),
+            (x, y),
+            (false, false),
+            true,
+        );
+    }
+    image
+}
+
+// Nametables 0 and 1 side by side above 2 and 3, with the screen's 256x240
+// at the scroll position outlined (wrapping round the edges like the PPU)
+pub fn nametables(memory: &PpuMemory, colors: &Palette) -> Image {
+    let mut image = Image::new(512, 480);
+    let table = (memory.ctrl & PpuCtrl::BACKGROUND_TABLE != 0) as usize;
+    for n in 0..4 {
+        let base = n * 0x400;
+        let (left, top) = ((n % 2) * 256, (n / 2) * 240);
+        for row in 0..30 {
+            for col in 0..32 {
+                let tile = memory.vram[base + row * 32 + col] as usize;
+                // Each attribute byte covers 4x4 tiles, two bits per 2x2
+                let attribute = memory.vram[base + 0x3C0 + (row / 4) * 8 + col / 4];
+                let shift = ((row / 2) % 2) * 4 + ((col / 2) % 2) * 2;
+                let palette = ((attribute >> shift) & 3) as usize;
+                draw_tile(
+                    &mut image,
+                    memory,
+                    colors,
+                    (table, tile, palette),
+                    (left + col * 8, top + row * 8),
+                    (false, false),
+                    true,
+                );
+            }
+        }
+    }
+
+    let outline = [255, 0, 255];
+    let (sx, sy) = (memory.scroll_x as usize, memory.scroll_y as usize);
+    for i in 0..256 {
+        let x = (sx + i) % 512;
+        image.set_pixel(x, sy % 480, outline);
+        image.set_pixel(x, (sy + 239) % 480, outline);
+    }
+    for i in 0..240 {
+        let y = (sy + i) % 480;
+        image.set_pixel(sx % 512, y, outline);
+        image.set_pixel((sx + 255) % 512, y, outline);
+    }
+    image
+}
+
+// Background palettes on the top row, sprite palettes on the bottom
+pub fn palette_ram(memory: &PpuMemory, colors: &Palette) -> Image {
+    let mut image = Image::new(128, 16);
+    for (entry, value) in memory.palette.iter().enumerate() {
+        let rgb = colors.rgb(*value as u16 & 0x3F);
+        let (left, top) = ((entry % 16) * 8, (entry / 16) * 8);
+        for y in top..top + 8 {
+            for x in left..left + 8 {
+                image.set_pixel(x, y, rgb);
+            }
+        }
+    }
+    image
+}
+
+// Sprite n on its own, 8x8 or 8x16 as PPUCTRL says, flipped as it shows on
+// screen. Pixels of value 0 are transparent and come out as the backdrop.
+pub fn sprite_image(memory: &PpuMemory, n: usize, colors: &Palette) -> Image {
+    let sprite = memory.sprites()[n & 63];
+    let tall = memory.ctrl & PpuCtrl::TALL_SPRITES != 0;
+    let mut image = Image::new(8, if tall { 16 } else { 8 });
+    let backdrop = colors.rgb(memory.color(0, 0));
+    for y in 0..image.height {
+        for x in 0..8 {
+            image.set_pixel(x, y, backdrop);
+        }
+    }
+    let flip = (
+        sprite.attributes & Sprite::FLIP_H != 0,
+        sprite.attributes & Sprite::FLIP_V != 0,
+    );
+    let palette = sprite.palette();
+    if tall {
+        // The tile number's low bit picks the table, and a vertical flip
+        // swaps the two halves as well as flipping each
+        let table = (sprite.tile & 1) as usize;
+        let top = (sprite.tile & 0xFE) as usize;
+        let (first, second) = if flip.1 {
+            (top + 1, top)
+        } else {
+            (top, top + 1)
+        };
+        let spec = |tile| (table, tile, palette);
+        draw_tile(&mut image, memory, colors, spec(first), (0, 0), flip, false);
+        draw_tile(
+            &mut image,
+            memory,
+            colors,
+            spec(second),
+            (0, 8),
+            flip,
+            false,
+        );
+    } else {
+        let table = (memory.ctrl & PpuCtrl::SPRITE_TABLE != 0) as usize;
+        let spec = (table, sprite.tile as usize, palette);
+        draw_tile(&mut image, memory, colors, spec, (0, 0), flip, false);
+    }
+    image
+}
+
+// All 64 sprites in OAM order, 16 to a row, each in an 8x16 cell
+pub fn oam_view(memory: &PpuMemory, colors: &Palette) -> Image {
+    let mut image = Image::new(128, 64);
+    for n in 0..64 {
+        let sprite = sprite_image(memory, n, colors);
+        let (left, top) = ((n % 16) * 8, (n / 16) * 16);
+        for y in 0..sprite.height {
+            for x in 0..sprite.width {
+                image.set_pixel(left + x, top + y, sprite.pixel(x, y));
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Colour n comes out as [n, n, n], so a pixel says which entry drew it
+    fn colors() -> Palette {
+        let data: Vec<u8> = (0..64).flat_map(|n| [n, n, n]).collect();
+        Palette::from_pal(&data).unwrap()
+    }
+
+    fn shade(pixel: [u8; 3]) -> u8 {
+        assert_eq!(pixel[0], pixel[1]);
+        assert_eq!(pixel[1], pixel[2]);
+        pixel[0]
+    }
+
+    // Row `row` of a tile with the given low and high plane bytes
+    fn set_row(memory: &mut PpuMemory, table: usize, tile: usize, row: usize, planes: (u8, u8)) {
+        let base = table * 0x1000 + tile * 16;
+        memory.chr[base + row] = planes.0;
+        memory.chr[base + row + 8] = planes.1;
+    }
+
+    fn memory() -> PpuMemory {
+        let mut memory = PpuMemory::new();
+        for (entry, value) in memory.palette.iter_mut().enumerate() {
+            *value = 0x20 + entry as u8;
+        }
+        memory
+    }
+
+    #[test]
+    fn decodes_the_two_planes_of_a_tile() {
+        let mut memory = memory();
+        // Left to right: both planes, high only, low only, neither
+        set_row(&mut memory, 1, 1, 2, (0b1010_0000, 0b1100_0000));
+        let pixels = memory.tile(1, 1);
+        assert_eq!(pixels[16..20], [3, 2, 1, 0]);
+        assert!(memory.tile(0, 1).iter().all(|&p| p == 0));
+
+        // Tile 1 sits in the second column of the first row, in palette 2
+        let image = pattern_table(&memory, 1, 2, &colors());
+        let row: Vec<u8> = (8..12).map(|x| shade(image.pixel(x, 2))).collect();
+        assert_eq!(row, [0x2B, 0x2A, 0x29, 0x20]);
+    }
+
+    #[test]
+    fn attribute_quadrants_pick_the_palette() {
+        let mut memory = memory();
+        set_row(&mut memory, 0, 1, 0, (0xFF, 0x00));
+        // Nametable 1, top left attribute byte: one palette a 2x2 quadrant,
+        // top left in the low bits
+        let base = 0x400;
+        memory.vram[base + 0x3C0] = 0b11_10_01_00;
+        for (row, col) in [(0, 0), (0, 2), (2, 0), (2, 2)] {
+            memory.vram[base + row * 32 + col] = 1;
+        }
+
+        let image = nametables(&memory, &colors());
+        let shades: Vec<u8> = [(0, 0), (0, 2), (2, 0), (2, 2)]
+            .iter()
+            .map(|&(row, col)| shade(image.pixel(256 + col * 8 + 3, row * 8)))
+            .collect();
+        assert_eq!(shades, [0x21, 0x25, 0x29, 0x2D]);
+    }
+
+    #[test]
+    fn value_zero_shows_the_backdrop() {
+        let mut memory = memory();
+        memory.vram[0x3C0] = 0xFF;
+        assert_eq!(memory.color(3, 0), 0x20);
+        assert_eq!(memory.color(4, 0), 0x20);
+        assert_eq!(memory.color(7, 3), 0x3F);
+
+        // Palette 3's colour 0 and the sprite palettes' $3F10 are not drawn
+        let image = nametables(&memory, &colors());
+        assert_eq!(shade(image.pixel(4, 4)), 0x20);
+        let sprite = sprite_image(&memory, 0, &colors());
+        assert_eq!(shade(sprite.pixel(4, 4)), 0x20);
+    }
+
+    #[test]
+    fn tall_sprites_swap_halves_when_flipped_vertically() {
+        let mut memory = memory();
+        memory.ctrl = PpuCtrl::TALL_SPRITES;
+        // An odd tile number means table 1, tiles 2 and 3
+        set_row(&mut memory, 1, 2, 0, (0xFF, 0x00));
+        set_row(&mut memory, 1, 3, 0, (0x00, 0xFF));
+        memory.oam[..4].copy_from_slice(&[0, 3, 1, 0]);
+
+        let column = |memory: &PpuMemory| -> Vec<u8> {
+            let image = sprite_image(memory, 0, &colors());
+            assert_eq!((image.width, image.height), (8, 16));
+            (0..16).map(|y| shade(image.pixel(0, y))).collect()
+        };
+        let upright = column(&memory);
+        assert_eq!(upright[0], 0x35);
+        assert_eq!(upright[8], 0x36);
+        assert!(upright
+            .iter()
+            .enumerate()
+            .all(|(y, &s)| y == 0 || y == 8 || s == 0x20));
+
+        memory.oam[2] |= Sprite::FLIP_V;
+        let flipped = column(&memory);
+        assert_eq!(flipped[7], 0x36);
+        assert_eq!(flipped[15], 0x35);
+        assert!(flipped
+            .iter()
+            .enumerate()
+            .all(|(y, &s)| y == 7 || y == 15 || s == 0x20));
+    }
+}
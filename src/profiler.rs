@@ -0,0 +1,206 @@
+use crate::call_stack::{CallFrame, CallStack};
+use crate::cpu_6502::Cpu6502;
+use crate::debugger::CPU_CYCLES_PER_FRAME;
+use crate::symbols::SymbolTable;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// Attributes cpu cycles to the routine that spent them. The routines come
+// from the debugger's shadow CallStack, so the profiler and the backtraces
+// always agree on what called what. The call path is kept here as well
+// and only brought up to date when the stack changes, not rebuilt for
+// every instruction.
+
+// A routine is known by its entry address, None for whatever was running
+// when profiling started
+pub type Routine = Option<u16>;
+
+#[derive(Clone, Default, Debug)]
+pub struct RoutineStats {
+    pub calls: u64,
+    // Cycles spent in the routine and everything it called
+    pub inclusive: u64,
+    // Cycles spent in the routine itself
+    pub exclusive: u64,
+    // Most inclusive cycles the routine used in a single frame
+    pub max_frame: u64,
+    frame: u64,
+    frame_cycles: u64,
+}
+
+impl RoutineStats {
+    fn add(&mut self, frame: u64, cycles: u64) {
+        if frame != self.frame {
+            self.max_frame = self.max_frame.max(self.frame_cycles);
+            self.frame = frame;
+            self.frame_cycles = 0;
+        }
+        self.frame_cycles += cycles;
+        self.inclusive += cycles;
+    }
+
+    fn peak(&self) -> u64 {
+        self.max_frame.max(self.frame_cycles)
+    }
+}
+
+pub struct Profiler {
+    // The call stack's frames as of the last log(), and the routines they
+    // are in with [top] in front
+    frames: Vec<CallFrame>,
+    path: Vec<Routine>,
+    stats: HashMap<Routine, RoutineStats>,
+    // Exclusive cycles of every call path, for flame graphs
+    paths: HashMap<Vec<Routine>, u64>,
+    // Clock at the start of the previous instruction
+    last_clock: Option<u64>,
+    total: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            path: vec![None],
+            stats: HashMap::new(),
+            paths: HashMap::new(),
+            last_clock: None,
+            total: 0,
+        }
+    }
+
+    // Called with the cpu on an instruction boundary, before the
+    // instruction at pc runs and after the call stack has seen it. The
+    // cycles since the last call are charged to the path as it was, then
+    // the path is brought up to date.
+    pub fn log(&mut self, cpu: &Cpu6502, call_stack: &CallStack) {
+        if let Some(last_clock) = self.last_clock {
+            let cycles = cpu.clock_count.saturating_sub(last_clock);
+            self.charge(last_clock / CPU_CYCLES_PER_FRAME, cycles);
+        }
+        self.last_clock = Some(cpu.clock_count);
+
+        let frames = call_stack.frames();
+        if frames == self.frames.as_slice() {
+            return;
+        }
+        let kept = self
+            .frames
+            .iter()
+            .zip(frames)
+            .take_while(|(old, new)| old == new)
+            .count();
+        self.frames.truncate(kept);
+        self.path.truncate(kept + 1);
+        for frame in &frames[kept..] {
+            self.stats.entry(Some(frame.target)).or_default().calls += 1;
+            self.frames.push(*frame);
+            self.path.push(Some(frame.target));
+        }
+    }
+
+    fn charge(&mut self, frame: u64, cycles: u64) {
+        self.total += cycles;
+
+        // A recursive routine is on the stack more than once but only
+        // spends the cycles once
+        for (i, routine) in self.path.iter().enumerate() {
+            if !self.path[..i].contains(routine) {
+                self.stats.entry(*routine).or_default().add(frame, cycles);
+            }
+        }
+        if let Some(routine) = self.path.last() {
+            self.stats.entry(*routine).or_default().exclusive += cycles;
+        }
+
+        match self.paths.get_mut(self.path.as_slice()) {
+            Some(total) => *total += cycles,
+            None => {
+                self.paths.insert(self.path.clone(), cycles);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> &HashMap<Routine, RoutineStats> {
+        &self.stats
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total
+    }
+
+    // Routines on the stack right now, outermost first
+    pub fn call_stack(&self) -> &[Routine] {
+        &self.path
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // REPORTS
+
+    fn name(symbols: &SymbolTable, routine: Routine) -> String {
+        match routine {
+            Some(addr) => symbols.name_or_hex(addr),
+            None => "[top]".to_string(),
+        }
+    }
+
+    // One line per routine, most expensive first. Max/frame is also shown
+    // as a share of a frame, anything near 100% is overrunning vblank.
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let mut routines: Vec<(&Routine, &RoutineStats)> = self.stats.iter().collect();
+        routines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<24} {:>8} {:>12} {:>7} {:>12} {:>7} {:>10} {:>7}",
+            "routine", "calls", "inclusive", "%", "exclusive", "%", "max/frame", "%frame"
+        );
+        let percent = |n: u64, of: u64| {
+            if of == 0 {
+                0.0
+            } else {
+                n as f64 * 100.0 / of as f64
+            }
+        };
+        for (routine, stats) in routines {
+            let _ = writeln!(
+                out,
+                "{:<24} {:>8} {:>12} {:>6.2}% {:>12} {:>6.2}% {:>10} {:>6.1}%",
+                Profiler::name(symbols, *routine),
+                stats.calls,
+                stats.inclusive,
+                percent(stats.inclusive, self.total),
+                stats.exclusive,
+                percent(stats.exclusive, self.total),
+                stats.peak(),
+                percent(stats.peak(), CPU_CYCLES_PER_FRAME)
+            );
+        }
+        out
+    }
+
+    // Collapsed stacks, "outer;inner;leaf cycles" per line, the input
+    // format of flamegraph.pl and inferno
+    pub fn collapsed(&self, symbols: &SymbolTable) -> String {
+        let mut lines: Vec<String> = self
+            .paths
+            .iter()
+            .map(|(path, cycles)| {
+                let names: Vec<String> = path.iter().map(|r| Profiler::name(symbols, *r)).collect();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+}
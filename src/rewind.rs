@@ -0,0 +1,318 @@
+use crate::cpu_6502::Cpu6502;
+use crate::debugger::CPU_CYCLES_PER_FRAME;
+use crate::save_state;
+use std::collections::VecDeque;
+
+// Keeps the last few seconds of save states so emulation can be stepped
+// backwards a frame at a time. States are grouped behind a keyframe: the
+// keyframe is stored whole, the frames after it only as the XOR of their
+// state with the keyframe, which is mostly zeros. Both are run length
+// encoded. Groups are dropped oldest first, a whole group at a time since
+// its deltas are useless without the keyframe, whenever the buffer holds
+// too many frames or goes over its memory budget.
+
+// Sixty seconds of frames, a keyframe every second
+pub const DEFAULT_FRAMES: usize = 60 * 60;
+pub const DEFAULT_KEYFRAME_INTERVAL: usize = 60;
+pub const DEFAULT_BUDGET: usize = 32 * 1024 * 1024;
+
+struct Group {
+    keyframe: Vec<u8>,
+    // Length of the uncompressed keyframe
+    size: usize,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn frames(&self) -> usize {
+        1 + self.deltas.len()
+    }
+
+    fn bytes(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(|d| d.len()).sum::<usize>()
+    }
+}
+
+pub struct Rewind {
+    groups: VecDeque<Group>,
+    max_frames: usize,
+    keyframe_interval: usize,
+    budget: usize,
+    frames: usize,
+    bytes: usize,
+    // Frame of the last state log() recorded
+    last_frame: Option<u64>,
+    // The newest group's keyframe uncompressed, so push() does not have to
+    // decompress it for every delta
+    keyframe: Option<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(max_frames: usize, keyframe_interval: usize, budget: usize) -> Self {
+        Self {
+            groups: VecDeque::new(),
+            max_frames: max_frames.max(1),
+            keyframe_interval: keyframe_interval.max(1),
+            budget,
+            frames: 0,
+            bytes: 0,
+            last_frame: None,
+            keyframe: None,
+        }
+    }
+
+    // Frames that can be stepped back
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    // Memory used by the compressed states
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.frames = 0;
+        self.bytes = 0;
+        self.last_frame = None;
+        self.keyframe = None;
+    }
+
+    // Called before every instruction, records a state at the first
+    // instruction of each frame
+    pub fn log(&mut self, cpu: &Cpu6502) {
+        let frame = cpu.clock_count / CPU_CYCLES_PER_FRAME;
+        if self.last_frame != Some(frame) {
+            self.push(cpu);
+            self.last_frame = Some(frame);
+        }
+    }
+
+    // Records the machine as it is now, normally once at the start of
+    // every frame
+    pub fn push(&mut self, cpu: &Cpu6502) {
+        let state = save_state::save(cpu);
+
+        let group = self
+            .groups
+            .back_mut()
+            .filter(|g| g.frames() < self.keyframe_interval);
+        let added = match group {
+            Some(group) => {
+                let keyframe = self.keyframe.get_or_insert_with(|| {
+                    decompress(&group.keyframe, group.size)
+                        .expect("rewind keyframe was written by compress")
+                });
+                let delta = compress(&xor(&state, keyframe));
+                let added = delta.len();
+                group.deltas.push(delta);
+                added
+            }
+            None => {
+                let keyframe = compress(&state);
+                let added = keyframe.len();
+                self.groups.push_back(Group {
+                    keyframe,
+                    size: state.len(),
+                    deltas: Vec::new(),
+                });
+                self.keyframe = Some(state);
+                added
+            }
+        };
+        self.frames += 1;
+        self.bytes += added;
+
+        // Never drop the group just written to
+        while self.groups.len() > 1 && (self.frames > self.max_frames || self.bytes > self.budget) {
+            if let Some(oldest) = self.groups.pop_front() {
+                self.frames -= oldest.frames();
+                self.bytes -= oldest.bytes();
+            }
+        }
+    }
+
+    // Puts the machine back to the newest state and forgets it, so calling
+    // this repeatedly walks backwards a frame at a time. False when there
+    // is nothing left to go back to.
+    pub fn step_back(&mut self, cpu: &mut Cpu6502) -> Result<bool, String> {
+        let group = match self.groups.back_mut() {
+            Some(group) => group,
+            None => return Ok(false),
+        };
+        if self.keyframe.is_none() {
+            self.keyframe = Some(decompress(&group.keyframe, group.size)?);
+        }
+        let state = match group.deltas.pop() {
+            Some(delta) => {
+                self.bytes -= delta.len();
+                let keyframe = self.keyframe.as_ref().expect("decompressed above");
+                xor(&decompress(&delta, group.size)?, keyframe)
+            }
+            None => {
+                self.bytes -= group.keyframe.len();
+                self.groups.pop_back();
+                self.keyframe.take().expect("decompressed above")
+            }
+        };
+        self.frames -= 1;
+        // Running on from here records this frame again
+        self.last_frame = None;
+
+        save_state::load(cpu, &state)?;
+        Ok(true)
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0))
+        .collect()
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// RUN LENGTH ENCODING
+
+// Runs of zeros and literal bytes taking turns, "zeros literals bytes..."
+// with both counts as LEB128 varints. Anything else repeating is rare in a
+// delta, so only zeros get runs.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = data[i..].iter().take_while(|b| **b == 0).count();
+        i += zeros;
+
+        // A literal run ends at the first pair of zeros, a single zero is
+        // cheaper to copy than to start a new run for
+        let start = i;
+        while i < data.len() && !(data[i] == 0 && data.get(i + 1).is_none_or(|b| *b == 0)) {
+            i += 1;
+        }
+
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, i - start);
+        out.extend_from_slice(&data[start..i]);
+    }
+    out
+}
+
+pub fn decompress(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(size);
+    let mut rest = data;
+    while !rest.is_empty() {
+        let zeros = read_varint(&mut rest)?;
+        let literals = read_varint(&mut rest)?;
+        if literals > rest.len() || out.len() + zeros + literals > size {
+            return Err("rewind state is corrupt".to_string());
+        }
+        out.resize(out.len() + zeros, 0);
+        out.extend_from_slice(&rest[..literals]);
+        rest = &rest[literals..];
+    }
+    out.resize(size, 0);
+    Ok(out)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<usize, String> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+        let (byte, rest) = data
+            .split_first()
+            .ok_or("rewind state is corrupt".to_string())?;
+        *data = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift > 56 {
+            return Err("rewind state is corrupt".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Debugger;
+
+    #[test]
+    fn compression_round_trips() {
+        let mut long = vec![0u8; 1000];
+        long.extend((0..600).map(|i| (i % 251) as u8 + 1));
+        long.extend([0, 7, 0, 0, 9]);
+        let inputs: [&[u8]; 6] = [
+            &[],
+            &[0; 300],
+            &[1, 2, 3],
+            &[0, 5, 0, 6, 0, 0],
+            &[9, 0],
+            &long,
+        ];
+        for data in inputs {
+            let packed = compress(data);
+            assert_eq!(decompress(&packed, data.len()).unwrap(), data);
+        }
+        assert!(compress(&[0; 300]).len() < 4);
+        assert!(decompress(&compress(&long), 10).is_err());
+        assert!(decompress(&[0x80], 10).is_err());
+    }
+
+    #[test]
+    fn rewinding_and_running_again_ends_up_the_same() {
+        // Adds to a table in RAM forever
+        let mut cpu = Cpu6502::new();
+        let program = [
+            0xA5, 0x10, // LDA $10
+            0x69, 0x07, // ADC #$07
+            0x85, 0x10, // STA $10
+            0xAA, // TAX
+            0x7D, 0x00, 0x02, // ADC $0200,X
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0x4C, 0x00, 0x80, // JMP $8000
+        ];
+        for (i, b) in program.iter().enumerate() {
+            cpu.bus.write(&(0x8000 + i as u16), b);
+        }
+        cpu.reset();
+        cpu.pc = 0x8000;
+
+        let mut debugger = Debugger::new();
+        debugger.set_rewind(Some(Rewind::new(100, 4, DEFAULT_BUDGET)));
+        debugger.resume();
+        debugger.update(&mut cpu, 20 * CPU_CYCLES_PER_FRAME);
+        let end = cpu.clock_count;
+        let expected = save_state::save(&cpu);
+
+        // Back over a couple of keyframes, then run the same frames again
+        for _ in 0..7 {
+            assert!(debugger.step_back(&mut cpu).unwrap());
+        }
+        assert!(cpu.clock_count < end - 6 * CPU_CYCLES_PER_FRAME);
+        debugger.resume();
+        let left = end - cpu.clock_count;
+        debugger.update(&mut cpu, left);
+        assert_eq!(cpu.clock_count, end);
+        assert!(save_state::save(&cpu) == expected);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::controller::Controller;
+use crate::cpu_6502::Cpu6502;
+use std::path::Path;
+
+// Snapshots of the whole machine. A state file is a header followed by
+// chunks, each a four letter tag, a little endian u32 length and the data:
+//
+//     "NESS" version:u16
+//     "CPU " registers and the internals of the instruction in flight
+//     "RAM " the 64K the bus holds
+//     "PAD " both controllers, optional
+//
+// Every part of the machine writes its own chunk, so the PPU, APU and
+// mapper can add theirs later without changing the ones already here.
+// Chunks a loader does not know are skipped, chunks it needs must be
+// present. VERSION goes up whenever an existing chunk changes layout.
+
+const MAGIC: &[u8; 4] = b"NESS";
+pub const VERSION: u16 = 1;
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        Self { data }
+    }
+
+    // Writes one chunk, fill gets a writer for the chunk data
+    pub fn chunk(&mut self, tag: &[u8; 4], fill: impl FnOnce(&mut ChunkWriter)) {
+        let mut chunk = ChunkWriter { data: Vec::new() };
+        fill(&mut chunk);
+        self.data.extend_from_slice(tag);
+        self.data
+            .extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(&chunk.data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct ChunkWriter {
+    data: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+}
+
+pub struct StateReader<'a> {
+    version: u16,
+    chunks: Vec<([u8; 4], &'a [u8])>,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, String> {
+        if data.len() < 6 || &data[..4] != MAGIC {
+            return Err("not a save state".to_string());
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version > VERSION {
+            return Err(format!(
+                "save state version {} is newer than {}",
+                version, VERSION
+            ));
+        }
+
+        let mut chunks = Vec::new();
+        let mut rest = &data[6..];
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                return Err("save state truncated".to_string());
+            }
+            let tag = [rest[0], rest[1], rest[2], rest[3]];
+            let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            if rest.len() < 8 + len {
+                return Err(format!(
+                    "chunk '{}' truncated",
+                    String::from_utf8_lossy(&tag)
+                ));
+            }
+            chunks.push((tag, &rest[8..8 + len]));
+            rest = &rest[8 + len..];
+        }
+        Ok(Self { version, chunks })
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn has_chunk(&self, tag: &[u8; 4]) -> bool {
+        self.chunks.iter().any(|(t, _)| t == tag)
+    }
+
+    pub fn chunk(&self, tag: &[u8; 4]) -> Result<ChunkReader<'a>, String> {
+        self.chunks
+            .iter()
+            .find(|(t, _)| t == tag)
+            .map(|(t, data)| ChunkReader { tag: *t, data })
+            .ok_or(format!(
+                "save state has no '{}' chunk",
+                String::from_utf8_lossy(tag)
+            ))
+    }
+}
+
+pub struct ChunkReader<'a> {
+    tag: [u8; 4],
+    data: &'a [u8],
+}
+
+impl ChunkReader<'_> {
+    pub fn bytes(&mut self, len: usize) -> Result<&[u8], String> {
+        if self.data.len() < len {
+            return Err(format!(
+                "chunk '{}' too short",
+                String::from_utf8_lossy(&self.tag)
+            ));
+        }
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(value)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let mut value = [0u8; 8];
+        value.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MACHINE
+
+pub fn save(cpu: &Cpu6502) -> Vec<u8> {
+    let mut out = StateWriter::new();
+    cpu.save_state(&mut out);
+    out.chunk(b"RAM ", |c| c.bytes(&cpu.bus.ram));
+    out.chunk(b"PAD ", |c| {
+        for pad in &cpu.bus.controllers {
+            pad.save_state(c);
+        }
+    });
+    out.finish()
+}
+
+// Nothing is changed unless the whole state reads back
+pub fn load(cpu: &mut Cpu6502, data: &[u8]) -> Result<(), String> {
+    let state = StateReader::new(data)?;
+    let mut ram = state.chunk(b"RAM ")?;
+    let ram = ram.bytes(cpu.bus.ram.len())?;
+    let mut pads = [Controller::new(), Controller::new()];
+    if state.has_chunk(b"PAD ") {
+        let mut c = state.chunk(b"PAD ")?;
+        for pad in pads.iter_mut() {
+            pad.load_state(&mut c)?;
+        }
+    }
+
+    cpu.load_state(&state)?;
+    cpu.bus.ram.copy_from_slice(ram);
+    cpu.bus.controllers = pads;
+    Ok(())
+}
+
+pub fn save_file(cpu: &Cpu6502, path: &Path) -> Result<(), String> {
+    std::fs::write(path, save(cpu)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn load_file(cpu: &mut Cpu6502, path: &Path) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    load(cpu, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keeps adding to a table in RAM, so the state never settles
+    fn machine() -> Cpu6502 {
+        let mut cpu = Cpu6502::new();
+        let program = [
+            0xA5, 0x10, // LDA $10
+            0x69, 0x07, // ADC #$07
+            0x85, 0x10, // STA $10
+            0xAA, // TAX
+            0x7D, 0x00, 0x02, // ADC $0200,X
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0x4C, 0x00, 0x80, // JMP $8000
+        ];
+        for (i, b) in program.iter().enumerate() {
+            cpu.bus.write(&(0x8000 + i as u16), b);
+        }
+        cpu.bus.write(&0xFFFC, &0x00);
+        cpu.bus.write(&0xFFFD, &0x80);
+        cpu.reset();
+        cpu
+    }
+
+    fn run(cpu: &mut Cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn loading_carries_on_cycle_for_cycle() {
+        let mut cpu = machine();
+        // Stop in the middle of an instruction
+        run(&mut cpu, 1001);
+        let state = save(&cpu);
+        run(&mut cpu, 5000);
+        let expected = save(&cpu);
+        let (a, x, y, pc, sp, sr) = (cpu.a, cpu.x, cpu.y, cpu.pc, cpu.sp, cpu.sr);
+        let clock_count = cpu.clock_count;
+        let ram = cpu.bus.ram;
+
+        load(&mut cpu, &state).unwrap();
+        run(&mut cpu, 5000);
+        assert_eq!(
+            (cpu.a, cpu.x, cpu.y, cpu.pc, cpu.sp, cpu.sr),
+            (a, x, y, pc, sp, sr)
+        );
+        assert_eq!(cpu.clock_count, clock_count);
+        assert!(cpu.bus.ram == ram);
+        assert_eq!(save(&cpu), expected);
+
+        // And on into the next instructions the same way
+        let mut other = machine();
+        load(&mut other, &expected).unwrap();
+        run(&mut cpu, 777);
+        run(&mut other, 777);
+        assert_eq!(save(&other), save(&cpu));
+    }
+
+    #[test]
+    fn a_bad_state_changes_nothing() {
+        let mut cpu = machine();
+        run(&mut cpu, 300);
+        let state = save(&cpu);
+        run(&mut cpu, 300);
+        let before = save(&cpu);
+
+        assert!(load(&mut cpu, &state[..state.len() - 10]).is_err());
+        assert_eq!(save(&cpu), before);
+
+        let mut out = StateWriter::new();
+        out.chunk(b"RAM ", |c| c.bytes(&vec![0xAA; 0x10000]));
+        assert!(load(&mut cpu, &out.finish()).is_err());
+        assert_eq!(save(&cpu), before);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::assembler::Assembly;
+use crate::cartridge::Cartridge;
+use crate::cpu_6502::{AddrMode, Cpu6502};
+use crate::disassembler::DisassembledInstruction;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// Names for addresses, and the source line each address came from, loaded
+// from the debug files assemblers and other emulators write:
+//
+//     .dbg    ld65 --dbgfile, labels, constants and source lines
+//     .nl     FCEUX name lists, $C000#Name#Comment
+//     .mlb    Mesen label files, P:0123:Name:Comment
+//     .sym    VICE label files as written by ld65 -Ln, al 00C000 .name
+//
+// Mesen gives PRG ROM labels as offsets into the ROM. With a cartridge
+// they go wherever it shows that byte to the cpu, both $8000 and $C000 for
+// a 16K NROM, and labels in banks it has not mapped are left out. Without
+// one the ROM is taken to start at $8000.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+pub struct SymbolTable {
+    // Name shown for an address, the first one loaded wins
+    labels: BTreeMap<u16, String>,
+    // Every name that can be used in an expression, constants included
+    names: HashMap<String, u16>,
+    lines: BTreeMap<u16, SourceLocation>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self {
+            labels: BTreeMap::new(),
+            names: HashMap::new(),
+            lines: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_label(&mut self, name: &str, addr: u16) {
+        self.labels.entry(addr).or_insert_with(|| name.to_string());
+        self.names.insert(name.to_string(), addr);
+    }
+
+    // A name that can be used in expressions but is not shown for its value
+    pub fn add_constant(&mut self, name: &str, value: u16) {
+        self.names.insert(name.to_string(), value);
+    }
+
+    pub fn add_line(&mut self, addr: u16, file: &str, line: usize) {
+        self.lines.insert(
+            addr,
+            SourceLocation {
+                file: file.to_string(),
+                line,
+            },
+        );
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(|s| s.as_str())
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.names.get(name).copied()
+    }
+
+    // Every name and its value, labels and constants alike
+    pub fn names(&self) -> &HashMap<String, u16> {
+        &self.names
+    }
+
+    pub fn source(&self, addr: u16) -> Option<&SourceLocation> {
+        self.lines.get(&addr)
+    }
+
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.lines.is_empty()
+    }
+
+    // "name" if addr has a label, "$xxxx" otherwise
+    pub fn name_or_hex(&self, addr: u16) -> String {
+        match self.label(addr) {
+            Some(name) => name.to_string(),
+            None => format!("${}", Cpu6502::hex(addr as u32, 4)),
+        }
+    }
+
+    // Debugger style line like DisassembledInstruction's Display, with the
+    // target shown by name when it has one
+    pub fn format_instruction(&self, ins: &DisassembledInstruction) -> String {
+        match ins.target.and_then(|t| self.label(t)) {
+            Some(name) if ins.mode != AddrMode::Imm => ins.line_with(&ins.operand_with(name)),
+            _ => ins.to_string(),
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////
+    // LOADING
+
+    // Picks the format from the file extension, returns how many names were
+    // added. The cartridge is needed to place Mesen's PRG ROM labels.
+    pub fn load(&mut self, path: &Path, cartridge: Option<&Cartridge>) -> Result<usize, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let before = self.len();
+        match extension.as_str() {
+            "dbg" => self.load_dbg(&text)?,
+            "nl" => self.load_nl(&text)?,
+            "mlb" => self.load_mlb(&text, cartridge)?,
+            "sym" | "lbl" | "labels" => self.load_vice(&text)?,
+            _ => return Err(format!("{}: unknown symbol file type", path.display())),
+        }
+        Ok(self.len() - before)
+    }
+
+    // Symbols and source lines of a program built with the crate assembler
+    pub fn add_assembly(&mut self, assembly: &Assembly) {
+        for (name, value) in &assembly.symbols {
+            self.add_label(name, *value);
+        }
+        for line in assembly.lines.iter().filter(|l| !l.bytes.is_empty()) {
+            self.add_line(line.addr, &line.file, line.line);
+        }
+    }
+
+    // ld65 debug info. Lines point at spans, spans at segments, so
+    // everything is collected first and resolved at the end.
+    pub fn load_dbg(&mut self, text: &str) -> Result<(), String> {
+        let mut files: HashMap<u32, String> = HashMap::new();
+        let mut segments: HashMap<u32, u32> = HashMap::new();
+        let mut spans: HashMap<u32, (u32, u32)> = HashMap::new();
+        let mut lines: Vec<(u32, usize, Vec<u32>)> = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let (kind, rest) = match line.split_once(char::is_whitespace) {
+                Some(split) => split,
+                None => continue,
+            };
+            let fields = dbg_fields(rest);
+            let number = |key: &str| -> Result<u32, String> {
+                let value = fields
+                    .get(key)
+                    .ok_or(format!("line {}: no {}", i + 1, key))?;
+                parse_number(value).ok_or(format!("line {}: bad {} '{}'", i + 1, key, value))
+            };
+
+            match kind {
+                "file" => {
+                    files.insert(
+                        number("id")?,
+                        fields.get("name").cloned().unwrap_or_default(),
+                    );
+                }
+                "seg" => {
+                    segments.insert(number("id")?, number("start")?);
+                }
+                "span" => {
+                    spans.insert(number("id")?, (number("seg")?, number("start")?));
+                }
+                "line" => {
+                    // Lines made by macro expansion (type 2) would hide the
+                    // line that used the macro
+                    if fields.get("type").map(|t| t.as_str()) == Some("2") {
+                        continue;
+                    }
+                    if let Some(span) = fields.get("span") {
+                        let ids: Vec<u32> = span.split('+').filter_map(parse_number).collect();
+                        lines.push((number("file")?, number("line")? as usize, ids));
+                    }
+                }
+                "sym" => {
+                    let name = fields
+                        .get("name")
+                        .ok_or(format!("line {}: no name", i + 1))?;
+                    let value = match fields.get("val") {
+                        Some(_) => number("val")? as u16,
+                        // Imports have no value of their own
+                        None => continue,
+                    };
+                    match fields.get("type").map(|t| t.as_str()) {
+                        Some("lab") => self.add_label(name, value),
+                        Some("equ") => self.add_constant(name, value),
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for (file, line, ids) in lines {
+            let name = files.get(&file).cloned().unwrap_or_default();
+            for id in ids {
+                if let Some((seg, start)) = spans.get(&id) {
+                    if let Some(base) = segments.get(seg) {
+                        self.add_line((base + start) as u16, &name, line);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // FCEUX name list, one "$addr#name#comment" per line. "$addr/size"
+    // names an array, only its first byte gets the name.
+    pub fn load_nl(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(3, '#');
+            let addr = parts.next().unwrap_or_default();
+            let name = parts.next().unwrap_or_default().trim();
+            let addr = addr.trim_start_matches('$');
+            let addr = addr.split('/').next().unwrap_or_default();
+            let addr = u16::from_str_radix(addr, 16)
+                .map_err(|_| format!("line {}: bad address '{}'", i + 1, addr))?;
+            if !name.is_empty() {
+                self.add_label(name, addr);
+            }
+        }
+        Ok(())
+    }
+
+    // Mesen label file, "type:addr[-end]:name[:comment]". Both the one
+    // letter types of Mesen and the longer names of Mesen 2 are understood.
+    pub fn load_mlb(&mut self, text: &str, cartridge: Option<&Cartridge>) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(4, ':');
+            let kind = parts.next().unwrap_or_default();
+            let addr = parts.next().unwrap_or_default();
+            let name = parts.next().unwrap_or_default().trim();
+            let addr = addr.split('-').next().unwrap_or_default();
+            let offset = u32::from_str_radix(addr, 16)
+                .map_err(|_| format!("line {}: bad address '{}'", i + 1, addr))?;
+
+            let addresses = match kind {
+                "P" | "NesPrgRom" => match cartridge {
+                    Some(cartridge) => cartridge
+                        .cpu_addresses(offset as usize)
+                        .into_iter()
+                        .map(u32::from)
+                        .collect(),
+                    None => vec![0x8000 + offset],
+                },
+                "R" | "NesInternalRam" => vec![offset & 0x07FF],
+                "G" | "NesMemory" => vec![offset],
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => vec![0x6000 + offset],
+                _ => continue,
+            };
+            for addr in addresses {
+                if !name.is_empty() && addr <= 0xFFFF {
+                    self.add_label(name, addr as u16);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // VICE monitor labels, "al 00C000 .name"
+    pub fn load_vice(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() < 3 || words[0] != "al" {
+                continue;
+            }
+            let addr = words[1].rsplit(':').next().unwrap_or_default();
+            let addr = u32::from_str_radix(addr, 16)
+                .map_err(|_| format!("line {}: bad address '{}'", i + 1, addr))?;
+            self.add_label(words[2].trim_start_matches('.'), addr as u16);
+        }
+        Ok(())
+    }
+}
+
+// key=value pairs separated by commas, values may be quoted
+fn dbg_fields(text: &str) -> HashMap<String, String> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in text.trim().chars().chain(std::iter::once(',')) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if let Some((key, value)) = field.split_once('=') {
+                    fields.insert(key.trim().to_string(), value.to_string());
+                }
+                field.clear();
+            }
+            _ => field.push(c),
+        }
+    }
+    fields
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
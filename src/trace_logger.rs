@@ -0,0 +1,294 @@
+use crate::cpu_6502::{AddrMode, Cpu6502};
+use crate::debugger::CPU_CYCLES_PER_FRAME;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Writes a line for every instruction the cpu executes, in the layout of
+// nestest.log so traces can be diffed against it (or against Mesen):
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//
+// There is no PPU yet, so scanline and dot are worked out from the cycle
+// count, three dots per cpu cycle.
+
+// Output is written in blocks this big, so a long trace never holds more
+// than this in memory
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TraceFormat {
+    Nestest,
+    Mesen,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TraceCondition {
+    // The instruction at this address is about to run
+    Address(u16),
+    // The frame counter reached this frame
+    Frame(u64),
+}
+
+impl TraceCondition {
+    fn is_met(&self, cpu: &Cpu6502) -> bool {
+        match self {
+            TraceCondition::Address(addr) => cpu.pc == *addr,
+            TraceCondition::Frame(frame) => cpu.clock_count / CPU_CYCLES_PER_FRAME >= *frame,
+        }
+    }
+}
+
+pub struct TraceLogger {
+    format: TraceFormat,
+    output: Option<BufWriter<File>>,
+    // Ring buffer mode keeps only the last capacity lines
+    ring: VecDeque<String>,
+    capacity: usize,
+    start: Option<TraceCondition>,
+    stop: Option<TraceCondition>,
+    active: bool,
+    finished: bool,
+    lines: u64,
+    // First write error, the trace stops there
+    error: Option<io::Error>,
+}
+
+impl TraceLogger {
+    // Streams every line to a file
+    pub fn to_file(path: &Path, format: TraceFormat) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let mut trace = TraceLogger::new(format);
+        trace.output = Some(BufWriter::with_capacity(BUFFER_SIZE, file));
+        Ok(trace)
+    }
+
+    // Keeps the last capacity lines in memory, for looking at what led up
+    // to a crash
+    pub fn ring(capacity: usize, format: TraceFormat) -> Self {
+        let mut trace = TraceLogger::new(format);
+        trace.capacity = capacity.max(1);
+        trace.ring = VecDeque::with_capacity(trace.capacity);
+        trace
+    }
+
+    fn new(format: TraceFormat) -> Self {
+        Self {
+            format,
+            output: None,
+            ring: VecDeque::new(),
+            capacity: 0,
+            start: None,
+            stop: None,
+            active: true,
+            finished: false,
+            lines: 0,
+            error: None,
+        }
+    }
+
+    // Logging waits for this condition before it begins
+    pub fn start_when(&mut self, condition: TraceCondition) {
+        self.start = Some(condition);
+        self.active = false;
+    }
+
+    // Logging ends for good once this condition is met
+    pub fn stop_when(&mut self, condition: TraceCondition) {
+        self.stop = Some(condition);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active && !self.finished
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    // The lines kept in ring buffer mode, oldest first
+    pub fn recent(&self) -> impl Iterator<Item = &String> {
+        self.ring.iter()
+    }
+
+    // Called with the cpu sitting on an instruction boundary, just before
+    // the instruction at pc runs
+    pub fn log(&mut self, cpu: &Cpu6502) {
+        if self.finished {
+            return;
+        }
+        if !self.active {
+            match self.start {
+                Some(condition) if !condition.is_met(cpu) => return,
+                _ => self.active = true,
+            }
+        }
+        if self.stop.is_some_and(|c| c.is_met(cpu)) {
+            self.finished = true;
+            self.flush();
+            return;
+        }
+
+        let line = format_line(cpu, self.format);
+        self.lines += 1;
+
+        if self.capacity > 0 {
+            if self.ring.len() == self.capacity {
+                self.ring.pop_front();
+            }
+            self.ring.push_back(line);
+        } else if let Some(output) = self.output.as_mut() {
+            if let Err(e) = writeln!(output, "{}", line) {
+                self.error = Some(e);
+                self.finished = true;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(output) = self.output.as_mut() {
+            if let Err(e) = output.flush() {
+                self.error.get_or_insert(e);
+            }
+        }
+    }
+
+    // Writes the ring buffer out to a file
+    pub fn save_recent(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for line in &self.ring {
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()
+    }
+}
+
+impl Drop for TraceLogger {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// One trace line for the instruction waiting at pc
+pub fn format_line(cpu: &Cpu6502, format: TraceFormat) -> String {
+    let ins = cpu.decode(cpu.pc);
+    let bytes: Vec<String> = ins
+        .bytes
+        .iter()
+        .map(|b| Cpu6502::hex(*b as u32, 2))
+        .collect();
+    let dots = cpu.clock_count * 3;
+    let scanline = (dots / 341) % 262;
+    let dot = dots % 341;
+
+    match format {
+        TraceFormat::Nestest => format!(
+            "{}  {:<8} {}{:<32}A:{} X:{} Y:{} P:{} SP:{} PPU:{:>3},{:>3} CYC:{}",
+            Cpu6502::hex(cpu.pc as u32, 4),
+            bytes.join(" "),
+            if ins.illegal { '*' } else { ' ' },
+            format!("{} {}", ins.mnemonic, operand_text(cpu)).trim_end(),
+            Cpu6502::hex(cpu.a as u32, 2),
+            Cpu6502::hex(cpu.x as u32, 2),
+            Cpu6502::hex(cpu.y as u32, 2),
+            Cpu6502::hex(cpu.sr as u32, 2),
+            Cpu6502::hex(cpu.sp as u32, 2),
+            scanline,
+            dot,
+            cpu.clock_count
+        ),
+        TraceFormat::Mesen => {
+            let flags: String = "NV-BDIZC"
+                .chars()
+                .enumerate()
+                .map(|(i, c)| {
+                    if cpu.sr & (0x80 >> i) != 0 {
+                        c
+                    } else {
+                        c.to_ascii_lowercase()
+                    }
+                })
+                .collect();
+            format!(
+                "{}  {:<8}  {:<32} A:{} X:{} Y:{} P:{} SP:{} CYC:{:<3} SL:{:<3} FC:{} CPU Cycle:{}",
+                Cpu6502::hex(cpu.pc as u32, 4),
+                bytes.join(" "),
+                format!("{} {}", ins.mnemonic, operand_text(cpu)).trim_end(),
+                Cpu6502::hex(cpu.a as u32, 2),
+                Cpu6502::hex(cpu.x as u32, 2),
+                Cpu6502::hex(cpu.y as u32, 2),
+                flags,
+                Cpu6502::hex(cpu.sp as u32, 2),
+                dot,
+                scanline,
+                cpu.clock_count / CPU_CYCLES_PER_FRAME,
+                cpu.clock_count
+            )
+        }
+    }
+}
+
+// Operand the way nestest prints it, with the effective address and the
+// value found there
+fn operand_text(cpu: &Cpu6502) -> String {
+    let ins = cpu.decode(cpu.pc);
+    let peek = |addr: u16| cpu.bus.read(&addr, true);
+    let hex = |n: u16, d: u8| Cpu6502::hex(n as u32, d);
+    let effective = cpu.operand_address().unwrap_or(0);
+    let value = hex(peek(effective) as u16, 2);
+    let jump = ins.mnemonic == "JMP" || ins.mnemonic == "JSR";
+
+    match ins.mode {
+        AddrMode::Imp if ins.is_accumulator() => "A".to_string(),
+        AddrMode::Imp => String::new(),
+        AddrMode::Imm => format!("#${}", hex(ins.operand, 2)),
+        AddrMode::Rel => format!("${}", hex(ins.target.unwrap_or(0), 4)),
+        AddrMode::Zp0 => format!("${} = {}", hex(ins.operand, 2), value),
+        AddrMode::Zpx => format!(
+            "${},X @ {} = {}",
+            hex(ins.operand, 2),
+            hex(effective, 2),
+            value
+        ),
+        AddrMode::Zpy => format!(
+            "${},Y @ {} = {}",
+            hex(ins.operand, 2),
+            hex(effective, 2),
+            value
+        ),
+        AddrMode::Abs if jump => format!("${}", hex(ins.operand, 4)),
+        AddrMode::Abs => format!("${} = {}", hex(ins.operand, 4), value),
+        AddrMode::Abx => format!(
+            "${},X @ {} = {}",
+            hex(ins.operand, 4),
+            hex(effective, 4),
+            value
+        ),
+        AddrMode::Aby => format!(
+            "${},Y @ {} = {}",
+            hex(ins.operand, 4),
+            hex(effective, 4),
+            value
+        ),
+        AddrMode::Ind => format!("(${}) = {}", hex(ins.operand, 4), hex(effective, 4)),
+        AddrMode::Izx => format!(
+            "(${},X) @ {} = {} = {}",
+            hex(ins.operand, 2),
+            hex(ins.operand.wrapping_add(cpu.x as u16) & 0x00FF, 2),
+            hex(effective, 4),
+            value
+        ),
+        AddrMode::Izy => format!(
+            "(${}),Y = {} @ {} = {}",
+            hex(ins.operand, 2),
+            hex(effective.wrapping_sub(cpu.y as u16), 4),
+            hex(effective, 4),
+            value
+        ),
+    }
+}
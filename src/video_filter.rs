@@ -0,0 +1,1155 @@
+use crate::image::Image;
+
+// Upscalers for the picture, all on the CPU. Each takes an RGB image and
+// returns a bigger one, so they can be chained ("scale2x,nearest2" is
+// Scale2x and then doubled again) and picked by name at run time:
+//
+//     nearest2, nearest3, nearest4   plain pixel repetition
+//     scale2x, scale3x               AdvanceMAME's EPX rules, exact
+//     hq2x, hq3x                     Maxim Stepin's hqx, his case tables
+//     blend2x, blend3x               blending of edges found by comparing
+//                                    neighbours in YUV
+//     xbr2x                          Hyllian's xBR, first level rules
+//
+// The blend filters use hqx's notion of two colours differing, but only a
+// few rules about which neighbours match rather than his tables. They are
+// cheaper than hqx and a little softer.
+
+pub trait VideoFilter {
+    fn name(&self) -> &'static str;
+
+    // How many times bigger the output is each way
+    fn scale(&self) -> usize;
+
+    fn apply(&self, input: &Image) -> Image;
+}
+
+pub struct Nearest(pub usize);
+pub struct Scale2x;
+pub struct Scale3x;
+pub struct Hq2x;
+pub struct Hq3x;
+pub struct Blend2x;
+pub struct Blend3x;
+pub struct Xbr2x;
+
+// Looks a filter up by the name it reports
+pub fn filter_by_name(name: &str) -> Option<Box<dyn VideoFilter>> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "nearest2" => Some(Box::new(Nearest(2))),
+        "nearest3" => Some(Box::new(Nearest(3))),
+        "nearest4" => Some(Box::new(Nearest(4))),
+        "scale2x" => Some(Box::new(Scale2x)),
+        "scale3x" => Some(Box::new(Scale3x)),
+        "hq2x" => Some(Box::new(Hq2x)),
+        "hq3x" => Some(Box::new(Hq3x)),
+        "blend2x" => Some(Box::new(Blend2x)),
+        "blend3x" => Some(Box::new(Blend3x)),
+        "xbr2x" => Some(Box::new(Xbr2x)),
+        _ => None,
+    }
+}
+
+// Filters run one after the other
+pub struct FilterChain {
+    filters: Vec<Box<dyn VideoFilter>>,
+}
+
+impl Default for FilterChain {
+    fn default() -> Self {
+        FilterChain::new()
+    }
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self {
+            filters: Vec::new(),
+        }
+    }
+
+    // A comma separated list of filter names, empty or "none" for no
+    // filtering
+    pub fn parse(names: &str) -> Result<Self, String> {
+        let mut chain = FilterChain::new();
+        if names.trim().is_empty() || names.trim().eq_ignore_ascii_case("none") {
+            return Ok(chain);
+        }
+        for name in names.split(',') {
+            let filter = filter_by_name(name).ok_or(format!("unknown filter '{}'", name))?;
+            chain.push(filter);
+        }
+        Ok(chain)
+    }
+
+    pub fn push(&mut self, filter: Box<dyn VideoFilter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn scale(&self) -> usize {
+        self.filters.iter().map(|f| f.scale()).product()
+    }
+
+    pub fn describe(&self) -> String {
+        let names: Vec<&str> = self.filters.iter().map(|f| f.name()).collect();
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(",")
+        }
+    }
+
+    pub fn apply(&self, input: &Image) -> Image {
+        let mut image = input.clone();
+        for filter in &self.filters {
+            image = filter.apply(&image);
+        }
+        image
+    }
+}
+
+type Rgb = [u8; 3];
+
+// The pixel at x+dx, y+dy, with the edge pixels repeated outwards
+fn at(image: &Image, x: usize, y: usize, dx: isize, dy: isize) -> Rgb {
+    let x = (x as isize + dx).clamp(0, image.width as isize - 1) as usize;
+    let y = (y as isize + dy).clamp(0, image.height as isize - 1) as usize;
+    image.pixel(x, y)
+}
+
+// Builds an image scale times the size, asking block for the scale*scale
+// output pixels of each input pixel, row by row
+fn upscale(input: &Image, scale: usize, block: impl Fn(usize, usize) -> Vec<Rgb>) -> Image {
+    let mut output = Image::new(input.width * scale, input.height * scale);
+    for y in 0..input.height {
+        for x in 0..input.width {
+            for (i, color) in block(x, y).into_iter().enumerate() {
+                output.set_pixel(x * scale + i % scale, y * scale + i / scale, color);
+            }
+        }
+    }
+    output
+}
+
+// Weighted average of colours, weights summing to anything
+fn blend(colors: &[(Rgb, u32)]) -> Rgb {
+    let total: u32 = colors.iter().map(|(_, w)| w).sum();
+    let mut out = [0; 3];
+    for (channel, value) in out.iter_mut().enumerate() {
+        let sum: u32 = colors.iter().map(|(c, w)| c[channel] as u32 * w).sum();
+        *value = ((sum + total / 2) / total) as u8;
+    }
+    out
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// NEAREST AND SCALE2X/3X
+
+impl VideoFilter for Nearest {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            2 => "nearest2",
+            3 => "nearest3",
+            4 => "nearest4",
+            _ => "nearest",
+        }
+    }
+
+    fn scale(&self) -> usize {
+        self.0.max(1)
+    }
+
+    fn apply(&self, input: &Image) -> Image {
+        let scale = self.scale();
+        upscale(input, scale, |x, y| vec![input.pixel(x, y); scale * scale])
+    }
+}
+
+impl VideoFilter for Scale2x {
+    fn name(&self) -> &'static str {
+        "scale2x"
+    }
+
+    fn scale(&self) -> usize {
+        2
+    }
+
+    fn apply(&self, input: &Image) -> Image {
+        upscale(input, 2, |x, y| {
+            let p = |dx, dy| at(input, x, y, dx, dy);
+            let (b, d, e, f, h) = (p(0, -1), p(-1, 0), p(0, 0), p(1, 0), p(0, 1));
+            if b == h || d == f {
+                return vec![e; 4];
+            }
+            vec![
+                if d == b { d } else { e },
+                if b == f { f } else { e },
+                if d == h { d } else { e },
+                if h == f { f } else { e },
+            ]
+        })
+    }
+}
+
+impl VideoFilter for Scale3x {
+    fn name(&self) -> &'static str {
+        "scale3x"
+    }
+
+    fn scale(&self) -> usize {
+        3
+    }
+
+    fn apply(&self, input: &Image) -> Image {
+        upscale(input, 3, |x, y| {
+            let p = |dx, dy| at(input, x, y, dx, dy);
+            let (a, b, c) = (p(-1, -1), p(0, -1), p(1, -1));
+            let (d, e, f) = (p(-1, 0), p(0, 0), p(1, 0));
+            let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+            if b == h || d == f {
+                return vec![e; 9];
+            }
+            vec![
+                if d == b { d } else { e },
+                if (d == b && e != c) || (b == f && e != a) {
+                    b
+                } else {
+                    e
+                },
+                if b == f { f } else { e },
+                if (d == b && e != g) || (d == h && e != a) {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (b == f && e != i) || (h == f && e != c) {
+                    f
+                } else {
+                    e
+                },
+                if d == h { d } else { e },
+                if (d == h && e != i) || (h == f && e != g) {
+                    h
+                } else {
+                    e
+                },
+                if h == f { f } else { e },
+            ]
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// HQ2X/3X
+
+fn yuv(c: Rgb) -> (i32, i32, i32) {
+    let (r, g, b) = (c[0] as i32, c[1] as i32, c[2] as i32);
+    let y = (r + g + b) >> 2;
+    let u = 128 + ((r - b) >> 2);
+    let v = 128 + ((2 * g - r - b) >> 3);
+    (y, u, v)
+}
+
+// hqx's notion of two colours looking different
+fn differ(a: Rgb, b: Rgb) -> bool {
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+    (ya - yb).abs() > 48 || (ua - ub).abs() > 7 || (va - vb).abs() > 6
+}
+
+// The 3x3 neighbourhood as a b c / d e f / g h i
+fn neighbours(input: &Image, x: usize, y: usize) -> [Rgb; 9] {
+    let mut n = [[0; 3]; 9];
+    for (i, color) in n.iter_mut().enumerate() {
+        *color = at(input, x, y, i as isize % 3 - 1, i as isize / 3 - 1);
+    }
+    n
+}
+
+// Each neighbour that looks different from the centre sets a bit of an 8
+// bit pattern, and Stepin's tables pick a blend for every output pixel
+// from that, comparing a pair of neighbours with each other where the
+// pattern alone is not enough. The tables are the same from every corner,
+// so, as in FFmpeg's version, they are written once for the top left
+// corner (and for hq3x the top edge next to it) and turned round for the
+// others. Bits are top left, top, top right, left, right, bottom left,
+// bottom, bottom right from 0 to 7. A case is a mask and the bits that
+// must be set under it.
+type Cases = &'static [(u8, u8)];
+
+fn matches(pattern: u8, cases: Cases) -> bool {
+    cases.iter().any(|(mask, bits)| pattern & mask == *bits)
+}
+
+fn pattern(w: &[Rgb; 9]) -> u8 {
+    [0, 1, 2, 3, 5, 6, 7, 8]
+        .iter()
+        .enumerate()
+        .filter(|(_, i)| differ(w[4], w[**i]))
+        .fold(0, |pattern, (bit, _)| pattern | 1 << bit)
+}
+
+// hqx blends truncate
+fn mix(colors: &[(Rgb, u32)]) -> Rgb {
+    let total: u32 = colors.iter().map(|(_, w)| w).sum();
+    let mut out = [0; 3];
+    for (channel, value) in out.iter_mut().enumerate() {
+        let sum: u32 = colors.iter().map(|(c, w)| c[channel] as u32 * w).sum();
+        *value = (sum / total) as u8;
+    }
+    out
+}
+
+// The neighbourhood seen from each corner in turn, as indices into
+// neighbours(), turned so the corner is top left and the edge clockwise
+// from it top centre. Then where that corner and edge are in a 3x3 block.
+const TURNS: [([usize; 9], usize, usize); 4] = [
+    ([0, 1, 2, 3, 4, 5, 6, 7, 8], 0, 1),
+    ([2, 5, 8, 1, 4, 7, 0, 3, 6], 2, 5),
+    ([8, 7, 6, 5, 4, 3, 2, 1, 0], 8, 7),
+    ([6, 3, 0, 7, 4, 1, 8, 5, 2], 6, 3),
+];
+
+fn turn(n: &[Rgb; 9], order: &[usize; 9]) -> [Rgb; 9] {
+    order.map(|i| n[i])
+}
+
+const ACROSS_LEFT: Cases = &[(0xBF, 0x37), (0xDB, 0x13)];
+const ACROSS_TOP: Cases = &[(0xDB, 0x49), (0xEF, 0x6D)];
+const CORNER_KEPT: Cases = &[(0x0B, 0x0B), (0xFE, 0x4A), (0xFE, 0x1A)];
+const CORNER_DIAGONAL: Cases = &[
+    (0x6F, 0x2A),
+    (0x5B, 0x0A),
+    (0xBF, 0x3A),
+    (0xDF, 0x5A),
+    (0x9F, 0x8A),
+    (0xCF, 0x8A),
+    (0xEF, 0x4E),
+    (0x3F, 0x0E),
+    (0xFB, 0x5A),
+    (0xBB, 0x8A),
+    (0x7F, 0x5A),
+    (0xAF, 0x8A),
+    (0xEB, 0x8A),
+];
+const LEAN_LEFT: Cases = &[(0x1B, 0x03), (0x4F, 0x43), (0x8B, 0x83), (0x6B, 0x43)];
+const LEAN_TOP: Cases = &[(0x4B, 0x09), (0x8B, 0x89), (0x1F, 0x19), (0x3B, 0x19)];
+const EDGE_ACROSS: Cases = &[(0x7E, 0x2A), (0xEF, 0xAB), (0xBF, 0x8F), (0x7E, 0x0E)];
+const EDGE_NEAR: Cases = &[
+    (0x4F, 0x4B),
+    (0x9F, 0x1B),
+    (0x2F, 0x0B),
+    (0xBE, 0x0A),
+    (0xEE, 0x0A),
+    (0x7E, 0x0A),
+    (0xEB, 0x4B),
+    (0x3B, 0x1B),
+];
+
+// The corner cases both sizes share, where two neighbours are compared
+fn hq_corner_compared(w: &[Rgb; 9], k: u8) -> Option<Rgb> {
+    let [w0, w1, _, w3, w4, w5, _, w7, _] = *w;
+    if matches(k, ACROSS_LEFT) && differ(w1, w5) {
+        Some(mix(&[(w4, 3), (w3, 1)]))
+    } else if matches(k, ACROSS_TOP) && differ(w7, w3) {
+        Some(mix(&[(w4, 3), (w1, 1)]))
+    } else if matches(k, CORNER_KEPT) && differ(w3, w1) {
+        Some(w4)
+    } else if matches(k, CORNER_DIAGONAL) && differ(w3, w1) {
+        Some(mix(&[(w4, 3), (w0, 1)]))
+    } else {
+        None
+    }
+}
+
+fn hq2x_corner(w: &[Rgb; 9]) -> Rgb {
+    let k = pattern(w);
+    if let Some(color) = hq_corner_compared(w, k) {
+        return color;
+    }
+    let [w0, w1, _, w3, w4, ..] = *w;
+    if matches(k, &[(0x0B, 0x08)]) {
+        mix(&[(w4, 2), (w0, 1), (w1, 1)])
+    } else if matches(k, &[(0x0B, 0x02)]) {
+        mix(&[(w4, 2), (w0, 1), (w3, 1)])
+    } else if matches(k, &[(0x2F, 0x2F)]) {
+        mix(&[(w4, 14), (w3, 1), (w1, 1)])
+    } else if matches(k, ACROSS_LEFT) {
+        mix(&[(w4, 5), (w1, 2), (w3, 1)])
+    } else if matches(k, ACROSS_TOP) {
+        mix(&[(w4, 5), (w3, 2), (w1, 1)])
+    } else if matches(k, LEAN_LEFT) {
+        mix(&[(w4, 3), (w3, 1)])
+    } else if matches(k, LEAN_TOP) {
+        mix(&[(w4, 3), (w1, 1)])
+    } else if matches(k, EDGE_ACROSS) {
+        mix(&[(w4, 2), (w3, 3), (w1, 3)])
+    } else if matches(
+        k,
+        &[
+            (0xFB, 0x6A),
+            (0x6F, 0x6E),
+            (0x3F, 0x3E),
+            (0xFB, 0xFA),
+            (0xDF, 0xDE),
+            (0xDF, 0x1E),
+        ],
+    ) {
+        mix(&[(w4, 3), (w0, 1)])
+    } else if matches(k, &[(0x0A, 0x00)]) || matches(k, EDGE_NEAR) {
+        mix(&[(w4, 2), (w3, 1), (w1, 1)])
+    } else {
+        mix(&[(w4, 6), (w3, 1), (w1, 1)])
+    }
+}
+
+fn hq3x_corner(w: &[Rgb; 9]) -> Rgb {
+    let k = pattern(w);
+    if let Some(color) = hq_corner_compared(w, k) {
+        return color;
+    }
+    let [w0, w1, _, w3, w4, ..] = *w;
+    if matches(k, LEAN_TOP) {
+        mix(&[(w4, 3), (w1, 1)])
+    } else if matches(k, LEAN_LEFT) {
+        mix(&[(w4, 3), (w3, 1)])
+    } else if matches(k, EDGE_ACROSS) {
+        mix(&[(w3, 1), (w1, 1)])
+    } else if matches(k, EDGE_NEAR) {
+        mix(&[(w4, 2), (w3, 7), (w1, 7)])
+    } else if matches(
+        k,
+        &[
+            (0x0B, 0x08),
+            (0xF9, 0x68),
+            (0xF3, 0x62),
+            (0x6D, 0x6C),
+            (0x67, 0x66),
+            (0x3D, 0x3C),
+            (0x37, 0x36),
+            (0xF9, 0xF8),
+            (0xDD, 0xDC),
+            (0xF3, 0xF2),
+            (0xD7, 0xD6),
+            (0xDD, 0x1C),
+            (0xD7, 0x16),
+            (0x0B, 0x02),
+        ],
+    ) {
+        mix(&[(w4, 3), (w0, 1)])
+    } else {
+        mix(&[(w4, 2), (w3, 1), (w1, 1)])
+    }
+}
+
+fn hq3x_edge(w: &[Rgb; 9]) -> Rgb {
+    let k = pattern(w);
+    let [_, w1, _, w3, w4, w5, ..] = *w;
+    let kept_right = &[
+        (0xFE, 0xDE),
+        (0x9E, 0x16),
+        (0xDA, 0x12),
+        (0x17, 0x16),
+        (0x5B, 0x12),
+        (0xBB, 0x12),
+    ];
+    let kept_left = &[
+        (0x0F, 0x0B),
+        (0x5E, 0x0A),
+        (0xFB, 0x7B),
+        (0x3B, 0x0B),
+        (0xBE, 0x0A),
+        (0x7A, 0x0A),
+    ];
+    if (matches(k, kept_right) && differ(w1, w5)) || (matches(k, kept_left) && differ(w3, w1)) {
+        w4
+    } else if matches(k, &[(0xBF, 0x8F), (0x7E, 0x0E), (0xBF, 0x37), (0xDB, 0x13)]) {
+        mix(&[(w1, 3), (w4, 1)])
+    } else if matches(
+        k,
+        &[
+            (0x02, 0x00),
+            (0x7C, 0x28),
+            (0xED, 0xA9),
+            (0xF5, 0xB4),
+            (0xD9, 0x90),
+        ],
+    ) {
+        mix(&[(w4, 3), (w1, 1)])
+    } else if matches(
+        k,
+        &[
+            (0x4F, 0x4B),
+            (0xFB, 0x7B),
+            (0xFE, 0x7E),
+            (0x9F, 0x1B),
+            (0x2F, 0x0B),
+            (0xBE, 0x0A),
+            (0x7E, 0x0A),
+            (0xFB, 0x4B),
+            (0xFB, 0xDB),
+            (0xFE, 0xDE),
+            (0xFE, 0x56),
+            (0x57, 0x56),
+            (0x97, 0x16),
+            (0x3F, 0x1E),
+            (0xDB, 0x12),
+            (0xBB, 0x12),
+        ],
+    ) {
+        mix(&[(w4, 7), (w1, 1)])
+    } else {
+        w4
+    }
+}
+
+impl VideoFilter for Hq2x {
+    fn name(&self) -> &'static str {
+        "hq2x"
+    }
+
+    fn scale(&self) -> usize {
+        2
+    }
+
+    fn apply(&self, input: &Image) -> Image {
+        upscale(input, 2, |x, y| {
+            let n = neighbours(input, x, y);
+            let mut block = vec![[0; 3]; 4];
+            for (order, corner, _) in &TURNS {
+                block[corner / 6 * 2 + corner % 3 / 2] = hq2x_corner(&turn(&n, order));
+            }
+            block
+        })
+    }
+}
+
+impl VideoFilter for Hq3x {
+    fn name(&self) -> &'static str {
+        "hq3x"
+    }
+
+    fn scale(&self) -> usize {
+        3
+    }
+
+    fn apply(&self, input: &Image) -> Image {
+        upscale(input, 3, |x, y| {
+            let n = neighbours(input, x, y);
+            let mut block = vec![n[4]; 9];
+            for (order, corner, edge) in &TURNS {
+                let w = turn(&n, order);
+                block[*corner] = hq3x_corner(&w);
+                block[*edge] = hq3x_edge(&w);
+            }
+            block
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// BLEND2X/3X
+
+// The corner of e between its neighbours side1 and side2, with diagonal
+// the pixel in the corner itself
+fn blend_corner(e: Rgb, side1: Rgb, side2: Rgb, diagonal: Rgb) -> Rgb {
+    let edge = !differ(side1, side2) && differ(e, side1);
+    if edge && !differ(diagonal, side1) {
+        // A solid edge running across the corner
+        blend(&[(e, 2), (side1, 3), (side2, 3)])
+    } else if edge {
+        // Only the two sides match, a thin diagonal line
+        blend(&[(e, 2), (side1, 1), (side2, 1)])
+    } else if differ(e, diagonal) && !differ(e, side1) && !differ(e, side2) {
+        // A corner sticking in, soften it a little
+        blend(&[(e, 3), (diagonal, 1)])
+    } else {
+        e
+    }
+}
+
+impl VideoFilter for Blend2x {
+    fn name(&self) -> &'static str {
+        "blend2x"
+    }
+
+    fn scale(&self) -> usize {
+        2
+    }
+
+    fn apply(&self, input: &Image) -> Image {
+        upscale(input, 2, |x, y| {
+            let [a, b, c, d, e, f, g, h, i] = neighbours(input, x, y);
+            vec![
+                blend_corner(e, b, d, a),
+                blend_corner(e, b, f, c),
+                blend_corner(e, h, d, g),
+                blend_corner(e, h, f, i),
+            ]
+        })
+    }
+}
+
+impl VideoFilter for Blend3x {
+    fn name(&self) -> &'static str {
+        "blend3x"
+    }
+
+    fn scale(&self) -> usize {
+        3
+    }
+
+    fn apply(&self, input: &Image) -> Image {
+        upscale(input, 3, |x, y| {
+            let [a, b, c, d, e, f, g, h, i] = neighbours(input, x, y);
+            let (tl, tr) = (blend_corner(e, b, d, a), blend_corner(e, b, f, c));
+            let (bl, br) = (blend_corner(e, h, d, g), blend_corner(e, h, f, i));
+            // The middle of a side leans towards its neighbour when a corner
+            // next to it was blended
+            let side = |n: Rgb, c1: Rgb, c2: Rgb| {
+                if differ(e, n) && (c1 != e || c2 != e) {
+                    blend(&[(e, 7), (n, 1)])
+                } else {
+                    e
+                }
+            };
+            vec![
+                tl,
+                side(b, tl, tr),
+                tr,
+                side(d, tl, bl),
+                e,
+                side(f, tr, br),
+                bl,
+                side(h, bl, br),
+                br,
+            ]
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// XBR
+
+fn distance(a: Rgb, b: Rgb) -> i32 {
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+    48 * (ya - yb).abs() + 7 * (ua - ub).abs() + 6 * (va - vb).abs()
+}
+
+impl VideoFilter for Xbr2x {
+    fn name(&self) -> &'static str {
+        "xbr2x"
+    }
+
+    fn scale(&self) -> usize {
+        2
+    }
+
+    // For each corner, an edge runs across it when the colour differences
+    // along the corner's diagonal outweigh those across it. The corner then
+    // takes on the closer of the two neighbours beside it, half blended.
+    fn apply(&self, input: &Image) -> Image {
+        upscale(input, 2, |x, y| {
+            let p = |dx, dy| at(input, x, y, dx, dy);
+            let e = p(0, 0);
+            // (dx, dy) points at the corner: bottom right is (1, 1)
+            let corner = |dx: isize, dy: isize| {
+                let (f, h) = (p(dx, 0), p(0, dy));
+                let (i, c, g) = (p(dx, dy), p(dx, -dy), p(-dx, dy));
+                let (d, b) = (p(-dx, 0), p(0, -dy));
+                let (f4, h5) = (p(2 * dx, 0), p(0, 2 * dy));
+                let (i4, i5) = (p(2 * dx, dy), p(dx, 2 * dy));
+
+                let along = distance(e, c)
+                    + distance(e, g)
+                    + distance(i, f4)
+                    + distance(i, h5)
+                    + 4 * distance(h, f);
+                let across = distance(h, d)
+                    + distance(h, i5)
+                    + distance(f, i4)
+                    + distance(f, b)
+                    + 4 * distance(e, i);
+                if along < across && e != f && e != h {
+                    let closer = if distance(e, f) <= distance(e, h) {
+                        f
+                    } else {
+                        h
+                    };
+                    blend(&[(e, 1), (closer, 1)])
+                } else {
+                    e
+                }
+            };
+            vec![corner(-1, -1), corner(1, -1), corner(-1, 1), corner(1, 1)]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pictures drawn in characters, one colour each. The expected outputs
+    // are from the published Scale2x/3x rules and Hyllian's xBR, worked
+    // through independently of the code here.
+    const COLORS: [(char, Rgb); 4] = [
+        ('.', [0, 0, 0]),
+        ('#', [255, 255, 255]),
+        ('o', [200, 80, 0]),
+        ('+', [128, 128, 128]),
+    ];
+
+    fn image(rows: &[&str]) -> Image {
+        let mut image = Image::new(rows[0].len(), rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let color = COLORS.iter().find(|(name, _)| *name == c).unwrap().1;
+                image.set_pixel(x, y, color);
+            }
+        }
+        image
+    }
+
+    fn rows(image: &Image) -> Vec<String> {
+        (0..image.height)
+            .map(|y| {
+                (0..image.width)
+                    .map(|x| {
+                        let color = image.pixel(x, y);
+                        COLORS
+                            .iter()
+                            .find(|(_, c)| *c == color)
+                            .map_or('?', |(name, _)| *name)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    const SHAPES: [&str; 6] = [
+        "......", //
+        "..#...", //
+        ".###o.", //
+        "..#oo.", //
+        "...o..", //
+        "......",
+    ];
+
+    #[test]
+    fn nearest_repeats_pixels() {
+        let output = Nearest(3).apply(&image(&["#o", ".#"]));
+        assert_eq!(
+            rows(&output),
+            ["###ooo", "###ooo", "###ooo", "...###", "...###", "...###"]
+        );
+    }
+
+    #[test]
+    fn scale2x_matches_the_reference() {
+        let expected = [
+            "............",
+            "............",
+            "....##......",
+            "...####.....",
+            "..######o...",
+            "..#####ooo..",
+            "...####ooo..",
+            ".....#ooo...",
+            "......ooo...",
+            "......oo....",
+            "............",
+            "............",
+        ];
+        assert_eq!(rows(&Scale2x.apply(&image(&SHAPES))), expected);
+    }
+
+    #[test]
+    fn scale3x_matches_the_reference() {
+        let expected = [
+            "..................",
+            "..................",
+            "..................",
+            "......###.........",
+            "......###.........",
+            ".....######.......",
+            "...#########o.....",
+            "...########ooo....",
+            "...########oooo...",
+            ".....#####ooooo...",
+            "......####oooo....",
+            "........#ooooo....",
+            ".........oooo.....",
+            ".........ooo......",
+            ".........ooo......",
+            "..................",
+            "..................",
+            "..................",
+        ];
+        assert_eq!(rows(&Scale3x.apply(&image(&SHAPES))), expected);
+    }
+
+    #[test]
+    fn xbr2x_matches_the_reference() {
+        let input = [
+            "......", //
+            ".....#", //
+            "....##", //
+            "...###", //
+            "..####", //
+            "######",
+        ];
+        let expected = [
+            "............",
+            "............",
+            "..........+#",
+            ".........+##",
+            "........+###",
+            ".......+####",
+            "......+#####",
+            ".....+######",
+            "....+#######",
+            "...+########",
+            "############",
+            "############",
+        ];
+        assert_eq!(rows(&Xbr2x.apply(&image(&input))), expected);
+    }
+
+    type Same = &'static [(usize, usize)];
+
+    // A neighbourhood for one of Stepin's cases, with his numbering: 1 2 3 /
+    // 4 5 6 / 7 8 9 and pattern bits 1, 2, 4, 8 for 1 to 4 and 16 to 128
+    // for 6 to 9. Neighbours in the pattern get strong colours in turn, the
+    // others a grey close to the centre's, each a little different. Pairs
+    // in same are then made the same colour.
+    fn case(pattern: u8, same: Same) -> Image {
+        const STRONG: [Rgb; 8] = [
+            [255, 255, 255],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 0],
+            [0, 255, 255],
+            [255, 0, 255],
+            [255, 128, 0],
+        ];
+        let mut w = [[64, 64, 64]; 10];
+        let mut strong = STRONG.iter();
+        for (bit, i) in [1, 2, 3, 4, 6, 7, 8, 9].into_iter().enumerate() {
+            w[i] = if pattern & 1 << bit != 0 {
+                *strong.next().unwrap()
+            } else {
+                [64 + 3 * i as u8, 64, 64]
+            };
+        }
+        for (from, to) in same {
+            w[*to] = w[*from];
+        }
+        let mut image = Image::new(3, 3);
+        for (i, color) in w[1..].iter().enumerate() {
+            image.set_pixel(i % 3, i / 3, *color);
+        }
+        image
+    }
+
+    // The output block of the middle pixel, row by row
+    fn middle(filter: &dyn VideoFilter, image: &Image) -> Vec<Rgb> {
+        let scale = filter.scale();
+        let output = filter.apply(image);
+        (0..scale * scale)
+            .map(|i| output.pixel(scale + i % scale, scale + i / scale))
+            .collect()
+    }
+
+    // Expected blocks are worked out from the PIXEL macros of the cases in
+    // Stepin's hq2x and hq3x tables, named in the comments
+    #[test]
+    fn hq2x_matches_the_reference() {
+        let cases: [(u8, Same, [Rgb; 4]); 10] = [
+            // 0: 20 20 20 20
+            (
+                0,
+                &[],
+                [[68, 64, 64], [70, 64, 64], [73, 64, 64], [74, 64, 64]],
+            ),
+            // 2: 22 21 20 20
+            (
+                2,
+                &[],
+                [[67, 64, 64], [70, 64, 64], [73, 64, 64], [74, 64, 64]],
+            ),
+            // 16: 20 22 20 21
+            (
+                16,
+                &[],
+                [[68, 64, 64], [67, 64, 64], [73, 64, 64], [76, 64, 64]],
+            ),
+            // 8: 21 20 22 20
+            (
+                8,
+                &[],
+                [[66, 64, 64], [70, 64, 64], [75, 64, 64], [74, 64, 64]],
+            ),
+            // 10: 10 21 22 20
+            (
+                10,
+                &[],
+                [[64, 64, 64], [70, 64, 64], [75, 64, 64], [74, 64, 64]],
+            ),
+            // 10: 20 21 22 20
+            (
+                10,
+                &[(2, 4)],
+                [[159, 159, 159], [70, 64, 64], [75, 64, 64], [74, 64, 64]],
+            ),
+            // 51: 11 10 20 21
+            (
+                51,
+                &[],
+                [[67, 64, 64], [66, 64, 64], [73, 64, 64], [76, 64, 64]],
+            ),
+            // 51: 60 90 20 21
+            (
+                51,
+                &[(2, 6)],
+                [[113, 48, 48], [207, 16, 16], [73, 64, 64], [76, 64, 64]],
+            ),
+            // 255: 0 0 0 0
+            (
+                255,
+                &[],
+                [[64, 64, 64], [64, 64, 64], [64, 64, 64], [64, 64, 64]],
+            ),
+            // 255: 100 0 0 0
+            (
+                255,
+                &[(2, 4)],
+                [[87, 56, 56], [64, 64, 64], [64, 64, 64], [64, 64, 64]],
+            ),
+        ];
+        for (pattern, same, expected) in cases {
+            let block = middle(&Hq2x, &case(pattern, same));
+            assert_eq!(block, expected, "case {} {:?}", pattern, same);
+        }
+    }
+
+    #[test]
+    fn hq3x_matches_the_reference() {
+        let cases: [(u8, Same, [Rgb; 9]); 10] = [
+            // 0: 2 1 2 1 C 1 2 1 2
+            (
+                0,
+                &[],
+                [
+                    [68, 64, 64],
+                    [65, 64, 64],
+                    [70, 64, 64],
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [68, 64, 64],
+                    [73, 64, 64],
+                    [70, 64, 64],
+                    [74, 64, 64],
+                ],
+            ),
+            // 2: 1M C 1M 1 C 1 2 1 2
+            (
+                2,
+                &[],
+                [
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [66, 64, 64],
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [68, 64, 64],
+                    [73, 64, 64],
+                    [70, 64, 64],
+                    [74, 64, 64],
+                ],
+            ),
+            // 3: 1L C 1M 1 C 1 2 1 2
+            (
+                3,
+                &[],
+                [
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [66, 64, 64],
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [68, 64, 64],
+                    [73, 64, 64],
+                    [70, 64, 64],
+                    [74, 64, 64],
+                ],
+            ),
+            // 6: 1M C 1R 1 C 1 2 1 2
+            (
+                6,
+                &[],
+                [
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [68, 64, 64],
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [68, 64, 64],
+                    [73, 64, 64],
+                    [70, 64, 64],
+                    [74, 64, 64],
+                ],
+            ),
+            // 16: 2 1 1M 1 C C 2 1 1M
+            (
+                16,
+                &[],
+                [
+                    [68, 64, 64],
+                    [65, 64, 64],
+                    [66, 64, 64],
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [73, 64, 64],
+                    [70, 64, 64],
+                    [70, 64, 64],
+                ],
+            ),
+            // 51: 1L C 1M 1 C C 2 1 1M
+            (
+                51,
+                &[],
+                [
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [66, 64, 64],
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [73, 64, 64],
+                    [70, 64, 64],
+                    [70, 64, 64],
+                ],
+            ),
+            // 51: 2 6 5 1 C 1 2 1 1M
+            (
+                51,
+                &[(2, 6)],
+                [
+                    [114, 48, 48],
+                    [207, 16, 16],
+                    [255, 0, 0],
+                    [67, 64, 64],
+                    [64, 64, 64],
+                    [111, 48, 48],
+                    [73, 64, 64],
+                    [70, 64, 64],
+                    [70, 64, 64],
+                ],
+            ),
+            // 10: 1M C 1M C C 1 1M 1 2
+            (
+                10,
+                &[],
+                [
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [66, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [68, 64, 64],
+                    [69, 64, 64],
+                    [70, 64, 64],
+                    [74, 64, 64],
+                ],
+            ),
+            // 10: 4 3 1M 3 C 1 1M 1 2
+            (
+                10,
+                &[(2, 4)],
+                [
+                    [231, 231, 231],
+                    [87, 87, 87],
+                    [66, 64, 64],
+                    [87, 87, 87],
+                    [64, 64, 64],
+                    [68, 64, 64],
+                    [69, 64, 64],
+                    [70, 64, 64],
+                    [74, 64, 64],
+                ],
+            ),
+            // 255: 2 C C C C C C C C
+            (
+                255,
+                &[(2, 4)],
+                [
+                    [159, 32, 32],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                    [64, 64, 64],
+                ],
+            ),
+        ];
+        for (pattern, same, expected) in cases {
+            let block = middle(&Hq3x, &case(pattern, same));
+            assert_eq!(block, expected, "case {} {:?}", pattern, same);
+        }
+    }
+
+    // Worked through by hand from blend_corner's three rules: a solid edge
+    // across the corner, a thin diagonal line, and a corner sticking in
+    #[test]
+    fn blend_filters_match_the_reference() {
+        let grey = |v: u8| [v, v, v];
+        let (k, solid, thin, poke) = (grey(0), grey(191), grey(128), grey(64));
+        let side = grey(32);
+        let cases = [
+            (
+                ["##.", "#..", "..."],
+                solid,
+                vec![solid, side, k, side, k, k, k, k, k],
+            ),
+            (
+                [".#.", "#..", "..."],
+                thin,
+                vec![thin, side, k, side, k, k, k, k, k],
+            ),
+            (
+                ["#..", "...", "..."],
+                poke,
+                vec![poke, k, k, k, k, k, k, k, k],
+            ),
+        ];
+        for (rows, corner, block) in cases {
+            let input = image(&rows);
+            assert_eq!(middle(&Blend2x, &input), [corner, k, k, k], "{:?}", rows);
+            assert_eq!(middle(&Blend3x, &input), block, "{:?}", rows);
+        }
+    }
+
+    #[test]
+    fn chains_apply_in_order() {
+        let chain = FilterChain::parse("scale2x, nearest2").unwrap();
+        assert_eq!(chain.describe(), "scale2x,nearest2");
+        assert_eq!(chain.scale(), 4);
+        let input = image(&SHAPES);
+        let expected = Nearest(2).apply(&Scale2x.apply(&input));
+        assert_eq!(chain.apply(&input), expected);
+        assert!(FilterChain::parse("none").unwrap().is_empty());
+        assert!(FilterChain::parse("hq5x").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use crate::audio::{AudioOutput, WavWriter};
+use crate::cpu_6502::Cpu6502;
+use crate::debugger::CPU_CYCLES_PER_FRAME;
+use crate::frame_buffer::{self, FrameBuffer, Overscan};
+use crate::image::Image;
+use crate::palette::Palette;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Records what the machine shows and plays, for bug reports. Pictures go
+// to a YUV4MPEG2 stream, which ffmpeg and most players read as it is:
+//
+//     YUV4MPEG2 W256 H240 F1789773:29781 Ip A1:1 C420jpeg
+//     FRAME
+//     Y plane, then Cb and Cr at half size each way
+//     FRAME
+//     ...
+//
+// The frame rate is the NTSC one exactly, a frame every 29781 CPU cycles,
+// and the sound goes to a WAV file that starts on the same cycle as the
+// first picture. Both are counted in CPU cycles, so they stay in sync.
+
+pub struct VideoRecorder {
+    out: BufWriter<File>,
+    palette: Palette,
+    overscan: Overscan,
+    // Handed to the bus at the first frame boundary, so the sound starts
+    // with the first picture
+    audio: Option<(WavWriter, u32)>,
+    started: bool,
+    last_frame: Option<u64>,
+    frames: u64,
+    error: Option<io::Error>,
+}
+
+impl VideoRecorder {
+    // Sound is recorded when audio gives a WAV file and its sample rate
+    pub fn create(
+        path: &Path,
+        palette: Palette,
+        overscan: Overscan,
+        audio: Option<(&Path, u32)>,
+    ) -> io::Result<Self> {
+        let audio = match audio {
+            Some((path, rate)) => Some((WavWriter::create(path, rate)?, rate)),
+            None => None,
+        };
+        let mut out = BufWriter::new(File::create(path)?);
+        let width = frame_buffer::WIDTH.saturating_sub(overscan.left + overscan.right);
+        let height = frame_buffer::HEIGHT.saturating_sub(overscan.top + overscan.bottom);
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F1789773:{} Ip A1:1 C420jpeg",
+            width, height, CPU_CYCLES_PER_FRAME
+        )?;
+        Ok(Self {
+            out,
+            palette,
+            overscan,
+            audio,
+            started: false,
+            last_frame: None,
+            frames: 0,
+            error: None,
+        })
+    }
+
+    // Pictures written so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // The first error writing hit, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    // Called before every instruction. Recording starts at the first frame
+    // boundary after this is first called, and from then on each boundary
+    // writes out the picture of the frame that just finished.
+    pub fn log(&mut self, cpu: &mut Cpu6502) {
+        let frame = cpu.clock_count / CPU_CYCLES_PER_FRAME;
+        if self.last_frame == Some(frame) {
+            return;
+        }
+        let first = self.last_frame.is_none();
+        self.last_frame = Some(frame);
+        if first {
+            return;
+        }
+
+        if self.started {
+            self.write_frame(&cpu.bus.screen);
+        } else {
+            self.started = true;
+            if let Some((wav, rate)) = self.audio.take() {
+                cpu.bus.audio = Some(AudioOutput::new(rate, Box::new(wav)));
+            }
+        }
+    }
+
+    pub fn write_frame(&mut self, screen: &FrameBuffer) {
+        if self.error.is_some() {
+            return;
+        }
+        let image = screen.screenshot(&self.palette, self.overscan);
+        let result = self
+            .out
+            .write_all(b"FRAME\n")
+            .and_then(|_| self.out.write_all(&yuv420(&image)));
+        match result {
+            Ok(()) => self.frames += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    // Stops recording, returning how many pictures were written. The frame
+    // in progress is written too, and the sound padded with silence to its
+    // end, so both cover the same frames.
+    pub fn finish(mut self, cpu: &mut Cpu6502) -> io::Result<u64> {
+        if self.started {
+            self.write_frame(&cpu.bus.screen);
+            let into_frame = cpu.clock_count % CPU_CYCLES_PER_FRAME;
+            if let Some(mut audio) = cpu.bus.audio.take() {
+                if into_frame != 0 {
+                    for _ in into_frame..CPU_CYCLES_PER_FRAME {
+                        audio.clock(0.0);
+                    }
+                }
+                audio.finish()?;
+            }
+        }
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()?;
+        Ok(self.frames)
+    }
+}
+
+// BT.601 studio range, chroma averaged over each 2x2 block (a half block on
+// odd edges)
+fn yuv420(image: &Image) -> Vec<u8> {
+    let (width, height) = (image.width, image.height);
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let mut y_plane = Vec::with_capacity(width * height);
+    let mut cb_plane = vec![0.0f32; chroma_width * chroma_height];
+    let mut cr_plane = vec![0.0f32; chroma_width * chroma_height];
+    let mut counts = vec![0.0f32; chroma_width * chroma_height];
+
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b] = image.pixel(x, y).map(|c| c as f32 / 255.0);
+            y_plane.push((16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8);
+            let i = (y / 2) * chroma_width + x / 2;
+            cb_plane[i] += 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+            cr_plane[i] += 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+            counts[i] += 1.0;
+        }
+    }
+
+    let mut out = y_plane;
+    for plane in [cb_plane, cr_plane] {
+        out.extend(
+            plane
+                .iter()
+                .zip(&counts)
+                .map(|(sum, count)| (sum / count).round() as u8),
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Debugger;
+    use std::fs;
+
+    #[test]
+    fn records_whole_frames_of_picture_and_sound() {
+        let dir = std::env::temp_dir().join(format!("nes-video-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (path, wav) = (dir.join("out.y4m"), dir.join("out.wav"));
+
+        // JMP $8000 forever
+        let mut cpu = Cpu6502::new();
+        for (addr, data) in [
+            (0x8000, 0x4C),
+            (0x8001, 0x00),
+            (0x8002, 0x80),
+            (0xFFFD, 0x80),
+        ] {
+            cpu.bus.write(&addr, &data);
+        }
+        cpu.reset();
+        let video =
+            VideoRecorder::create(&path, Palette::new(), Overscan::NONE, Some((&wav, 44_100)));
+        let mut debugger = Debugger::new();
+        debugger.set_video(Some(video.unwrap()));
+        debugger.resume();
+
+        // Recording starts at the end of frame 0 and stops part way into
+        // frame 3, which is finished off
+        debugger.update(&mut cpu, 3 * CPU_CYCLES_PER_FRAME + 1000);
+        let video = debugger.set_video(None).unwrap();
+        assert_eq!(video.frames(), 2);
+        assert_eq!(video.finish(&mut cpu).unwrap(), 3);
+        assert!(cpu.bus.audio.is_none());
+
+        let data = fs::read(&path).unwrap();
+        let header = b"YUV4MPEG2 W256 H240 F1789773:29781 Ip A1:1 C420jpeg\n";
+        assert_eq!(&data[..header.len()], header);
+        let frame = b"FRAME\n".len() + 256 * 240 * 3 / 2;
+        assert_eq!(data.len(), header.len() + 3 * frame);
+        assert_eq!(&data[header.len()..header.len() + 6], b"FRAME\n");
+
+        // The sound is padded out to the same three frames
+        let samples = (fs::read(&wav).unwrap().len() - 44) as f64 / 2.0;
+        let expected = 3.0 * CPU_CYCLES_PER_FRAME as f64 * 44_100.0 / 1_789_773.0;
+        assert!(
+            (samples - expected).abs() <= 1.0,
+            "{} {}",
+            samples,
+            expected
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn converts_to_studio_range_yuv() {
+        let mut image = Image::new(3, 3);
+        assert_eq!(yuv420(&image), [[16; 9].as_slice(), &[128; 8]].concat());
+        image.rgb.fill(255);
+        assert_eq!(yuv420(&image), [[235; 9].as_slice(), &[128; 8]].concat());
+
+        // Odd width: the last chroma sample covers a single column
+        let mut image = Image::new(3, 1);
+        image.set_pixel(0, 0, [255, 0, 0]);
+        image.set_pixel(1, 0, [255, 0, 0]);
+        image.set_pixel(2, 0, [0, 0, 255]);
+        assert_eq!(yuv420(&image), [81, 81, 41, 90, 240, 240, 110]);
+    }
+}
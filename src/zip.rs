@@ -0,0 +1,438 @@
+// Just enough zip to read and write the archives other emulators use for
+// their files (BizHawk movies are zips), plus the zlib stream PNG images
+// are wrapped in. Writing only stores, reading understands stored and
+// deflated entries.
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16, String> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or("zip file truncated".to_string())
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32, String> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or("zip file truncated".to_string())
+}
+
+// Every file in the archive with its name, in the order of the central
+// directory
+pub fn read_zip(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    // The end of central directory record is the last thing in the file,
+    // followed only by a comment of up to 64K
+    let end = (0..data.len().saturating_sub(21))
+        .rev()
+        .find(|i| data[*i..].starts_with(&[0x50, 0x4B, 0x05, 0x06]))
+        .ok_or("not a zip file")?;
+    let entries = u16_at(data, end + 10)? as usize;
+    let mut at = u32_at(data, end + 16)? as usize;
+
+    let mut files = Vec::new();
+    for _ in 0..entries {
+        if u32_at(data, at)? != 0x0201_4B50 {
+            return Err("zip central directory is corrupt".to_string());
+        }
+        let method = u16_at(data, at + 10)?;
+        let crc = u32_at(data, at + 16)?;
+        let packed = u32_at(data, at + 20)? as usize;
+        let size = u32_at(data, at + 24)? as usize;
+        let name_len = u16_at(data, at + 28)? as usize;
+        let extra_len = u16_at(data, at + 30)? as usize;
+        let comment_len = u16_at(data, at + 32)? as usize;
+        let offset = u32_at(data, at + 42)? as usize;
+        let name = data
+            .get(at + 46..at + 46 + name_len)
+            .ok_or("zip file truncated")?;
+        let name = String::from_utf8_lossy(name).to_string();
+        at += 46 + name_len + extra_len + comment_len;
+
+        // The local header repeats the name and may have its own extra
+        let start =
+            offset + 30 + u16_at(data, offset + 26)? as usize + u16_at(data, offset + 28)? as usize;
+        let stored = data
+            .get(start..start + packed)
+            .ok_or(format!("{}: truncated", name))?;
+        let contents = match method {
+            0 => stored.to_vec(),
+            8 => inflate(stored, size)?,
+            _ => {
+                return Err(format!(
+                    "{}: compression method {} not supported",
+                    name, method
+                ))
+            }
+        };
+        if contents.len() != size || crc32(&contents) != crc {
+            return Err(format!("{}: checksum mismatch", name));
+        }
+        files.push((name, contents));
+    }
+    Ok(files)
+}
+
+// Stores the files uncompressed, dated 1980-01-01
+pub fn write_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    let mut directory: Vec<u8> = Vec::new();
+
+    for (name, contents) in files {
+        let offset = out.len() as u32;
+        let crc = crc32(contents);
+        let size = contents.len() as u32;
+
+        // Fields shared by the local header and the directory entry:
+        // version needed, flags, method, time, date, crc, sizes, name length
+        let mut common: Vec<u8> = Vec::new();
+        for value in [20u16, 0, 0, 0, 0x0021] {
+            common.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [crc, size, size] {
+            common.extend_from_slice(&value.to_le_bytes());
+        }
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        out.extend_from_slice(&0x0403_4B50u32.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(contents);
+
+        directory.extend_from_slice(&0x0201_4B50u32.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&common);
+        // Extra, comment, disk, internal and external attributes
+        directory.extend_from_slice(&[0; 12]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = out.len() as u32;
+    out.extend_from_slice(&directory);
+    out.extend_from_slice(&0x0605_4B50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    out.extend_from_slice(&directory_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ZLIB
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// A zlib stream of stored deflate blocks, which are at most 64K each
+pub fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// INFLATE
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order the code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or("deflate data truncated")?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Stored blocks start on a byte boundary
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// Canonical Huffman code given as how many codes have each length and the
+// symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, input: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad deflate code".to_string())
+    }
+}
+
+pub fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let mut out: Vec<u8> = Vec::with_capacity(size);
+    let mut input = BitReader {
+        data,
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.align();
+                let len = data
+                    .get(input.pos..input.pos + 4)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+                    .ok_or("deflate data truncated")?;
+                let start = input.pos + 4;
+                let block = data
+                    .get(start..start + len)
+                    .ok_or("deflate data truncated")?;
+                out.extend_from_slice(block);
+                input.pos = start + len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (i, len) in lengths.iter_mut().enumerate() {
+                    *len = match i {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut input, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let literal_count = input.bits(5)? as usize + 257;
+                let distance_count = input.bits(5)? as usize + 1;
+                let code_count = input.bits(4)? as usize + 4;
+
+                let mut code_lengths = [0u8; 19];
+                for i in CODE_LENGTH_ORDER.iter().take(code_count) {
+                    code_lengths[*i] = input.bits(3)? as u8;
+                }
+                let codes = Huffman::new(&code_lengths);
+
+                let mut lengths: Vec<u8> = Vec::new();
+                while lengths.len() < literal_count + distance_count {
+                    let (value, repeat) = match codes.decode(&mut input)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => {
+                            let previous = *lengths.last().ok_or("bad deflate lengths")?;
+                            (previous, 3 + input.bits(2)?)
+                        }
+                        17 => (0, 3 + input.bits(3)?),
+                        _ => (0, 11 + input.bits(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+                }
+                if lengths.len() != literal_count + distance_count {
+                    return Err("bad deflate lengths".to_string());
+                }
+                let literals = Huffman::new(&lengths[..literal_count]);
+                let distances = Huffman::new(&lengths[literal_count..]);
+                inflate_block(&mut input, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("bad deflate block type".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn inflate_block(
+    input: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(input)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() {
+                    return Err("bad deflate length".to_string());
+                }
+                let len = LENGTH_BASE[i] as usize + input.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                let d = distances.decode(input)? as usize;
+                if d >= DISTANCE_BASE.len() {
+                    return Err("bad deflate distance".to_string());
+                }
+                let distance =
+                    DISTANCE_BASE[d] as usize + input.bits(DISTANCE_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err("deflate distance too far back".to_string());
+                }
+                // Copies can overlap what they write, so go a byte at a time
+                let start = out.len() - distance;
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inflates_fixed_blocks() {
+        // zlib with Z_FIXED
+        let packed = [
+            0x4B, 0xCB, 0xAC, 0x48, 0x4D, 0x51, 0x48, 0x43, 0x22, 0x3D, 0x4A, 0xD3, 0xD2, 0x72,
+            0x13, 0xF3, 0x14, 0x92, 0xF3, 0x53, 0x52, 0x8B, 0x15, 0xB9, 0xD2, 0x08, 0x29, 0x00,
+            0x00,
+        ];
+        let expected = "fixed fixed fixed Huffman codes!\n".repeat(2);
+        assert_eq!(
+            inflate(&packed, expected.len()).unwrap(),
+            expected.as_bytes()
+        );
+    }
+
+    #[test]
+    fn inflates_dynamic_and_stored_blocks() {
+        // zlib at level 9: a dynamic block, the empty stored block of a full
+        // flush, then another dynamic block
+        let packed = include_bytes!("../testdata/dynamic.deflate");
+        let mut expected = String::new();
+        let mut x: u32 = 1;
+        for _ in 0..40 {
+            x = (x.wrapping_mul(1103515245).wrapping_add(12345)) & 0x7FFF_FFFF;
+            let buttons: String = "RLDUTSBA"
+                .chars()
+                .enumerate()
+                .map(|(i, c)| if (x >> 16) & (0x80 >> i) != 0 { c } else { '.' })
+                .collect();
+            expected += &format!("|0|{}|........||\n", buttons);
+        }
+        assert_eq!(
+            inflate(packed, expected.len()).unwrap(),
+            expected.as_bytes()
+        );
+
+        assert!(inflate(&packed[..packed.len() / 2], expected.len()).is_err());
+        assert!(inflate(&[0x07], 0).is_err());
+    }
+
+    #[test]
+    fn reads_deflated_entries() {
+        // Written by Python's zipfile, both entries deflated
+        let files = read_zip(include_bytes!("../testdata/deflated.bk2")).unwrap();
+        let names: Vec<&str> = files.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["Header.txt", "Input Log.txt"]);
+        assert!(files[0]
+            .1
+            .starts_with(b"MovieVersion BizHawk v2.0.0\nAuthor someone\n"));
+        assert_eq!(files[1].1.len(), 1779);
+
+        // A stored archive of our own reads back too
+        let zip = write_zip(&[("a.txt", b"hello"), ("b.bin", &[0, 1, 2])]);
+        let files = read_zip(&zip).unwrap();
+        assert_eq!(files[0], ("a.txt".to_string(), b"hello".to_vec()));
+        assert_eq!(files[1], ("b.bin".to_string(), vec![0, 1, 2]));
+    }
+}